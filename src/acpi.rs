@@ -0,0 +1,322 @@
+/* ACPI, just enough of it to turn the machine off and restart it. The firmware leaves a few tables in memory: the
+   RSDP, found by looking for "RSD PTR " in the BIOS area, points to the RSDT (or the XSDT on newer machines), which
+   lists all the other tables. The one we want is the FADT, it has the power management ports and the reset
+   register.
+
+   Turning off is writing the S5 ("soft off") sleep type to the PM1 control registers. The value for S5 is only in
+   the DSDT, which is AML bytecode, so instead of running an AML interpreter we look for the `_S5_` package in it
+   and read the two numbers out of that, like most hobby kernels do.
+
+   Everything is read through the bootloader's mapping of physical memory (memory::phys_to_virt). */
+
+use crate::{memory, pit};
+use core::{fmt, ptr, slice, time::Duration};
+use x86_64::{instructions::port::Port, PhysAddr};
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const EBDA_POINTER: u64 = 0x40E; // the real mode segment of the extended BIOS data area is stored here
+const BIOS_AREA: (u64, u64) = (0xE0000, 0x100000);
+
+const HEADER_SIZE: usize = 36; // every table starts with the same header: signature, length, checksum...
+
+// offsets in the FADT
+const FADT_DSDT: usize = 40;
+const FADT_SMI_COMMAND: usize = 48;
+const FADT_ACPI_ENABLE: usize = 52;
+const FADT_PM1A_CONTROL: usize = 64;
+const FADT_PM1B_CONTROL: usize = 68;
+const FADT_FLAGS: usize = 112;
+const FADT_RESET_REGISTER: usize = 116;
+const FADT_RESET_VALUE: usize = 128;
+const FADT_X_DSDT: usize = 140;
+
+const FLAG_RESET_REGISTER: u32 = 1 << 10; // the reset register is there and works
+
+// PM1 control register bits
+const SCI_ENABLED: u16 = 1 << 0; // ACPI mode is on
+const SLEEP_TYPE_SHIFT: u16 = 10;
+const SLEEP_ENABLE: u16 = 1 << 13;
+
+// address spaces of a generic address structure
+const SPACE_MEMORY: u8 = 0;
+const SPACE_IO: u8 = 1;
+
+// AML opcodes that show up around `_S5_`
+const AML_NAME: u8 = 0x08;
+const AML_ROOT: u8 = b'\\';
+const AML_PACKAGE: u8 = 0x12;
+const AML_BYTE_PREFIX: u8 = 0x0A;
+const AML_ZERO: u8 = 0x00;
+const AML_ONE: u8 = 0x01;
+
+// the keyboard controller can pull the CPU's reset line, the way PCs restarted before ACPI
+const KEYBOARD_STATUS: u16 = 0x64;
+const KEYBOARD_COMMAND: u16 = 0x64;
+const KEYBOARD_INPUT_FULL: u8 = 1 << 1;
+const KEYBOARD_PULSE_RESET: u8 = 0xFE;
+
+/// How long to wait for the machine to actually go off or restart before giving up on a way of doing it.
+const GRACE: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    NoMemoryMapping, // memory::init hasn't run, so the tables can't be read
+    NoRsdp,
+    BadTable([u8; 4]),
+    NoTable([u8; 4]),
+    NoS5,
+    Ignored, // everything was written and the machine is still here
+}
+
+impl fmt::Display for AcpiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AcpiError::NoMemoryMapping => f.write_str("physical memory isn't mapped yet"),
+            AcpiError::NoRsdp => f.write_str("no ACPI tables found"),
+            AcpiError::BadTable(signature) => {
+                write!(f, "the {} table is broken", core::str::from_utf8(signature).unwrap_or("????"))
+            }
+            AcpiError::NoTable(signature) => {
+                write!(f, "there's no {} table", core::str::from_utf8(signature).unwrap_or("????"))
+            }
+            AcpiError::NoS5 => f.write_str("the DSDT doesn't say how to power off"),
+            AcpiError::Ignored => f.write_str("the machine ignored it"),
+        }
+    }
+}
+
+/// A register from a generic address structure, only the parts we use.
+#[derive(Debug, Clone, Copy)]
+struct Register {
+    space: u8,
+    address: u64,
+}
+
+/// What we need from the FADT.
+#[derive(Debug, Clone, Copy)]
+struct Fadt {
+    pm1a_control: u16,
+    pm1b_control: u16, // 0 if there's only one
+    smi_command: u16,
+    acpi_enable: u8,
+    dsdt: u64,
+    reset: Option<(Register, u8)>, // the register and what to write to it
+}
+
+/// `len` bytes of physical memory.
+unsafe fn physical(address: u64, len: usize) -> Result<&'static [u8], AcpiError> {
+    let virt = memory::phys_to_virt(PhysAddr::new_truncate(address)).ok_or(AcpiError::NoMemoryMapping)?;
+    Ok(slice::from_raw_parts(virt.as_ptr(), len))
+}
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    bytes.get(offset..offset + 2).map_or(0, |b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    bytes.get(offset..offset + 4).map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut value = [0u8; 8];
+    if let Some(b) = bytes.get(offset..offset + 8) {
+        value.copy_from_slice(b);
+    }
+    u64::from_le_bytes(value)
+}
+
+/// A whole table, after checking its length and checksum.
+fn table(address: u64) -> Result<&'static [u8], AcpiError> {
+    let header = unsafe { physical(address, HEADER_SIZE)? };
+    let mut signature = [0u8; 4];
+    signature.copy_from_slice(&header[..4]);
+    let len = read_u32(header, 4) as usize;
+    if len < HEADER_SIZE {
+        return Err(AcpiError::BadTable(signature));
+    }
+    let table = unsafe { physical(address, len)? };
+    if !checksum_ok(table) {
+        return Err(AcpiError::BadTable(signature));
+    }
+    Ok(table)
+}
+
+/// Looks for the RSDP on a 16 byte boundary in `[start, end)`.
+fn find_rsdp_in(start: u64, end: u64) -> Result<Option<&'static [u8]>, AcpiError> {
+    for address in (start..end).step_by(16) {
+        let candidate = unsafe { physical(address, 20)? };
+        if &candidate[..8] == RSDP_SIGNATURE && checksum_ok(candidate) {
+            return Ok(Some(unsafe { physical(address, 36)? }));
+        }
+    }
+    Ok(None)
+}
+
+/// The RSDP is in the first KiB of the EBDA or somewhere in the BIOS area.
+fn find_rsdp() -> Result<&'static [u8], AcpiError> {
+    let ebda = (read_u16(unsafe { physical(EBDA_POINTER, 2)? }, 0) as u64) << 4;
+    if ebda != 0 {
+        if let Some(rsdp) = find_rsdp_in(ebda, ebda + 1024)? {
+            return Ok(rsdp);
+        }
+    }
+    find_rsdp_in(BIOS_AREA.0, BIOS_AREA.1)?.ok_or(AcpiError::NoRsdp)
+}
+
+/// Finds a table by its signature in the RSDT or XSDT.
+fn find_table(signature: &[u8; 4]) -> Result<&'static [u8], AcpiError> {
+    let rsdp = find_rsdp()?;
+    let revision = rsdp[15];
+    let xsdt = read_u64(rsdp, 24);
+    // ACPI 2.0 and up has the XSDT with 64 bit addresses, the RSDT is still there but may be left out
+    let (root, entry_size) = if revision >= 2 && xsdt != 0 {
+        (table(xsdt)?, 8)
+    } else {
+        (table(read_u32(rsdp, 16) as u64)?, 4)
+    };
+    for offset in (HEADER_SIZE..root.len()).step_by(entry_size) {
+        let address = if entry_size == 8 { read_u64(root, offset) } else { read_u32(root, offset) as u64 };
+        let header = unsafe { physical(address, 4)? };
+        if header == signature {
+            return table(address);
+        }
+    }
+    Err(AcpiError::NoTable(*signature))
+}
+
+fn fadt() -> Result<Fadt, AcpiError> {
+    let fadt = find_table(b"FACP")?; // FACP is the FADT's signature, for historical reasons
+    let x_dsdt = read_u64(fadt, FADT_X_DSDT); // 0 if the table is too old to have it
+    let flags = read_u32(fadt, FADT_FLAGS);
+    let reset = if flags & FLAG_RESET_REGISTER != 0 && fadt.len() > FADT_RESET_VALUE {
+        let register = Register {
+            space: fadt[FADT_RESET_REGISTER],
+            address: read_u64(fadt, FADT_RESET_REGISTER + 4),
+        };
+        Some((register, fadt[FADT_RESET_VALUE]))
+    } else {
+        None
+    };
+    Ok(Fadt {
+        pm1a_control: read_u32(fadt, FADT_PM1A_CONTROL) as u16,
+        pm1b_control: read_u32(fadt, FADT_PM1B_CONTROL) as u16,
+        smi_command: read_u32(fadt, FADT_SMI_COMMAND) as u16,
+        acpi_enable: fadt.get(FADT_ACPI_ENABLE).copied().unwrap_or(0),
+        dsdt: if x_dsdt != 0 { x_dsdt } else { read_u32(fadt, FADT_DSDT) as u64 },
+        reset,
+    })
+}
+
+/// An integer in a package, as long as it's a small constant.
+fn aml_integer(aml: &[u8], index: &mut usize) -> Option<u16> {
+    let value = match *aml.get(*index)? {
+        AML_BYTE_PREFIX => {
+            *index += 1;
+            *aml.get(*index)? as u16
+        }
+        AML_ZERO => 0,
+        AML_ONE => 1,
+        _ => return None,
+    };
+    *index += 1;
+    Some(value)
+}
+
+/// SLP_TYPa and SLP_TYPb for S5, from `Name (_S5_, Package () { a, b, ... })` in the DSDT.
+fn s5_sleep_types(dsdt: &[u8]) -> Option<(u16, u16)> {
+    let aml = &dsdt[HEADER_SIZE.min(dsdt.len())..];
+    let start = aml.windows(4).position(|window| window == b"_S5_")?;
+    let named = match start {
+        0 => false,
+        1 => aml[0] == AML_NAME,
+        _ => aml[start - 1] == AML_NAME || (aml[start - 2] == AML_NAME && aml[start - 1] == AML_ROOT),
+    };
+    if !named || aml.get(start + 4) != Some(&AML_PACKAGE) {
+        return None;
+    }
+    let package_length = *aml.get(start + 5)?;
+    // the top two bits of the first byte say how many more bytes the length has, then comes the element count
+    let mut index = start + 5 + (package_length >> 6) as usize + 1 + 1;
+    let a = aml_integer(aml, &mut index)?;
+    let b = aml_integer(aml, &mut index)?;
+    Some((a, b))
+}
+
+/// Switches the chipset from legacy mode to ACPI mode, if the firmware didn't already.
+fn enable_acpi(fadt: &Fadt) {
+    let mut pm1a: Port<u16> = Port::new(fadt.pm1a_control);
+    if unsafe { pm1a.read() } & SCI_ENABLED != 0 || fadt.smi_command == 0 || fadt.acpi_enable == 0 {
+        return;
+    }
+    unsafe { Port::<u8>::new(fadt.smi_command).write(fadt.acpi_enable) };
+    for _ in 0..300 {
+        if unsafe { pm1a.read() } & SCI_ENABLED != 0 {
+            break;
+        }
+        pit::delay(Duration::from_millis(1));
+    }
+}
+
+/// Turns the machine off (ACPI S5). Only comes back if that didn't work, with the reason.
+pub fn power_off() -> AcpiError {
+    match try_power_off() {
+        Ok(()) => AcpiError::Ignored,
+        Err(error) => error,
+    }
+}
+
+fn try_power_off() -> Result<(), AcpiError> {
+    let fadt = fadt()?;
+    let (sleep_type_a, sleep_type_b) = s5_sleep_types(table(fadt.dsdt)?).ok_or(AcpiError::NoS5)?;
+    enable_acpi(&fadt);
+    unsafe {
+        Port::<u16>::new(fadt.pm1a_control).write((sleep_type_a << SLEEP_TYPE_SHIFT) | SLEEP_ENABLE);
+        if fadt.pm1b_control != 0 {
+            Port::<u16>::new(fadt.pm1b_control).write((sleep_type_b << SLEEP_TYPE_SHIFT) | SLEEP_ENABLE);
+        }
+    }
+    pit::delay(GRACE);
+    Ok(())
+}
+
+/// Restarts the machine with the FADT's reset register, and if there isn't one or it didn't work, through the
+/// keyboard controller. Only comes back if neither worked.
+pub fn reboot() {
+    if let Ok(Fadt { reset: Some((register, value)), .. }) = fadt() {
+        match register.space {
+            SPACE_IO => unsafe { Port::<u8>::new(register.address as u16).write(value) },
+            SPACE_MEMORY => {
+                if let Some(virt) = memory::phys_to_virt(PhysAddr::new_truncate(register.address)) {
+                    unsafe { ptr::write_volatile(virt.as_mut_ptr::<u8>(), value) };
+                }
+            }
+            _ => {} // PCI configuration space, not worth it when the keyboard controller is there
+        }
+        pit::delay(GRACE);
+    }
+
+    let mut status: Port<u8> = Port::new(KEYBOARD_STATUS);
+    for _ in 0..1000 {
+        if unsafe { status.read() } & KEYBOARD_INPUT_FULL == 0 {
+            break;
+        }
+        pit::delay(Duration::from_millis(1));
+    }
+    unsafe { Port::<u8>::new(KEYBOARD_COMMAND).write(KEYBOARD_PULSE_RESET) };
+    pit::delay(GRACE);
+}
+
+#[test_case]
+fn test_s5_sleep_types() {
+    let mut dsdt = alloc::vec![0u8; HEADER_SIZE];
+    // Name (\_S5_, Package (0x04) { 0x05, Zero, Zero, Zero }), after some other AML
+    dsdt.extend_from_slice(&[0x10, 0x20, AML_NAME, AML_ROOT, b'_', b'S', b'5', b'_', AML_PACKAGE, 0x08, 0x04]);
+    dsdt.extend_from_slice(&[AML_BYTE_PREFIX, 0x05, AML_ZERO, AML_ZERO, AML_ZERO]);
+    assert_eq!(s5_sleep_types(&dsdt), Some((5, 0)));
+    dsdt[HEADER_SIZE + 2] = 0x99; // not a Name any more
+    assert_eq!(s5_sleep_types(&dsdt), None);
+}
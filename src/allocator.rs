@@ -14,7 +14,7 @@ pub mod fixed_size_block;
 pub mod linked_list;
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+pub const HEAP_SIZE: usize = 1024 * 1024; // 1 MiB
 
 #[global_allocator]
 static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
@@ -46,6 +46,27 @@ pub fn init_heap(
     Ok(())
 }
 
+/// Heap usage in bytes, for /proc/heap.
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+}
+
+impl HeapStats {
+    pub fn free(&self) -> usize {
+        self.size - self.used
+    }
+}
+
+pub fn stats() -> HeapStats {
+    let allocator = ALLOCATOR.lock();
+    HeapStats {
+        size: allocator.heap_size(),
+        used: allocator.used(),
+    }
+}
+
 pub struct Dummy;
 
 unsafe impl GlobalAlloc for Dummy {
@@ -26,6 +26,8 @@ struct ListNode {
 pub struct FixedSizeBlockAllocator {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     fallback_allocator: linked_list_allocator::Heap,
+    heap_size: usize,
+    used: usize, // bytes handed out right now, rounded up to the block size
 }
 
 impl FixedSizeBlockAllocator {
@@ -35,9 +37,21 @@ impl FixedSizeBlockAllocator {
         FixedSizeBlockAllocator {
             list_heads: [EMPTY; BLOCK_SIZES.len()],
             fallback_allocator: linked_list_allocator::Heap::empty(),
+            heap_size: 0,
+            used: 0,
         }
     }
 
+    /// Size of the whole heap in bytes.
+    pub fn heap_size(&self) -> usize {
+        self.heap_size
+    }
+
+    /// How many bytes are allocated right now.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
     /// Initialize the allocator with the given heap bounds.
     ///
     /// This function is unsafe because the caller must guarantee that the given
@@ -45,6 +59,7 @@ impl FixedSizeBlockAllocator {
     /// called only once.
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         self.fallback_allocator.init(heap_start, heap_size);
+        self.heap_size = heap_size;
     }
 
     /// Allocates using the fallback allocator.
@@ -59,7 +74,7 @@ impl FixedSizeBlockAllocator {
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
-        match list_index(&layout) {
+        let ptr = match list_index(&layout) {
             Some(index) => {
                 match allocator.list_heads[index].take() {
                     Some(node) => {
@@ -77,11 +92,16 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                 }
             }
             None => allocator.fallback_alloc(layout),
+        };
+        if !ptr.is_null() {
+            allocator.used += list_index(&layout).map_or(layout.size(), |index| BLOCK_SIZES[index]);
         }
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
+        allocator.used -= list_index(&layout).map_or(layout.size(), |index| BLOCK_SIZES[index]);
         match list_index(&layout) {
             Some(index) => {
                 let new_node = ListNode {
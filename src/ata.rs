@@ -0,0 +1,335 @@
+/* A PIO driver for ATA (IDE) hard disks. It only polls, no DMA and no IRQ14/15, which is slow but simple.
+   In QEMU the boot image is the primary master, so a second `-drive ...,index=1` shows up as the primary slave.
+   The disks are called hda (primary master), hdb, hdc and hdd like on Linux. Only one `AtaDrive` per disk
+   can exist at a time, whoever has it owns the disk until it's dropped.
+   The two disks on a channel share its registers, so every command holds the channel's lock from selecting the
+   disk to the last word of data, otherwise a thread using hdd could select it in the middle of hdc's transfer. */
+
+use crate::block::{BlockDevice, BlockError, BLOCK_SIZE};
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
+
+const PRIMARY_IO: u16 = 0x1F0;
+const PRIMARY_CONTROL: u16 = 0x3F6;
+const SECONDARY_IO: u16 = 0x170;
+const SECONDARY_CONTROL: u16 = 0x376;
+
+// status register bits
+const STATUS_ERR: u8 = 1 << 0;
+const STATUS_DRQ: u8 = 1 << 3;
+const STATUS_DF: u8 = 1 << 5;
+const STATUS_BSY: u8 = 1 << 7;
+
+// commands
+const CMD_READ_SECTORS: u8 = 0x20;
+const CMD_WRITE_SECTORS: u8 = 0x30;
+const CMD_CACHE_FLUSH: u8 = 0xE7;
+const CMD_IDENTIFY: u8 = 0xEC;
+
+const CONTROL_NIEN: u8 = 1 << 1; // don't raise interrupts, we poll instead
+
+const POLL_LIMIT: usize = 1_000_000; // status reads before we give up on the drive
+
+const NAMES: [&str; 4] = ["hda", "hdb", "hdc", "hdd"];
+
+static IN_USE: Mutex<[bool; 4]> = Mutex::new([false; 4]); // indexed like NAMES
+static CHANNEL_LOCKS: [Mutex<()>; 2] = [Mutex::new(()), Mutex::new(())]; // primary, secondary
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Primary,
+    Secondary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    Master,
+    Slave,
+}
+
+/// The registers of one ATA channel.
+struct Registers {
+    data: Port<u16>,
+    error: PortReadOnly<u8>,
+    sector_count: Port<u8>,
+    lba_low: Port<u8>,
+    lba_mid: Port<u8>,
+    lba_high: Port<u8>,
+    drive_head: Port<u8>,
+    status: PortReadOnly<u8>,
+    command: PortWriteOnly<u8>,
+    alt_status: PortReadOnly<u8>,
+    control: PortWriteOnly<u8>,
+}
+
+impl Registers {
+    const fn new(channel: Channel) -> Self {
+        let (io, control) = match channel {
+            Channel::Primary => (PRIMARY_IO, PRIMARY_CONTROL),
+            Channel::Secondary => (SECONDARY_IO, SECONDARY_CONTROL),
+        };
+        Registers {
+            data: Port::new(io),
+            error: PortReadOnly::new(io + 1),
+            sector_count: Port::new(io + 2),
+            lba_low: Port::new(io + 3),
+            lba_mid: Port::new(io + 4),
+            lba_high: Port::new(io + 5),
+            drive_head: Port::new(io + 6),
+            status: PortReadOnly::new(io + 7),
+            command: PortWriteOnly::new(io + 7),
+            alt_status: PortReadOnly::new(control),
+            control: PortWriteOnly::new(control),
+        }
+    }
+
+    /// Waits ~400ns, the time a drive needs before its status is valid after being selected.
+    fn delay(&mut self) {
+        for _ in 0..4 {
+            unsafe {
+                self.alt_status.read();
+            }
+        }
+    }
+
+    fn wait_not_busy(&mut self) -> Result<u8, BlockError> {
+        for _ in 0..POLL_LIMIT {
+            let status = unsafe { self.status.read() };
+            if status & STATUS_BSY == 0 {
+                return Ok(status);
+            }
+        }
+        Err(BlockError::Timeout)
+    }
+
+    /// Waits until the drive is ready to move a sector of data.
+    fn wait_data(&mut self) -> Result<(), BlockError> {
+        for _ in 0..POLL_LIMIT {
+            let status = unsafe { self.status.read() };
+            if status & STATUS_BSY != 0 {
+                continue;
+            }
+            if status & (STATUS_ERR | STATUS_DF) != 0 {
+                return Err(BlockError::DeviceError);
+            }
+            if status & STATUS_DRQ != 0 {
+                return Ok(());
+            }
+        }
+        Err(BlockError::Timeout)
+    }
+}
+
+/// The lock that goes with a channel's registers.
+fn channel_lock(channel: Channel) -> &'static Mutex<()> {
+    match channel {
+        Channel::Primary => &CHANNEL_LOCKS[0],
+        Channel::Secondary => &CHANNEL_LOCKS[1],
+    }
+}
+
+/// Index into NAMES and IN_USE.
+fn disk_index(channel: Channel, position: Position) -> usize {
+    let channel = match channel {
+        Channel::Primary => 0,
+        Channel::Secondary => 2,
+    };
+    let position = match position {
+        Position::Master => 0,
+        Position::Slave => 1,
+    };
+    channel + position
+}
+
+pub struct AtaDrive {
+    registers: Registers,
+    channel: Channel,
+    position: Position,
+    sectors: u64,
+    model: [u8; 40],
+}
+
+impl AtaDrive {
+    /// Looks for an ATA disk at the given spot with the IDENTIFY command. None if there's none or it's in use.
+    pub fn identify(channel: Channel, position: Position) -> Option<AtaDrive> {
+        let index = disk_index(channel, position);
+        let mut in_use = IN_USE.lock();
+        if in_use[index] {
+            return None;
+        }
+        let _channel = channel_lock(channel).lock();
+        let mut registers = Registers::new(channel);
+        unsafe {
+            registers.control.write(CONTROL_NIEN);
+            registers.drive_head.write(match position {
+                Position::Master => 0xA0,
+                Position::Slave => 0xB0,
+            });
+        }
+        registers.delay();
+        unsafe {
+            registers.sector_count.write(0);
+            registers.lba_low.write(0);
+            registers.lba_mid.write(0);
+            registers.lba_high.write(0);
+            registers.command.write(CMD_IDENTIFY);
+            if registers.status.read() == 0 {
+                return None; // nothing attached
+            }
+        }
+        registers.wait_not_busy().ok()?;
+        let is_ata = unsafe { registers.lba_mid.read() == 0 && registers.lba_high.read() == 0 };
+        if !is_ata {
+            return None; // ATAPI (cd drive) or SATA, not for us
+        }
+        registers.wait_data().ok()?;
+
+        let mut identity = [0u16; 256];
+        for word in identity.iter_mut() {
+            *word = unsafe { registers.data.read() };
+        }
+        let sectors = (identity[60] as u64) | ((identity[61] as u64) << 16); // LBA28 sector count
+        if sectors == 0 {
+            return None;
+        }
+
+        // the model name is stored as big endian words, padded with spaces
+        let mut model = [b' '; 40];
+        for (i, word) in identity[27..47].iter().enumerate() {
+            model[i * 2] = (word >> 8) as u8;
+            model[i * 2 + 1] = (word & 0xFF) as u8;
+        }
+
+        in_use[index] = true;
+        Some(AtaDrive {
+            registers,
+            channel,
+            position,
+            sectors,
+            model,
+        })
+    }
+
+    pub fn model(&self) -> &str {
+        core::str::from_utf8(&self.model).unwrap_or("<unknown>").trim()
+    }
+
+    /// `hdb` and friends.
+    pub fn name(&self) -> &'static str {
+        NAMES[disk_index(self.channel, self.position)]
+    }
+
+    /// Selects the drive, with the top bits of `lba` that go in the same register.
+    fn select(&mut self, lba: u64) -> Result<(), BlockError> {
+        let drive_bits: u8 = match self.position {
+            Position::Master => 0xE0,
+            Position::Slave => 0xF0,
+        };
+        self.registers.wait_not_busy()?;
+        unsafe {
+            self.registers.drive_head.write(drive_bits | ((lba >> 24) & 0x0F) as u8);
+        }
+        self.registers.delay();
+        Ok(())
+    }
+
+    /// Selects the drive and sets up the registers for a one sector transfer at `lba`.
+    fn setup_transfer(&mut self, lba: u64, command: u8) -> Result<(), BlockError> {
+        if lba >= self.sectors {
+            return Err(BlockError::OutOfRange);
+        }
+        self.select(lba)?;
+        unsafe {
+            self.registers.sector_count.write(1);
+            self.registers.lba_low.write(lba as u8);
+            self.registers.lba_mid.write((lba >> 8) as u8);
+            self.registers.lba_high.write((lba >> 16) as u8);
+            self.registers.command.write(command);
+        }
+        Ok(())
+    }
+
+    /// The error register, for when something went wrong. It's the channel's, so it's only this disk's if
+    /// nothing else used the channel since.
+    pub fn last_error(&mut self) -> u8 {
+        let _channel = channel_lock(self.channel).lock();
+        unsafe { self.registers.error.read() }
+    }
+}
+
+impl Drop for AtaDrive {
+    fn drop(&mut self) {
+        IN_USE.lock()[disk_index(self.channel, self.position)] = false;
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_block(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), BlockError> {
+        let _channel = channel_lock(self.channel).lock();
+        self.setup_transfer(block, CMD_READ_SECTORS)?;
+        self.registers.wait_data()?;
+        for chunk in buf.chunks_exact_mut(2) {
+            let word = unsafe { self.registers.data.read() };
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), BlockError> {
+        let _channel = channel_lock(self.channel).lock();
+        self.setup_transfer(block, CMD_WRITE_SECTORS)?;
+        self.registers.wait_data()?;
+        for chunk in buf.chunks_exact(2) {
+            unsafe {
+                self.registers.data.write(u16::from_le_bytes([chunk[0], chunk[1]]));
+            }
+        }
+        let status = self.registers.wait_not_busy()?;
+        if status & (STATUS_ERR | STATUS_DF) != 0 {
+            return Err(BlockError::DeviceError);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        let _channel = channel_lock(self.channel).lock();
+        self.select(0)?; // the command goes to whichever disk was selected last
+        unsafe {
+            self.registers.command.write(CMD_CACHE_FLUSH);
+        }
+        let status = self.registers.wait_not_busy()?;
+        if status & (STATUS_ERR | STATUS_DF) != 0 {
+            return Err(BlockError::DeviceError);
+        }
+        Ok(())
+    }
+}
+
+/// Opens a data disk by name (`hdb`, `hdc` or `hdd`), None if it isn't there or someone else has it.
+pub fn open(name: &str) -> Option<AtaDrive> {
+    let (channel, position) = match name {
+        "hdb" => (Channel::Primary, Position::Slave),
+        "hdc" => (Channel::Secondary, Position::Master),
+        "hdd" => (Channel::Secondary, Position::Slave),
+        _ => return None, // hda is the boot disk
+    };
+    AtaDrive::identify(channel, position)
+}
+
+/// Every ATA disk that files can live on and nobody has opened yet. That's all of them except the primary master, which is the one we booted from.
+pub fn data_disks() -> Vec<AtaDrive> {
+    let candidates = [
+        (Channel::Primary, Position::Slave),
+        (Channel::Secondary, Position::Master),
+        (Channel::Secondary, Position::Slave),
+    ];
+    candidates
+        .iter()
+        .filter_map(|&(channel, position)| AtaDrive::identify(channel, position))
+        .collect()
+}
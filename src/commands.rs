@@ -1,40 +1,631 @@
-// #[no_std]
-// extern crate volatile;
-
-// use crate::println;
-// use crate::print;
-
-// pub fn main() {
-//     // Initialize the serial port
-//     let mut serial_port = unsafe { volatile::Volatile::new(0x3F8) };
-
-//     // Print the command prompt
-//     serial_port.write(b"> ");
-
-//     // Read input from the serial port
-//     let mut command = String::new();
-//     while true {
-//         let byte = unsafe { serial_port.read() };
-//         if byte == b'\r' {
-//             // Run the command
-//             let output = Command::new("sh")
-//                 .arg("-c")
-//                 .arg(command.trim())
-//                 .output()
-//                 .unwrap_or_else(|e| {
-//                     println!("Failed to execute command: {}", e);
-//                     return;
-//                 });
-
-//             // Print the output to the serial port
-//             serial_port.write(&output.stdout);
-//             serial_port.write(&output.stderr);
-
-//             // Clear the command and print the prompt again
-//             command.clear();
-//             serial_port.write(b"> ");
-//         } else {
-//             command.push(byte as char);
-//         }
-//     }
-// }
+/* The command table for the shell. Every command is a `Command` entry (name, aliases, usage and a handler),
+   the shell looks commands up here instead of a giant if/else chain, and /syshelp is generated from it.
+   Kernel modules can add their own commands with `commands::register` during init.
+
+   Commands print their output with `outln!` into `shell.out`, which is the shell's terminal unless the line was
+   redirected (`/lf > files.txt`, `>>` appends) or piped into another command (`/sw notes.txt | /grep todo`). Errors
+   still go straight to the terminal with `fail!`, so they don't end up in the file. There's a shell on the screen and
+   keyboard and another one on the serial port, each with its own `Terminal`. */
+
+use crate::{editor::Editor, print, rtc, serial_print, vfs::{self, VFS}, vga_buffer::print_error1};
+use alloc::{collections::BTreeMap, string::{String, ToString}, vec::Vec};
+use core::{
+    fmt::{self, Write},
+    mem,
+};
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+/// Prints an error from a command and marks the command as failed, scripts see that in `$?`.
+macro_rules! fail {
+    ($shell:expr, $($arg:tt)*) => {{
+        let _ = core::fmt::Write::write_fmt(&mut $shell.terminal, format_args!("{}\n", format_args!($($arg)*)));
+        $shell.status = 1;
+    }};
+}
+
+/// Like `println!`, but into the command's output (see `Output`).
+macro_rules! outln {
+    ($shell:expr) => {
+        outln!($shell, "")
+    };
+    ($shell:expr, $($arg:tt)*) => {{
+        let _ = core::fmt::Write::write_fmt(&mut $shell.out, format_args!("{}\n", format_args!($($arg)*)));
+    }};
+}
+
+pub mod builtin;
+pub mod env;
+pub mod fs;
+pub mod script;
+pub mod text;
+
+/// A command handler, gets the shell it runs in and the parsed arguments (without the command name).
+pub type CommandFn = fn(&mut Shell, &[&str]);
+
+/// Where a command shows up in /syshelp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandGroup {
+    General,
+    Experimental,
+    Hidden, // easter eggs and debug stuff, never listed
+}
+
+#[derive(Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub group: CommandGroup,
+    pub handler: CommandFn,
+}
+
+impl Command {
+    pub fn matches(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| *alias == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterError {
+    NameTaken(&'static str),
+}
+
+lazy_static! { // the table itself, filled with the builtins the first time it's used (needs the heap!)
+    static ref COMMANDS: Mutex<Vec<Command>> = {
+        let mut commands = builtin::commands();
+        commands.extend(env::commands());
+        commands.extend(fs::commands());
+        commands.extend(script::commands());
+        commands.extend(text::commands());
+        Mutex::new(commands)
+    };
+}
+
+/// Adds a command to the table.
+///
+/// Fails if the name or one of the aliases is already used by another command.
+/// Must be called after the heap is initialized.
+pub fn register(command: Command) -> Result<(), RegisterError> {
+    let mut commands = COMMANDS.lock();
+    let names = core::iter::once(&command.name).chain(command.aliases.iter());
+    for name in names {
+        if commands.iter().any(|existing| existing.matches(name)) {
+            return Err(RegisterError::NameTaken(name));
+        }
+    }
+    commands.push(command);
+    Ok(())
+}
+
+/// Looks up a command by its name or one of its aliases.
+pub fn find(name: &str) -> Option<Command> {
+    COMMANDS.lock().iter().find(|command| command.matches(name)).copied()
+}
+
+/// Prints the usage line of a command, for handlers that got the wrong arguments. Counts as a failure.
+pub fn print_usage(shell: &mut Shell, name: &str) {
+    shell.status = 1;
+    if let Some(command) = find(name) {
+        let _ = writeln!(shell.terminal, "Usage: {}", command.usage);
+    }
+}
+
+/// Returns a copy of the whole table, in registration order.
+pub fn all() -> Vec<Command> {
+    COMMANDS.lock().clone()
+}
+
+/// What Tab can turn the word in front of the cursor into.
+pub struct Completion {
+    pub start: usize, // where the word starts, counted in chars from the start of the line
+    pub candidates: Vec<String>,
+}
+
+/// Names and aliases of the listed commands starting with `prefix`, sorted.
+pub fn complete_command(prefix: &str) -> Vec<String> {
+    let commands = COMMANDS.lock();
+    let mut names: Vec<String> = commands
+        .iter()
+        .filter(|command| command.group != CommandGroup::Hidden)
+        .flat_map(|command| core::iter::once(&command.name).chain(command.aliases.iter()))
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| String::from(*name))
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// A piece of a command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Word(String),
+    Pipe,   // |
+    Write,  // >
+    Append, // >>
+}
+
+/// Splits a command line into words and the `|`, `>` and `>>` between them.
+///
+/// Words are separated by whitespace, double quotes group words together
+/// (`/tch a.txt "two  spaces"`), and a backslash escapes the next character,
+/// quoted or escaped `|` and `>` are just part of a word. Single quotes group words too,
+/// and nothing in them is special, not even a backslash.
+pub fn lex(line: &str) -> Vec<Token> {
+    lex_with(line, None)
+}
+
+/// Like `lex`, and with a shell its variables are expanded on the way (see `Shell::expand`),
+/// except for a `$` that's escaped or in single quotes: `\$HOME` and `'$HOME'` stay as they are.
+fn lex_with(line: &str, shell: Option<&Shell>) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut in_quotes = false;
+    let mut in_single_quotes = false;
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\'' if !in_quotes => {
+                in_single_quotes = !in_single_quotes;
+                in_arg = true;
+            }
+            c if in_single_quotes => current.push(c),
+            '$' => {
+                match shell.and_then(|shell| shell.variable(&line[index + 1..])) {
+                    Some((value, len)) => {
+                        current.push_str(&value);
+                        for _ in 0..len {
+                            chars.next(); // the name is ASCII, so its length in bytes is in chars as well
+                        }
+                    }
+                    None => current.push('$'),
+                }
+                in_arg = true;
+            }
+            '\\' => {
+                if let Some((_, escaped)) = chars.next() {
+                    current.push(escaped);
+                }
+                in_arg = true;
+            }
+            '"' => {
+                in_quotes = !in_quotes;
+                in_arg = true;
+            }
+            c if (c.is_whitespace() || c == '|' || c == '>') && !in_quotes => {
+                if in_arg {
+                    tokens.push(Token::Word(mem::take(&mut current)));
+                    in_arg = false;
+                }
+                match c {
+                    '|' => tokens.push(Token::Pipe),
+                    '>' if matches!(chars.peek(), Some((_, '>'))) => {
+                        chars.next();
+                        tokens.push(Token::Append);
+                    }
+                    '>' => tokens.push(Token::Write),
+                    _ => {}
+                }
+            }
+            c => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        tokens.push(Token::Word(current));
+    }
+    tokens
+}
+
+/// Splits a command line into arguments, `|` and `>` are left out (see `lex`).
+pub fn tokenize(line: &str) -> Vec<String> {
+    lex(line)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Word(word) => Some(word),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether `name` can be an environment variable: letters, digits and `_`, not starting with a digit.
+pub fn is_var_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// What the prompt looks like until PROMPT is changed: the current directory and a `>`.
+pub const DEFAULT_PROMPT: &str = "%w> ";
+
+/// Exit status of a command that doesn't exist, same as in Unix shells.
+pub const STATUS_UNKNOWN_COMMAND: u8 = 127;
+/// Exit status of a line that couldn't be parsed, like `/lf >` with no file.
+pub const STATUS_SYNTAX_ERROR: u8 = 2;
+/// Exit status of a program stopped with Ctrl+C, 128 + SIGINT like in Unix shells.
+pub const STATUS_INTERRUPTED: u8 = 130;
+
+/// What a shell is talking to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminal {
+    Vga,    // the screen, with keys from the keyboard
+    Serial, // COM1, for `qemu -serial stdio` and scripts driving the OS from outside
+}
+
+impl fmt::Write for Terminal {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self {
+            Terminal::Vga => print!("{}", s),
+            Terminal::Serial => {
+                // the other end is a raw terminal, it needs \r\n to get back to the start of the line
+                for (index, line) in s.split('\n').enumerate() {
+                    if index > 0 {
+                        serial_print!("\r\n");
+                    }
+                    serial_print!("{}", line);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where a command's output goes.
+pub enum Output {
+    Terminal(Terminal),
+    Buffer(String), // redirected to a file or piped into the next command, collected here until the command is done
+}
+
+impl fmt::Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self {
+            Output::Terminal(terminal) => terminal.write_str(s)?,
+            Output::Buffer(buffer) => buffer.push_str(s),
+        }
+        Ok(())
+    }
+}
+
+/// One command of a pipeline, `args[0]` is the command name.
+struct Stage {
+    args: Vec<String>,
+    redirect: Option<(String, bool)>, // file and whether to append
+}
+
+/// Cuts a lexed line into the commands between the pipes.
+fn parse_pipeline(tokens: Vec<Token>) -> Result<Vec<Stage>, &'static str> {
+    let mut stages = Vec::new();
+    let mut tokens = tokens.into_iter();
+    let mut stage = Stage { args: Vec::new(), redirect: None };
+    loop {
+        let token = tokens.next();
+        match token {
+            Some(Token::Word(word)) if stage.redirect.is_none() => stage.args.push(word),
+            Some(Token::Word(_)) => return Err("only one file can come after '>'"),
+            Some(Token::Write) | Some(Token::Append) => match tokens.next() {
+                Some(Token::Word(file)) if stage.redirect.is_none() => {
+                    stage.redirect = Some((file, token == Some(Token::Append)));
+                }
+                _ => return Err("'>' needs one file after it"),
+            },
+            Some(Token::Pipe) | None => {
+                if stage.args.is_empty() {
+                    return Err("missing command around '|' or '>'");
+                }
+                stages.push(mem::replace(&mut stage, Stage { args: Vec::new(), redirect: None }));
+                if token.is_none() {
+                    return Ok(stages);
+                }
+            }
+        }
+    }
+}
+
+/// The state of one shell session, handed to every command handler.
+pub struct Shell {
+    pub echo_text: String,             // last thing /echo printed, for /refr echo
+    pub cwd: String,                   // current directory as an absolute path, relative paths start here
+    pub editor: Option<Editor>,        // the full-screen editor, when it's open it gets the keys instead of the line editor
+    pub terminal: Terminal,            // where errors and the prompt go
+    pub status: u8,                    // exit status of the last command, 0 means it worked
+    pub script_depth: usize,           // how many /run scripts are running inside each other right now
+    pub out: Output,                   // where `outln!` goes for the command that's running
+    pub input: Option<String>,         // the text piped into the command that's running, if it's on the right of a '|'
+    pub env: BTreeMap<String, String>, // environment variables, `$NAME` in a command line
+    pub args: Vec<String>,             // $0, $1... while a script runs, empty at the prompt
+    pub in_background: bool,           // started by /bg, it has no keys to give the full-screen editor
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Shell::new()
+    }
+}
+
+impl Shell {
+    /// The shell on the screen and keyboard.
+    pub fn new() -> Self {
+        Shell::with_terminal(Terminal::Vga)
+    }
+
+    pub fn with_terminal(terminal: Terminal) -> Self {
+        Shell {
+            echo_text: String::new(),
+            cwd: String::from(vfs::ROOT_PATH),
+            editor: None,
+            terminal,
+            status: 0,
+            script_depth: 0,
+            out: Output::Terminal(terminal),
+            input: None,
+            env: [("PROMPT", DEFAULT_PROMPT), ("USER", "root")]
+                .iter()
+                .map(|(name, value)| (String::from(*name), String::from(*value)))
+                .collect(),
+            args: Vec::new(),
+            in_background: false,
+        }
+    }
+
+    /// An environment variable, `None` if it isn't set.
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.env.get(name).map(String::as_str)
+    }
+
+    /// Handles `NAME=value`, an empty value removes the variable. Returns false if it isn't an assignment.
+    pub fn assign(&mut self, assignment: &str) -> bool {
+        match assignment.split_once('=') {
+            Some((name, value)) if is_var_name(name.trim()) => {
+                let name = String::from(name.trim());
+                if value.is_empty() {
+                    self.env.remove(&name);
+                } else {
+                    self.env.insert(name, String::from(value));
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Replaces `$NAME`, `$1`, `$?` and `$$` in some text.
+    ///
+    /// A `$` that isn't followed by a name stays as it is, so `$/docs` is still a path.
+    pub fn expand(&self, text: &str) -> String {
+        let mut expanded = String::new();
+        let mut rest = text;
+        while let Some(index) = rest.find('$') {
+            expanded.push_str(&rest[..index]);
+            let after = &rest[index + 1..];
+            rest = match self.variable(after) {
+                Some((value, len)) => {
+                    expanded.push_str(&value);
+                    &after[len..]
+                }
+                None => {
+                    expanded.push('$'); // not a variable, `$/` paths for example
+                    after
+                }
+            };
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+
+    /// The value of the variable at the start of `after`, the text behind a `$`, and how long its name is.
+    /// None if there's no name there.
+    fn variable(&self, after: &str) -> Option<(String, usize)> {
+        let name_len = match after.chars().next() {
+            Some('$') => return Some((String::from("$"), 1)),
+            Some('?') => return Some((self.status.to_string(), 1)),
+            Some(c) if c.is_ascii_digit() => 1, // $12 is $1 followed by a 2, like %12 on DOS
+            _ => after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len()),
+        };
+        if name_len == 0 {
+            return None;
+        }
+        let name = &after[..name_len];
+        let value = match name.parse::<usize>() {
+            Ok(index) => self.args.get(index).map(String::as_str),
+            Err(_) => self.var(name),
+        };
+        Some((String::from(value.unwrap_or("")), name_len))
+    }
+
+    /// The prompt, made from PROMPT: `%w` is the current directory, `%u` the user,
+    /// `%t` the time, `%d` the date and `%%` a plain `%`.
+    pub fn prompt(&self) -> String {
+        let format = self.var("PROMPT").unwrap_or("");
+        let mut prompt = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                prompt.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('w') => prompt.push_str(&self.cwd),
+                Some('u') => prompt.push_str(self.var("USER").unwrap_or("")),
+                Some('t') => {
+                    let now = rtc::now();
+                    let _ = write!(prompt, "{:02}:{:02}:{:02}", now.hour, now.minute, now.second);
+                }
+                Some('d') => {
+                    let now = rtc::now();
+                    let _ = write!(prompt, "{:04}-{:02}-{:02}", now.year, now.month, now.day);
+                }
+                Some('%') => prompt.push('%'),
+                Some(other) => {
+                    prompt.push('%');
+                    prompt.push(other);
+                }
+                None => prompt.push('%'),
+            }
+        }
+        prompt
+    }
+
+    /// Moves `cwd` up to the closest directory that's still there. Another shell can remove or rename the one this
+    /// shell is in, or unmount the disk it's on, so this runs before every command line.
+    fn leave_missing_cwd(&mut self) {
+        let old = self.cwd.clone();
+        let mut vfs = VFS.lock();
+        while self.cwd != vfs::ROOT_PATH && !vfs.stat(&self.cwd).map(|stat| stat.is_dir()).unwrap_or(false) {
+            self.cwd = vfs::normalize(&self.cwd, "..");
+        }
+        drop(vfs);
+        if self.cwd != old {
+            let _ = writeln!(self.terminal, "'{}' is gone, now in '{}'", old, self.cwd);
+        }
+    }
+
+    /// Turns a path typed in this shell into an absolute one.
+    pub fn path(&self, path: &str) -> String {
+        vfs::normalize(&self.cwd, path)
+    }
+
+    /// Works out the completions for the end of `line` (everything in front of the cursor).
+    ///
+    /// The first word completes to command names, anything after it to paths.
+    pub fn complete(&self, line: &str) -> Completion {
+        let word_start = line.rfind(char::is_whitespace).map(|index| index + 1).unwrap_or(0);
+        let word = &line[word_start..];
+        let candidates = if line[..word_start].trim().is_empty() {
+            complete_command(word)
+        } else {
+            vfs::complete(&self.cwd, word)
+        };
+        Completion {
+            start: line[..word_start].chars().count(),
+            candidates,
+        }
+    }
+
+    /// Parses a command line and runs it, with its pipes and redirections.
+    /// Variables are expanded while it's split into words, see `lex_with`.
+    ///
+    /// Afterwards `status` says how it went (the last command's status for a pipeline),
+    /// handlers that fail set it (see `fail!`).
+    pub fn execute(&mut self, line: &str) {
+        self.leave_missing_cwd();
+        let tokens = lex_with(line, Some(self));
+        if tokens.is_empty() {
+            return; // empty line, nothing to do
+        }
+        let stages = match parse_pipeline(tokens) {
+            Ok(stages) => stages,
+            Err(error) => {
+                let _ = writeln!(self.terminal, "Syntax error: {}", error);
+                self.status = STATUS_SYNTAX_ERROR;
+                return;
+            }
+        };
+
+        let outer_input = self.input.take(); // a script in a pipe runs lines through here too
+        let count = stages.len();
+        let mut piped = None;
+        for (index, stage) in stages.iter().enumerate() {
+            self.input = piped.take();
+            let last = index + 1 == count;
+            if last && stage.redirect.is_none() {
+                self.run(&stage.args); // goes wherever our own output goes
+                break;
+            }
+            let outer = mem::replace(&mut self.out, Output::Buffer(String::new()));
+            self.run(&stage.args);
+            let text = match mem::replace(&mut self.out, outer) {
+                Output::Buffer(text) => text,
+                Output::Terminal(_) => String::new(),
+            };
+            piped = match &stage.redirect {
+                Some((file, append)) => {
+                    self.redirect(file, *append, &text);
+                    Some(String::new()) // like on unix, the next command gets nothing
+                }
+                None => Some(text),
+            };
+        }
+        self.input = outer_input;
+    }
+
+    /// Runs a single command with the input and output that are set up already.
+    fn run(&mut self, args: &[String]) {
+        let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+        let (name, args) = match args.split_first() {
+            Some((name, args)) => (*name, args),
+            None => return,
+        };
+
+        self.status = 0;
+        match find(name) {
+            Some(command) => (command.handler)(self, args),
+            None => {
+                let _ = writeln!(self.terminal, "Unknown Command: '{}'", name);
+                if self.terminal == Terminal::Vga {
+                    print_error1();
+                }
+                self.status = STATUS_UNKNOWN_COMMAND;
+            }
+        }
+    }
+
+    /// Puts the output of a command into a file for `>` and `>>`.
+    fn redirect(&mut self, file: &str, append: bool, text: &str) {
+        let path = self.path(file);
+        let result = if append {
+            VFS.lock().append(&path, text.as_bytes())
+        } else {
+            VFS.lock().write(&path, text.as_bytes())
+        };
+        if let Err(error) = result {
+            fail!(self, "Can't write '{}': {}", file, error);
+        }
+    }
+}
+
+#[test_case]
+fn test_lex() {
+    let word = |text: &str| Token::Word(String::from(text));
+    assert_eq!(
+        lex(r#"/tch "two  words" a\|b | /grep x >> out"#),
+        alloc::vec![word("/tch"), word("two  words"), word("a|b"), Token::Pipe, word("/grep"), word("x"), Token::Append, word("out")]
+    );
+    assert_eq!(lex("/lf>a"), alloc::vec![word("/lf"), Token::Write, word("a")]);
+}
+
+#[test_case]
+fn test_expand() {
+    let mut shell = Shell::new();
+    shell.assign("NAME=stb");
+    shell.args = alloc::vec![String::from("script.stb"), String::from("first")];
+    shell.status = 3;
+    assert_eq!(shell.expand("$NAME-$1 $? $$ $"), "stb-first 3 $ $");
+    assert_eq!(shell.expand("$MISSING."), ".");
+
+    shell.assign("HOME=$/home");
+    let word = |text: &str| Token::Word(String::from(text));
+    assert_eq!(
+        lex_with(r#"/echo $HOME \$HOME '$HOME' "$HOME"|$?"#, Some(&shell)),
+        alloc::vec![word("/echo"), word("$/home"), word("$HOME"), word("$HOME"), word("$/home"), Token::Pipe, word("3")]
+    );
+    assert_eq!(lex_with(r#"/echo '\ "|' it\'s"#, Some(&shell)), alloc::vec![word("/echo"), word(r#"\ "|"#), word("it's")]);
+}
+
+#[test_case]
+fn test_missing_cwd() {
+    VFS.lock().mkdir("$/gone").unwrap();
+    VFS.lock().mkdir("$/gone/deeper").unwrap();
+    let mut shell = Shell::with_terminal(Terminal::Serial);
+    shell.cwd = String::from("$/gone/deeper");
+    VFS.lock().remove_all("$/gone").unwrap(); // like /rmdir -r in the other shell
+    shell.execute("");
+    assert_eq!(shell.cwd, "$/");
+}
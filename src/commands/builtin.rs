@@ -0,0 +1,379 @@
+/* The commands that come with the OS, these used to live in one big if/else in keyboard.rs */
+
+use super::{all, find, lex, print_usage, Command, CommandGroup, Output, Shell, Terminal, Token, STATUS_INTERRUPTED};
+use crate::{
+    acpi, exit_qemu,
+    getcpu::get_cpu_name,
+    pit, println,
+    process::{self, ExitStatus, Program},
+    rtc, thread,
+    vfs::VFS,
+    vga_buffer::{ascii, print_shutdown, print_smiley_face},
+    hlt_loop, QemuExitCode,
+};
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt::Write;
+
+pub const OSVER: &str = "0.9.8.5";
+
+pub fn commands() -> Vec<Command> {
+    vec![
+        Command {
+            name: "/syshelp",
+            aliases: &["/help"],
+            usage: "/syshelp",
+            help: "Display's This Information",
+            group: CommandGroup::General,
+            handler: syshelp,
+        },
+        Command {
+            name: "/cls",
+            aliases: &["/clear"],
+            usage: "/cls",
+            help: "Clears the Screen",
+            group: CommandGroup::General,
+            handler: cls,
+        },
+        Command {
+            name: "/sysinf",
+            aliases: &[],
+            usage: "/sysinf",
+            help: "Shows System Information",
+            group: CommandGroup::General,
+            handler: sysinf,
+        },
+        Command {
+            name: "/shutdown",
+            aliases: &[],
+            usage: "/shutdown",
+            help: "Turns the PC off",
+            group: CommandGroup::General,
+            handler: shutdown,
+        },
+        Command {
+            name: "/reboot",
+            aliases: &["/restart"],
+            usage: "/reboot",
+            help: "Restarts the PC",
+            group: CommandGroup::General,
+            handler: reboot,
+        },
+        Command {
+            name: "/date",
+            aliases: &[],
+            usage: "/date",
+            help: "Shows today's date",
+            group: CommandGroup::General,
+            handler: date,
+        },
+        Command {
+            name: "/time",
+            aliases: &[],
+            usage: "/time",
+            help: "Shows the time",
+            group: CommandGroup::General,
+            handler: time,
+        },
+        Command {
+            name: "/uptime",
+            aliases: &[],
+            usage: "/uptime",
+            help: "Shows how long the OS has been running",
+            group: CommandGroup::General,
+            handler: uptime,
+        },
+        Command {
+            name: "/bg",
+            aliases: &[],
+            usage: "/bg <command>",
+            help: "Runs a command in its own thread, the prompt comes right back",
+            group: CommandGroup::Experimental,
+            handler: background,
+        },
+        Command {
+            name: "/exec",
+            aliases: &[],
+            usage: "/exec <program> [args...]",
+            help: "Runs an ELF program in user mode, Ctrl+C stops it",
+            group: CommandGroup::Experimental,
+            handler: exec,
+        },
+        Command {
+            name: "/echo",
+            aliases: &[],
+            usage: "/echo <text>",
+            help: "Echoes text",
+            group: CommandGroup::General,
+            handler: echo,
+        },
+        Command {
+            name: "/refr",
+            aliases: &[],
+            usage: "/refr echo",
+            help: "references the echo input",
+            group: CommandGroup::General,
+            handler: refr,
+        },
+        Command {
+            name: "/who",
+            aliases: &["/whoami"],
+            usage: "/who",
+            help: "Shows the current user",
+            group: CommandGroup::General,
+            handler: who,
+        },
+        Command {
+            name: "/asciitest",
+            aliases: &[],
+            usage: "/asciitest",
+            help: "prints the custom smiley face",
+            group: CommandGroup::Hidden,
+            handler: ascii_test,
+        },
+        Command {
+            name: "*print",
+            aliases: &[],
+            usage: "*print <binary>",
+            help: "prints the character with the given binary code",
+            group: CommandGroup::Hidden,
+            handler: print_binary,
+        },
+        Command {
+            name: "/1000_1C3",
+            aliases: &[],
+            usage: "/1000_1C3",
+            help: "credits",
+            group: CommandGroup::Hidden,
+            handler: credits,
+        },
+        Command {
+            name: "/qemu-exit",
+            aliases: &[],
+            usage: "/qemu-exit [success|failed]",
+            help: "ends a test run, qemu needs -device isa-debug-exit,iobase=0xf4,iosize=0x04",
+            group: CommandGroup::Hidden,
+            handler: qemu_exit,
+        },
+    ]
+}
+
+fn syshelp(shell: &mut Shell, _args: &[&str]) {
+    let commands = all();
+    outln!(shell, "==========System Help==================   \n");
+    for group in &[CommandGroup::General, CommandGroup::Experimental] {
+        if *group == CommandGroup::Experimental {
+            outln!(shell, "EXPERIMENTAL:");
+        }
+        for command in commands.iter().filter(|command| command.group == *group) {
+            outln!(shell, "{} = {}", command.usage, command.help);
+        }
+    }
+    outln!(shell, "=======================================   ");
+}
+
+fn cls(shell: &mut Shell, _args: &[&str]) {
+    match shell.terminal {
+        Terminal::Vga => {
+            for _ in 1..26 {
+                println!();
+            }
+        }
+        Terminal::Serial => {
+            let _ = shell.terminal.write_str("\x1b[2J\x1b[H"); // ANSI: clear the screen, cursor to the top left
+        }
+    }
+}
+
+fn sysinf(shell: &mut Shell, _args: &[&str]) {
+    outln!(shell, "=======System Information========\n");
+    ascii();
+    outln!(shell, "OS: S.T.B. OS by Admiralix      ");
+    outln!(shell, "OS VERSION: {} Build 09866      ", OSVER);
+    if let Some(cpu_name) = get_cpu_name() {
+        let name = core::str::from_utf8(&cpu_name).unwrap_or("<Invalid UTF-8>");
+        outln!(shell, " CPU Name: {}", name.trim());
+    } else {
+        fail!(shell, "Failed to retrieve CPU name.");
+    }
+    outln!(shell, "RES: 80x25px                    ");
+    outln!(shell, "RAM Size: UNKNOWN");
+    outln!(shell, "=================================");
+}
+
+fn shutdown(shell: &mut Shell, _args: &[&str]) {
+    outln!(shell, "Shutting down...");
+    let error = acpi::power_off();
+    fail!(shell, "Can't power off: {}", error);
+    exit_qemu(QemuExitCode::Success); // no ACPI, but maybe it's qemu with isa-debug-exit
+
+    // the old way, the user has to press the button
+    for _ in 1..26 {
+        println!();
+    }
+    print_shutdown();
+    hlt_loop();
+}
+
+fn reboot(shell: &mut Shell, _args: &[&str]) {
+    outln!(shell, "Restarting...");
+    acpi::reboot();
+    exit_qemu(QemuExitCode::Success); // the same fallback as /shutdown
+    fail!(shell, "Can't restart, the PC ignored both the reset register and the keyboard controller");
+}
+
+fn date(shell: &mut Shell, _args: &[&str]) {
+    let now = rtc::now();
+    outln!(shell, "{} {:04}-{:02}-{:02}", now.weekday(), now.year, now.month, now.day);
+}
+
+fn time(shell: &mut Shell, _args: &[&str]) {
+    let now = rtc::now();
+    outln!(shell, "{:02}:{:02}:{:02} UTC", now.hour, now.minute, now.second);
+}
+
+fn uptime(shell: &mut Shell, _args: &[&str]) {
+    let uptime = pit::uptime();
+    let seconds = uptime.as_secs();
+    outln!(
+        shell,
+        "Up {}:{:02}:{:02}.{:03} ({} ticks at {} Hz)",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        uptime.subsec_millis(),
+        pit::ticks(),
+        pit::FREQUENCY_HZ
+    );
+}
+
+fn background(shell: &mut Shell, args: &[&str]) {
+    if args.is_empty() {
+        return print_usage(shell, "/bg");
+    }
+    // the line gets parsed again in the thread, so `/bg "/sw notes.txt | /grep x"` runs the whole pipeline there
+    let line = args.join(" ");
+    // the full-screen editor takes over the keys, which a shell in the background doesn't get
+    let opens_editor = lex(&line).split(|token| *token == Token::Pipe).any(|stage| {
+        matches!(stage.first(), Some(Token::Word(name)) if find(name).is_some_and(|command| command.name == "/edit"))
+    });
+    if opens_editor {
+        return fail!(shell, "Can't run '{}' in the background: /edit needs the keyboard", line);
+    }
+    let mut background = Shell::with_terminal(shell.terminal);
+    background.cwd = shell.cwd.clone();
+    background.env = shell.env.clone();
+    background.in_background = true; // for an /edit further in, in a script
+    let handle = thread::spawn("bg", {
+        let line = line.clone();
+        move || background.execute(&line)
+    });
+    match handle {
+        Ok(handle) => outln!(shell, "[{}] {}", handle.id(), line),
+        Err(error) => fail!(shell, "Can't run '{}' in the background: {}", line, error),
+    }
+}
+
+fn exec(shell: &mut Shell, args: &[&str]) {
+    let path = match args.first() {
+        Some(path) => *path,
+        None => return print_usage(shell, "/exec"),
+    };
+    let image = match VFS.lock().read(&shell.path(path)) {
+        Ok(image) => image,
+        Err(error) => return fail!(shell, "Can't run '{}': {}", path, error),
+    };
+    // piped or redirected output is collected and handed on when the program is done, like any command's
+    let output = match shell.out {
+        Output::Terminal(terminal) => Output::Terminal(terminal),
+        Output::Buffer(_) => Output::Buffer(String::new()),
+    };
+    let program = Program {
+        image: &image,
+        args,
+        cwd: &shell.cwd,
+        input: shell.input.take().unwrap_or_default().into_bytes(),
+        output,
+        terminal: shell.terminal,
+    };
+    let finished = match process::spawn(program) {
+        Ok(handle) => handle.join(),
+        Err(error) => return fail!(shell, "Can't run '{}': {}", path, error),
+    };
+    if let Output::Buffer(text) = finished.output {
+        let _ = shell.out.write_str(&text);
+    }
+    match finished.status {
+        ExitStatus::Exited(code) => shell.status = code as u8,
+        ExitStatus::Faulted(fault) => fail!(shell, "'{}' was stopped: {}", path, fault),
+        ExitStatus::Killed => shell.status = STATUS_INTERRUPTED,
+    }
+}
+
+fn echo(shell: &mut Shell, args: &[&str]) {
+    let text = args.join(" ");
+    if !text.is_empty() {
+        outln!(shell, "{}", text);
+    }
+    shell.echo_text = text;
+}
+
+fn refr(shell: &mut Shell, args: &[&str]) {
+    if args != ["echo"] {
+        print_usage(shell, "/refr");
+        return;
+    }
+    outln!(shell, "--->    {}", shell.echo_text);
+}
+
+fn who(shell: &mut Shell, _args: &[&str]) {
+    outln!(shell, "USER: AOS User");
+    outln!(shell, "USER PRIVILEGES: Administrator");
+}
+
+fn ascii_test(_shell: &mut Shell, _args: &[&str]) {
+    print_smiley_face()
+}
+
+fn print_binary(shell: &mut Shell, args: &[&str]) {
+    match args {
+        [binary_string] => print_binary_character(shell, binary_string),
+        _ => print_usage(shell, "*print"),
+    }
+}
+
+pub fn print_binary_character(shell: &mut Shell, binary_string: &str) {
+    // Remove the '0b' prefix if present and parse the binary string
+    if let Ok(character) = u8::from_str_radix(&binary_string.replace("0b", ""), 2) {
+        // Convert the u8 value to a char
+        if let Some(ascii_char) = core::char::from_u32(character.into()) {
+            // Print the character
+            outln!(shell, "{}", ascii_char);
+        } else {
+            fail!(shell, "Invalid ASCII character");
+        }
+    } else {
+        fail!(shell, "Invalid binary representation");
+    }
+}
+
+fn qemu_exit(shell: &mut Shell, args: &[&str]) {
+    let code = match args {
+        [] | ["success"] => QemuExitCode::Success,
+        ["failed"] => QemuExitCode::Failed,
+        _ => return print_usage(shell, "/qemu-exit"),
+    };
+    exit_qemu(code);
+    // still running, so there's no isa-debug-exit device
+    fail!(shell, "Can't exit: not running in qemu with isa-debug-exit");
+}
+
+fn credits(shell: &mut Shell, _args: &[&str]) {
+    outln!(shell, "Thanks for Using S.T.B. OS!\n");
+    outln!(shell, "Admiralix Team:               ");
+    outln!(shell, "icewallowpiz - Leader of Project and Lead Programmer\n");
+    outln!(shell, "Contributors:                 ");
+    outln!(shell, "DAWOOD - Lead Website Designer\n");
+    outln!(shell, "Pr1thv1 - Fixed a Keyboard problem");
+    outln!(shell, "Special Thanks to:");
+    outln!(shell, "Snneezou");
+}
@@ -0,0 +1,58 @@
+/* Environment variables. They live in the shell, `$NAME` in a command line is replaced with their value, and a few
+   mean something to the shell itself: PROMPT is what the prompt looks like (see `Shell::prompt`) and USER is who
+   `%u` in it shows. Scripts share them, so AUTOEXEC can set PROMPT for the whole session. */
+
+use super::{print_usage, Command, CommandGroup, Shell};
+use alloc::{vec, vec::Vec};
+
+pub fn commands() -> Vec<Command> {
+    vec![
+        Command {
+            name: "/set",
+            aliases: &[],
+            usage: "/set [NAME=value]",
+            help: "sets an environment variable, on its own lists them",
+            group: CommandGroup::General,
+            handler: set,
+        },
+        Command {
+            name: "/unset",
+            aliases: &[],
+            usage: "/unset <NAME>",
+            help: "removes an environment variable",
+            group: CommandGroup::General,
+            handler: unset,
+        },
+        Command {
+            name: "/env",
+            aliases: &[],
+            usage: "/env",
+            help: "lists the environment variables",
+            group: CommandGroup::General,
+            handler: env,
+        },
+    ]
+}
+
+fn set(shell: &mut Shell, args: &[&str]) {
+    if args.is_empty() {
+        return env(shell, args);
+    }
+    if !shell.assign(&args.join(" ")) {
+        print_usage(shell, "/set");
+    }
+}
+
+fn unset(shell: &mut Shell, args: &[&str]) {
+    match args {
+        [name] if shell.env.remove(*name).is_some() => {}
+        [name] => fail!(shell, "Can't unset '{}': not set", name),
+        _ => print_usage(shell, "/unset"),
+    }
+}
+
+fn env(shell: &mut Shell, _args: &[&str]) {
+    for (name, value) in &shell.env {
+        outln!(shell, "{}={}", name, value);
+    }
+}
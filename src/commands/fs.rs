@@ -0,0 +1,525 @@
+/* The filesystem commands, they all take paths like `$/kernl/stbos.uff`, `../file1.txt` or just `file1.txt`
+   and go through the VFS, so they work the same on STBFS, FAT volumes and whatever else is mounted. */
+
+use super::{print_usage, Command, CommandGroup, Shell, Terminal};
+use crate::{
+    ata,
+    block::BlockDevice,
+    editor::Editor,
+    fat::FatVolume,
+    procfs::ProcFs,
+    rtc,
+    stbfs::{self, disk::DiskFs, Filesystem, FS},
+    vfs::{self, FileSystem, VFS},
+};
+use alloc::{boxed::Box, format, string::String, vec, vec::Vec};
+use core::fmt::Write;
+
+pub fn commands() -> Vec<Command> {
+    vec![
+        Command {
+            name: "/cd",
+            aliases: &[],
+            usage: "/cd <dir>",
+            help: "change dir.",
+            group: CommandGroup::Experimental,
+            handler: change_dir,
+        },
+        Command {
+            name: "/pwd",
+            aliases: &[],
+            usage: "/pwd",
+            help: "shows the current dir.",
+            group: CommandGroup::Experimental,
+            handler: print_dir,
+        },
+        Command {
+            name: "/lf",
+            aliases: &["/ls"],
+            usage: "/lf [-l] [dir]",
+            help: "list files, -l for sizes, times, permissions and owners",
+            group: CommandGroup::Experimental,
+            handler: list_files,
+        },
+        Command {
+            name: "/sw",
+            aliases: &["/cat"],
+            usage: "/sw <file>",
+            help: "show content of files",
+            group: CommandGroup::Experimental,
+            handler: show_file,
+        },
+        Command {
+            name: "/hexdump",
+            aliases: &["/hd"],
+            usage: "/hexdump <file>",
+            help: "shows the bytes of a file",
+            group: CommandGroup::Experimental,
+            handler: hexdump,
+        },
+        Command {
+            name: "/edit",
+            aliases: &["/nano"],
+            usage: "/edit <file>",
+            help: "opens a file in the full-screen editor",
+            group: CommandGroup::Experimental,
+            handler: edit_file,
+        },
+        Command {
+            name: "/mkdir",
+            aliases: &[],
+            usage: "/mkdir <dir>",
+            help: "makes a dir.",
+            group: CommandGroup::Experimental,
+            handler: make_dir,
+        },
+        Command {
+            name: "/tch",
+            aliases: &["/touch"],
+            usage: "/tch <filename> [content]",
+            help: "makes a new file, leaves it alone if it's there.",
+            group: CommandGroup::Experimental,
+            handler: touch_file,
+        },
+        Command {
+            name: "/write",
+            aliases: &[],
+            usage: "/write <file> <content>",
+            help: "replaces what's in a file.",
+            group: CommandGroup::Experimental,
+            handler: write_file,
+        },
+        Command {
+            name: "/append",
+            aliases: &[],
+            usage: "/append <file> <content>",
+            help: "adds a line to the end of a file.",
+            group: CommandGroup::Experimental,
+            handler: append_file,
+        },
+        Command {
+            name: "/rm",
+            aliases: &[],
+            usage: "/rm [-r] <path>",
+            help: "removes a file, -r for directories and everything in them.",
+            group: CommandGroup::Experimental,
+            handler: remove,
+        },
+        Command {
+            name: "/rmdir",
+            aliases: &[],
+            usage: "/rmdir [-r] <dir>",
+            help: "removes an empty dir, -r even if it isn't.",
+            group: CommandGroup::Experimental,
+            handler: remove_dir,
+        },
+        Command {
+            name: "/mv",
+            aliases: &[],
+            usage: "/mv <from> <to>",
+            help: "moves or renames a file or dir.",
+            group: CommandGroup::Experimental,
+            handler: move_path,
+        },
+        Command {
+            name: "/cp",
+            aliases: &[],
+            usage: "/cp [-r] <from> <to>",
+            help: "copies a file, -r for dirs.",
+            group: CommandGroup::Experimental,
+            handler: copy_path,
+        },
+        Command {
+            name: "/mkfs",
+            aliases: &[],
+            usage: "/mkfs",
+            help: "formats the data disk",
+            group: CommandGroup::Experimental,
+            handler: make_fs,
+        },
+        Command {
+            name: "/mount",
+            aliases: &[],
+            usage: "/mount [<ramfs|proc|stbfs|fat> [disk] <dir>]",
+            help: "mounts a filesystem, or lists them",
+            group: CommandGroup::Experimental,
+            handler: mount,
+        },
+        Command {
+            name: "/umount",
+            aliases: &[],
+            usage: "/umount <dir>",
+            help: "unmounts a filesystem",
+            group: CommandGroup::Experimental,
+            handler: unmount,
+        },
+    ]
+}
+
+fn change_dir(shell: &mut Shell, args: &[&str]) {
+    let path = match args {
+        [path] => *path,
+        [] => "$/", // like on unix, /cd on its own goes home
+        _ => return print_usage(shell, "/cd"),
+    };
+    let absolute = shell.path(path);
+    match VFS.lock().stat(&absolute) {
+        Ok(stat) if stat.is_dir() => shell.cwd = absolute,
+        Ok(_) => fail!(shell, "Can't change to '{}': not a directory", path),
+        Err(error) => fail!(shell, "Can't change to '{}': {}", path, error),
+    }
+}
+
+fn print_dir(shell: &mut Shell, _args: &[&str]) {
+    outln!(shell, "{}", shell.cwd);
+}
+
+fn list_files(shell: &mut Shell, args: &[&str]) {
+    let (long, args) = match args {
+        ["-l", rest @ ..] => (true, rest),
+        _ => (false, args),
+    };
+    let path = match args {
+        [path] => *path,
+        [] => ".",
+        _ => return print_usage(shell, "/lf"),
+    };
+    let absolute = shell.path(path);
+    let mut vfs = VFS.lock();
+    let entries = match vfs.read_dir(&absolute) {
+        Ok(entries) => entries,
+        Err(error) => return fail!(shell, "Can't list '{}': {}", path, error),
+    };
+
+    outln!(shell, "Contents of directory '{}':", absolute);
+    for entry in entries {
+        if long {
+            match vfs.stat(&vfs::normalize(&absolute, &entry.name)) {
+                Ok(stat) => outln!(
+                    shell,
+                    "{} {:<5} {:>8} {} {}",
+                    stat.mode_string(),
+                    owner_name(stat.owner),
+                    stat.size,
+                    short_time(stat.modified),
+                    entry.name
+                ),
+                Err(error) => outln!(shell, "?????????? {}: {}", entry.name, error),
+            }
+            continue;
+        }
+        match entry.kind {
+            vfs::FileType::Directory => outln!(shell, "Directory: {}", entry.name),
+            vfs::FileType::File => outln!(shell, "File: {}", entry.name),
+        }
+    }
+}
+
+/// There are no user names yet, only root has one.
+fn owner_name(owner: u16) -> String {
+    match owner {
+        stbfs::ROOT_USER => String::from("root"),
+        owner => format!("{}", owner),
+    }
+}
+
+/// `2026-10-18 12:34`, or a dash when the time isn't known.
+fn short_time(time: u64) -> String {
+    if time == 0 {
+        return format!("{:<16}", "-");
+    }
+    let time = rtc::DateTime::from_unix(time);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", time.year, time.month, time.day, time.hour, time.minute)
+}
+
+fn show_file(shell: &mut Shell, args: &[&str]) {
+    let path = match args {
+        [path] => *path,
+        _ => return print_usage(shell, "/sw"),
+    };
+    match VFS.lock().read(&shell.path(path)) {
+        Ok(content) => {
+            let text = String::from_utf8_lossy(&content);
+            if text.ends_with('\n') {
+                let _ = shell.out.write_str(&text); // so `/sw notes.txt | wc` doesn't count an extra line
+            } else {
+                outln!(shell, "{}", text);
+            }
+        }
+        Err(error) => fail!(shell, "Can't show '{}': {}", path, error),
+    }
+}
+
+fn hexdump(shell: &mut Shell, args: &[&str]) {
+    let path = match args {
+        [path] => *path,
+        _ => return print_usage(shell, "/hexdump"),
+    };
+    let content = match VFS.lock().read(&shell.path(path)) {
+        Ok(content) => content,
+        Err(error) => return fail!(shell, "Can't show '{}': {}", path, error),
+    };
+
+    // 16 bytes a line: offset, the bytes in hex, then the printable ones as text
+    for (line, chunk) in content.chunks(16).enumerate() {
+        let mut hex = String::new();
+        let mut text = String::new();
+        for (i, byte) in chunk.iter().enumerate() {
+            if i == 8 {
+                hex.push(' ');
+            }
+            let _ = write!(hex, "{:02x} ", byte);
+            text.push(if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' });
+        }
+        outln!(shell, "{:08x}  {:<49} |{}|", line * 16, hex, text);
+    }
+    outln!(shell, "{:08x}", content.len());
+}
+
+fn edit_file(shell: &mut Shell, args: &[&str]) {
+    let path = match args {
+        [path] => *path,
+        _ => return print_usage(shell, "/edit"),
+    };
+    if shell.terminal != Terminal::Vga {
+        return fail!(shell, "Can't edit '{}': the editor only works on the screen", path);
+    }
+    if shell.in_background {
+        return fail!(shell, "Can't edit '{}': the editor doesn't work in the background", path);
+    }
+    let absolute = shell.path(path);
+    if let Ok(stat) = VFS.lock().stat(&absolute) {
+        if stat.is_dir() {
+            return fail!(shell, "Can't edit '{}': is a directory", path);
+        }
+    }
+    shell.editor = Some(Editor::open(&absolute));
+}
+
+fn make_dir(shell: &mut Shell, args: &[&str]) {
+    let path = match args {
+        [path] => *path,
+        _ => return print_usage(shell, "/mkdir"),
+    };
+    match VFS.lock().mkdir(&shell.path(path)) {
+        Ok(()) => outln!(shell, "Directory '{}' created.", path),
+        Err(error) => fail!(shell, "Can't create directory '{}': {}", path, error),
+    }
+}
+
+fn touch_file(shell: &mut Shell, args: &[&str]) {
+    let (path, content) = match args {
+        [path, content @ ..] => (*path, content.join(" ")),
+        _ => return print_usage(shell, "/tch"),
+    };
+    let absolute = shell.path(path);
+    let mut vfs = VFS.lock();
+    match vfs.stat(&absolute) {
+        Ok(_) if content.is_empty() => return, // like on unix, touching something that's there is fine
+        Ok(_) => return fail!(shell, "File '{}' already exists, use /write to change it.", path),
+        Err(_) => {}
+    }
+    match vfs.create(&absolute, content.as_bytes()) {
+        Ok(()) => outln!(shell, "File '{}' created.", path),
+        Err(error) => fail!(shell, "Can't create file '{}': {}", path, error),
+    }
+}
+
+fn write_file(shell: &mut Shell, args: &[&str]) {
+    let (path, content) = match args {
+        [path, content @ ..] if !content.is_empty() => (*path, content.join(" ")),
+        _ => return print_usage(shell, "/write"),
+    };
+    if let Err(error) = VFS.lock().write(&shell.path(path), content.as_bytes()) {
+        fail!(shell, "Can't write '{}': {}", path, error);
+    }
+}
+
+fn append_file(shell: &mut Shell, args: &[&str]) {
+    let (path, mut content) = match args {
+        [path, content @ ..] if !content.is_empty() => (*path, content.join(" ")),
+        _ => return print_usage(shell, "/append"),
+    };
+    content.push('\n');
+    if let Err(error) = VFS.lock().append(&shell.path(path), content.as_bytes()) {
+        fail!(shell, "Can't append to '{}': {}", path, error);
+    }
+}
+
+/// Splits a leading `-r` off the arguments.
+fn recursive_flag<'a>(args: &'a [&'a str]) -> (bool, &'a [&'a str]) {
+    match args {
+        ["-r", rest @ ..] => (true, rest),
+        _ => (false, args),
+    }
+}
+
+/// Whether the shell is in `path` or somewhere below it, then it can't go away. Other shells can be in there
+/// too, they move up on their next command line (see `Shell::execute`).
+fn holds_cwd(shell: &Shell, path: &str) -> bool {
+    shell.cwd == path || vfs::is_below(&shell.cwd, path)
+}
+
+fn remove(shell: &mut Shell, args: &[&str]) {
+    let (recursive, path) = match recursive_flag(args) {
+        (recursive, [path]) => (recursive, *path),
+        _ => return print_usage(shell, "/rm"),
+    };
+    let absolute = shell.path(path);
+    if holds_cwd(shell, &absolute) {
+        return fail!(shell, "Can't remove '{}': the shell is in there", path);
+    }
+    let mut vfs = VFS.lock();
+    let result = match vfs.stat(&absolute) {
+        Ok(stat) if stat.is_dir() && !recursive => Err(vfs::VfsError::IsADirectory),
+        Ok(_) => vfs.remove_all(&absolute),
+        Err(error) => Err(error),
+    };
+    if let Err(error) = result {
+        fail!(shell, "Can't remove '{}': {}", path, error);
+    }
+}
+
+fn remove_dir(shell: &mut Shell, args: &[&str]) {
+    let (recursive, path) = match recursive_flag(args) {
+        (recursive, [path]) => (recursive, *path),
+        _ => return print_usage(shell, "/rmdir"),
+    };
+    let absolute = shell.path(path);
+    if holds_cwd(shell, &absolute) {
+        return fail!(shell, "Can't remove '{}': the shell is in there", path);
+    }
+    let mut vfs = VFS.lock();
+    let result = match vfs.stat(&absolute) {
+        Ok(stat) if !stat.is_dir() => Err(vfs::VfsError::NotADirectory),
+        Ok(_) if recursive => vfs.remove_all(&absolute),
+        Ok(_) => vfs.unlink(&absolute),
+        Err(error) => Err(error),
+    };
+    if let Err(error) = result {
+        fail!(shell, "Can't remove '{}': {}", path, error);
+    }
+}
+
+/// Where `from` ends up when it's moved or copied to `to`, which may be a directory to put it in.
+fn target_path(vfs: &mut vfs::Vfs, from: &str, to: &str) -> String {
+    match (vfs.stat(to), vfs::split_parent(from)) {
+        (Ok(stat), Some((_, name))) if stat.is_dir() => vfs::normalize(to, name),
+        _ => String::from(to),
+    }
+}
+
+fn move_path(shell: &mut Shell, args: &[&str]) {
+    let (from, to) = match args {
+        [from, to] => (*from, *to),
+        _ => return print_usage(shell, "/mv"),
+    };
+    let absolute = shell.path(from);
+    if holds_cwd(shell, &absolute) {
+        return fail!(shell, "Can't move '{}': the shell is in there", from);
+    }
+    let mut vfs = VFS.lock();
+    let target = target_path(&mut vfs, &absolute, &shell.path(to));
+    if let Err(error) = vfs.rename(&absolute, &target) {
+        fail!(shell, "Can't move '{}' to '{}': {}", from, to, error);
+    }
+}
+
+fn copy_path(shell: &mut Shell, args: &[&str]) {
+    let (recursive, from, to) = match recursive_flag(args) {
+        (recursive, [from, to]) => (recursive, *from, *to),
+        _ => return print_usage(shell, "/cp"),
+    };
+    let absolute = shell.path(from);
+    let mut vfs = VFS.lock();
+    let target = target_path(&mut vfs, &absolute, &shell.path(to));
+    if let Err(error) = vfs.copy(&absolute, &target, recursive) {
+        fail!(shell, "Can't copy '{}' to '{}': {}", from, to, error);
+    }
+}
+
+fn make_fs(shell: &mut Shell, _args: &[&str]) {
+    // reuse the disk we're mounted on, otherwise go look for one
+    let detached = FS.lock().detach();
+    let device = match detached {
+        Some(disk) => disk.into_device(),
+        None => match stbfs::find_disk() {
+            Some(drive) => {
+                VFS.lock().set_source(vfs::ROOT_PATH, drive.name());
+                Box::new(drive)
+            }
+            None => return fail!(shell, "No data disk found."),
+        },
+    };
+    outln!(shell, "Formatting disk, this may take a moment...");
+    match stbfs::format(device) {
+        Ok(()) => outln!(shell, "Disk formatted, files are now saved to it."),
+        Err(error) => fail!(shell, "Can't format disk: {}", error),
+    }
+}
+
+fn mount(shell: &mut Shell, args: &[&str]) {
+    let (kind, disk, path) = match args {
+        [] => return list_mounts(shell),
+        [kind, path] => (*kind, None, *path),
+        [kind, disk, path] => (*kind, Some(*disk), *path),
+        _ => return print_usage(shell, "/mount"),
+    };
+
+    let fs: Box<dyn FileSystem> = match (kind, disk) {
+        ("ramfs", None) => Box::new(Filesystem::new()),
+        ("proc", None) => Box::new(ProcFs),
+        ("stbfs", Some(name)) | ("fat", Some(name)) => {
+            let drive = match ata::open(name) {
+                Some(drive) => drive,
+                None => return fail!(shell, "Can't open disk '{}': not there or already in use", name),
+            };
+            let device: Box<dyn BlockDevice + Send> = Box::new(drive);
+            let mounted = match kind {
+                "stbfs" => DiskFs::mount(device)
+                    .and_then(Filesystem::load)
+                    .map(|fs| Box::new(fs) as Box<dyn FileSystem>)
+                    .map_err(vfs::VfsError::from),
+                _ => FatVolume::mount(device)
+                    .map(|volume| Box::new(volume) as Box<dyn FileSystem>)
+                    .map_err(vfs::VfsError::from),
+            };
+            match mounted {
+                Ok(fs) => fs,
+                Err(error) => return fail!(shell, "Can't mount '{}' as {}: {}", name, kind, error),
+            }
+        }
+        _ => return print_usage(shell, "/mount"),
+    };
+
+    let absolute = shell.path(path);
+    match VFS.lock().mount(&absolute, disk.unwrap_or(kind), fs) {
+        Ok(()) => outln!(shell, "Mounted {} at '{}'.", kind, absolute),
+        Err(error) => fail!(shell, "Can't mount at '{}': {}", path, error),
+    }
+}
+
+fn list_mounts(shell: &mut Shell) {
+    let vfs = VFS.lock();
+    for mount in vfs.mounts() {
+        outln!(shell, "{} on {} type {}", mount.source, mount.path, mount.fs.fs_type());
+    }
+}
+
+fn unmount(shell: &mut Shell, args: &[&str]) {
+    let path = match args {
+        [path] => *path,
+        _ => return print_usage(shell, "/umount"),
+    };
+    let absolute = shell.path(path);
+    let result = VFS.lock().umount(&absolute);
+    match result {
+        Ok(_) => {
+            if shell.cwd == absolute || vfs::is_below(&shell.cwd, &absolute) {
+                shell.cwd = vfs::normalize(&absolute, ".."); // we were in there, go to where it was mounted
+            }
+            outln!(shell, "Unmounted '{}'.", absolute);
+        }
+        Err(error) => fail!(shell, "Can't unmount '{}': {}", path, error),
+    }
+}
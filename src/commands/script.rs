@@ -0,0 +1,271 @@
+/* Batch files, like .BAT files on DOS. `/run setup.stb one two` reads the file and runs it line by line, anything
+   that isn't one of the few script keywords below goes through `Shell::execute`, same as a line typed at the prompt.
+
+       # comments start with #, :: or rem
+       set NAME=$1              (same as /set, $NAME reads it, $1.. are the arguments, $? the last status)
+       if not exist $/docs /mkdir $/docs
+       if "$NAME" == "" goto usage
+       if errorlevel 1 exit 1   (true when the last status is 1 or more, like on DOS)
+       :usage
+       exit 2
+
+   Variables are the shell's environment ones, a script that sets one leaves it set. Command lines are expanded by
+   `Shell::execute`, the keywords expand their own words. */
+
+use super::{print_usage, Command, CommandGroup, Shell};
+use crate::vfs::VFS;
+use alloc::{collections::BTreeMap, format, string::String, vec, vec::Vec};
+use core::{fmt, mem};
+
+const MAX_STEPS: usize = 10_000; // lines run before we decide the script is stuck in a goto loop
+const MAX_DEPTH: usize = 8; // scripts running scripts, so one that runs itself doesn't eat the stack
+
+/// Where the boot script lives, the first one that exists is run before the first prompt.
+pub const AUTOEXEC: [&str; 2] = ["$/AUTOEXEC", "$/AUTOEXEC.STB"];
+
+const IF_USAGE: &str = "if [not] <a> ==|!= <b> | exist <path> | errorlevel <n> <command>";
+
+pub fn commands() -> Vec<Command> {
+    vec![Command {
+        name: "/run",
+        aliases: &[],
+        usage: "/run <script> [args...]",
+        help: "runs the commands in a script file.",
+        group: CommandGroup::Experimental,
+        handler: run_script,
+    }]
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptError {
+    NoLabel(String),
+    Syntax(&'static str),
+    BadStatus(String),
+    TooManySteps,
+    TooDeep,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::NoLabel(label) => write!(f, "no label ':{}'", label),
+            ScriptError::Syntax(usage) => write!(f, "expected {}", usage),
+            ScriptError::BadStatus(status) => write!(f, "'{}' isn't an exit status (0-255)", status),
+            ScriptError::TooManySteps => write!(f, "stopped after {} lines, stuck in a loop?", MAX_STEPS),
+            ScriptError::TooDeep => write!(f, "scripts nested more than {} deep", MAX_DEPTH),
+        }
+    }
+}
+
+fn run_script(shell: &mut Shell, args: &[&str]) {
+    match args {
+        [path, args @ ..] => run(shell, path, args),
+        _ => print_usage(shell, "/run"),
+    }
+}
+
+/// Runs the script at `path` with `args` as `$1`, `$2`...
+///
+/// Afterwards `shell.status` is the script's exit status: the one given to `exit`,
+/// otherwise the status of the last command it ran.
+pub fn run(shell: &mut Shell, path: &str, args: &[&str]) {
+    if shell.script_depth >= MAX_DEPTH {
+        return fail!(shell, "Can't run '{}': {}", path, ScriptError::TooDeep);
+    }
+    let content = VFS.lock().read(&shell.path(path)); // unlocked again before the commands run, they need it
+    let content = match content {
+        Ok(content) => content,
+        Err(error) => return fail!(shell, "Can't run '{}': {}", path, error),
+    };
+    let text = String::from_utf8_lossy(&content);
+    let script = Script::new(&text);
+    let args = core::iter::once(path).chain(args.iter().copied()).map(String::from).collect();
+
+    let outer_args = mem::replace(&mut shell.args, args);
+    shell.script_depth += 1;
+    let result = script.run(shell);
+    shell.script_depth -= 1;
+    shell.args = outer_args;
+    match result {
+        Ok(status) => shell.status = status,
+        Err((line, error)) => fail!(shell, "{}:{}: {}", path, line + 1, error),
+    }
+}
+
+/// Runs the AUTOEXEC script from the STBFS root if there is one, the shell does this once at boot.
+pub fn autoexec(shell: &mut Shell) {
+    let found = AUTOEXEC.iter().find(|path| VFS.lock().stat(path).map(|stat| !stat.is_dir()).unwrap_or(false));
+    if let Some(path) = found {
+        run(shell, path, &[]);
+    }
+}
+
+/// What to do after a line.
+enum Flow {
+    Next,
+    Goto(String),
+    Exit(u8),
+}
+
+struct Script<'a> {
+    lines: Vec<&'a str>,
+    labels: BTreeMap<&'a str, usize>, // label name -> its line
+}
+
+impl<'a> Script<'a> {
+    fn new(text: &'a str) -> Self {
+        let lines: Vec<&str> = text.lines().map(str::trim).collect();
+        let labels = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| line.starts_with(':') && !line.starts_with("::"))
+            .map(|(index, line)| (line[1..].trim(), index))
+            .collect();
+        Script { lines, labels }
+    }
+
+    /// Runs the whole script, errors come with the (0 based) line they happened on.
+    fn run(&self, shell: &mut Shell) -> Result<u8, (usize, ScriptError)> {
+        let mut line = 0;
+        let mut steps = 0;
+        while line < self.lines.len() {
+            steps += 1;
+            if steps > MAX_STEPS {
+                return Err((line, ScriptError::TooManySteps));
+            }
+            match self.statement(shell, self.lines[line]).map_err(|error| (line, error))? {
+                Flow::Next => line += 1,
+                Flow::Goto(label) => match self.labels.get(label.as_str()) {
+                    Some(&target) => line = target,
+                    None => return Err((line, ScriptError::NoLabel(label))),
+                },
+                Flow::Exit(status) => return Ok(status),
+            }
+        }
+        Ok(shell.status)
+    }
+
+    fn statement(&self, shell: &mut Shell, line: &str) -> Result<Flow, ScriptError> {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(':') {
+            return Ok(Flow::Next); // comments and labels
+        }
+        let (keyword, rest) = next_word(line);
+        match keyword.to_ascii_lowercase().as_str() {
+            "rem" => Ok(Flow::Next),
+            "set" => set(shell, rest),
+            "goto" => match next_word(rest) {
+                (label, "") if !label.is_empty() => Ok(Flow::Goto(shell.expand(&label))),
+                _ => Err(ScriptError::Syntax("goto <label>")),
+            },
+            "exit" => match rest {
+                "" => Ok(Flow::Exit(shell.status)),
+                status => parse_status(&shell.expand(status)).map(Flow::Exit),
+            },
+            "if" => self.condition(shell, rest),
+            _ => {
+                shell.execute(line);
+                Ok(Flow::Next)
+            }
+        }
+    }
+
+    /// `if [not] <condition> <statement>`, the statement can be a command or another keyword like goto.
+    fn condition(&self, shell: &mut Shell, line: &str) -> Result<Flow, ScriptError> {
+        let (mut word, mut rest) = next_word(line);
+        let negate = word.eq_ignore_ascii_case("not");
+        if negate {
+            (word, rest) = next_word(rest);
+        }
+        let (holds, command) = if word.eq_ignore_ascii_case("exist") {
+            let (path, command) = next_word(rest);
+            if path.is_empty() {
+                return Err(ScriptError::Syntax(IF_USAGE));
+            }
+            (VFS.lock().stat(&shell.path(&shell.expand(&path))).is_ok(), command)
+        } else if word.eq_ignore_ascii_case("errorlevel") {
+            let (level, command) = next_word(rest);
+            (shell.status >= parse_status(&shell.expand(&level))?, command)
+        } else {
+            let (operator, rest) = next_word(rest);
+            let (right, command) = next_word(rest);
+            let (left, right) = (shell.expand(&word), shell.expand(&right));
+            match operator.as_str() {
+                "==" => (left == right, command),
+                "!=" => (left != right, command),
+                _ => return Err(ScriptError::Syntax(IF_USAGE)),
+            }
+        };
+        if command.is_empty() {
+            return Err(ScriptError::Syntax(IF_USAGE));
+        }
+        if holds != negate {
+            self.statement(shell, command)
+        } else {
+            Ok(Flow::Next)
+        }
+    }
+}
+
+/// `set NAME=value` in a script, the same as `/set` at the prompt.
+fn set(shell: &mut Shell, assignment: &str) -> Result<Flow, ScriptError> {
+    let assignment = shell.expand(assignment);
+    let assignment = match assignment.split_once('=') {
+        // `set PROMPT="%w> "` works the same as with /set, where the quotes are needed for the >
+        Some((name, value)) if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') => {
+            format!("{}={}", name, &value[1..value.len() - 1])
+        }
+        _ => assignment,
+    };
+    if assignment.is_empty() {
+        shell.execute("/env");
+    } else if !shell.assign(&assignment) {
+        return Err(ScriptError::Syntax("set <name>=<value>"));
+    }
+    Ok(Flow::Next)
+}
+
+/// Splits the first word off a line, double quotes group words like in `tokenize` and are taken off.
+fn next_word(line: &str) -> (String, &str) {
+    let line = line.trim_start();
+    let mut word = String::new();
+    let mut in_quotes = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => return (word, line[index..].trim()),
+            c => word.push(c),
+        }
+    }
+    (word, "")
+}
+
+fn parse_status(status: &str) -> Result<u8, ScriptError> {
+    status.trim().parse().map_err(|_| ScriptError::BadStatus(String::from(status.trim())))
+}
+
+#[test_case]
+fn test_labels() {
+    let script = Script::new("set N=1\n  :start\n:: a comment\n: spaced \nrem :not_a_label\ngoto start\n");
+    assert_eq!(script.labels.get("start"), Some(&1));
+    assert_eq!(script.labels.get("spaced"), Some(&3));
+    assert_eq!(script.labels.len(), 2);
+}
+
+#[test_case]
+fn test_next_word() {
+    assert_eq!(next_word("  goto   label  "), (String::from("goto"), "label"));
+    assert_eq!(next_word(r#""two words" == "" /echo"#), (String::from("two words"), r#"== "" /echo"#));
+    assert_eq!(next_word(r#"a"b c"d e"#), (String::from("ab cd"), "e"));
+    assert_eq!(next_word(""), (String::new(), ""));
+}
+
+#[test_case]
+fn test_run() {
+    let mut shell = Shell::new();
+    shell.args = vec![String::from("count.stb"), String::from("xxx")];
+    let script = Script::new("set N=x\n:loop\nif $N == $1 goto done\nset N=x$N\ngoto loop\n:done\nexit 3\n");
+    assert_eq!(script.run(&mut shell), Ok(3));
+    assert_eq!(shell.expand("$N"), "xxx");
+    assert_eq!(Script::new("\n goto nowhere").run(&mut shell), Err((1, ScriptError::NoLabel(String::from("nowhere")))));
+    assert_eq!(Script::new("if $N ==").run(&mut shell), Err((0, ScriptError::Syntax(IF_USAGE))));
+}
@@ -0,0 +1,175 @@
+/* Filters for text, mostly useful on the right of a pipe: `/lf | /grep txt`, `/sw log.txt | /sort | /head -n 5`.
+   Each one reads the file it's given, or the piped text when there's no file. */
+
+use super::{print_usage, Command, CommandGroup, Shell};
+use crate::vfs::VFS;
+use alloc::{string::String, vec, vec::Vec};
+
+pub fn commands() -> Vec<Command> {
+    vec![
+        Command {
+            name: "/grep",
+            aliases: &[],
+            usage: "/grep [-i] [-v] <text> [file]",
+            help: "shows the lines that have <text> in them, -i ignores case, -v shows the others",
+            group: CommandGroup::Experimental,
+            handler: grep,
+        },
+        Command {
+            name: "/wc",
+            aliases: &[],
+            usage: "/wc [file]",
+            help: "counts lines, words and bytes",
+            group: CommandGroup::Experimental,
+            handler: word_count,
+        },
+        Command {
+            name: "/head",
+            aliases: &[],
+            usage: "/head [-n <lines>] [file]",
+            help: "shows the first 10 lines (or -n of them)",
+            group: CommandGroup::Experimental,
+            handler: head,
+        },
+        Command {
+            name: "/sort",
+            aliases: &[],
+            usage: "/sort [-r] [file]",
+            help: "sorts lines, -r for the other way around",
+            group: CommandGroup::Experimental,
+            handler: sort,
+        },
+    ]
+}
+
+/// Takes the leading `-x` flags off the arguments, anything not in `known` is an error.
+fn flags<'a>(args: &'a [&'a str], known: &str) -> Result<(Vec<char>, &'a [&'a str]), ()> {
+    let mut found = Vec::new();
+    let mut rest = args;
+    while let Some((flag, after)) = rest.split_first() {
+        match flag.strip_prefix('-') {
+            Some(letters) if !letters.is_empty() => {
+                if !letters.chars().all(|letter| known.contains(letter)) {
+                    return Err(());
+                }
+                found.extend(letters.chars());
+                rest = after;
+            }
+            _ => break,
+        }
+    }
+    Ok((found, rest))
+}
+
+/// The text a filter works on: the file if there is one, otherwise what was piped in.
+///
+/// `None` when there's neither (or the file can't be read), the error is printed already.
+fn input(shell: &mut Shell, file: Option<&str>, command: &str) -> Option<String> {
+    match file {
+        Some(file) => match VFS.lock().read(&shell.path(file)) {
+            Ok(content) => Some(String::from(String::from_utf8_lossy(&content))),
+            Err(error) => {
+                fail!(shell, "Can't read '{}': {}", file, error);
+                None
+            }
+        },
+        None => {
+            let piped = shell.input.take();
+            if piped.is_none() {
+                print_usage(shell, command);
+            }
+            piped
+        }
+    }
+}
+
+fn grep(shell: &mut Shell, args: &[&str]) {
+    let (flags, args) = match flags(args, "iv") {
+        Ok(parsed) => parsed,
+        Err(()) => return print_usage(shell, "/grep"),
+    };
+    let (pattern, file) = match args {
+        [pattern] => (*pattern, None),
+        [pattern, file] => (*pattern, Some(*file)),
+        _ => return print_usage(shell, "/grep"),
+    };
+    let text = match input(shell, file, "/grep") {
+        Some(text) => text,
+        None => return,
+    };
+    let ignore_case = flags.contains(&'i');
+    let invert = flags.contains(&'v');
+    let pattern = if ignore_case { pattern.to_lowercase() } else { String::from(pattern) };
+
+    let mut matched = false;
+    for line in text.lines() {
+        let found = if ignore_case {
+            line.to_lowercase().contains(&pattern)
+        } else {
+            line.contains(&pattern)
+        };
+        if found != invert {
+            outln!(shell, "{}", line);
+            matched = true;
+        }
+    }
+    if !matched {
+        shell.status = 1; // like grep, so scripts can do `if errorlevel 1`
+    }
+}
+
+fn word_count(shell: &mut Shell, args: &[&str]) {
+    let file = match args {
+        [] => None,
+        [file] => Some(*file),
+        _ => return print_usage(shell, "/wc"),
+    };
+    if let Some(text) = input(shell, file, "/wc") {
+        let lines = text.lines().count();
+        let words = text.split_whitespace().count();
+        outln!(shell, "{} {} {}", lines, words, text.len());
+    }
+}
+
+fn head(shell: &mut Shell, args: &[&str]) {
+    let (count, file) = match args {
+        [] => (Some(10), None),
+        [file] => (Some(10), Some(*file)),
+        ["-n", count] => (count.parse().ok(), None),
+        ["-n", count, file] => (count.parse().ok(), Some(*file)),
+        _ => (None, None),
+    };
+    let count: usize = match count {
+        Some(count) => count,
+        None => return print_usage(shell, "/head"),
+    };
+    if let Some(text) = input(shell, file, "/head") {
+        for line in text.lines().take(count) {
+            outln!(shell, "{}", line);
+        }
+    }
+}
+
+fn sort(shell: &mut Shell, args: &[&str]) {
+    let (flags, args) = match flags(args, "r") {
+        Ok(parsed) => parsed,
+        Err(()) => return print_usage(shell, "/sort"),
+    };
+    let file = match args {
+        [] => None,
+        [file] => Some(*file),
+        _ => return print_usage(shell, "/sort"),
+    };
+    let text = match input(shell, file, "/sort") {
+        Some(text) => text,
+        None => return,
+    };
+    let mut lines: Vec<&str> = text.lines().collect();
+    lines.sort_unstable();
+    if flags.contains(&'r') {
+        lines.reverse();
+    }
+    for line in lines {
+        outln!(shell, "{}", line);
+    }
+}
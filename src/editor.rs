@@ -0,0 +1,344 @@
+/* A nano-style full-screen editor, `/edit <file>` starts it. While it's open it gets every key instead of the
+   line editor and draws straight into the VGA buffer, when it closes the screen from before comes back.
+
+   Screen:  row 0        title bar with the file name
+            rows 1-22    the text, scrolls up/down and sideways to keep the cursor visible
+            row 23       messages and questions
+            row 24       the keys
+
+   Keys:  arrows, Home/End, PageUp/PageDown   move around
+          Ctrl+O or Ctrl+S                    save
+          Ctrl+W                              search, Enter on an empty search finds the last one again
+          Ctrl+X                              exit, asks first if there are unsaved changes  */
+
+use crate::{
+    vfs::VFS,
+    vga_buffer::{set_cursor_position, Color, ColorCode, Screen, BUFFER_HEIGHT, BUFFER_WIDTH, WRITER},
+};
+use alloc::{boxed::Box, format, string::String, vec::Vec};
+use pc_keyboard::{DecodedKey, KeyCode};
+use x86_64::instructions::interrupts;
+
+const TEXT_TOP: usize = 1;
+const TEXT_ROWS: usize = BUFFER_HEIGHT - 3;
+const MESSAGE_ROW: usize = BUFFER_HEIGHT - 2;
+const HELP_ROW: usize = BUFFER_HEIGHT - 1;
+const TAB_WIDTH: usize = 4;
+
+const CTRL_O: char = '\u{f}';
+const CTRL_S: char = '\u{13}';
+const CTRL_W: char = '\u{17}';
+const CTRL_X: char = '\u{18}';
+const BACKSPACE: char = '\u{8}';
+const DELETE: char = '\u{7f}';
+const ESCAPE: char = '\u{1b}';
+
+const HELP: &str = "^O Save   ^W Where is   ^X Exit";
+
+/// What the keys do right now.
+enum Mode {
+    Editing,
+    Search(Vec<char>), // typing what to look for
+    ConfirmExit,       // "save changes?"
+}
+
+pub struct Editor {
+    path: String, // absolute
+    lines: Vec<Vec<char>>,
+    row: usize,  // cursor line
+    col: usize,  // cursor column in that line
+    top: usize,  // first line on screen
+    left: usize, // first column on screen
+    modified: bool,
+    mode: Mode,
+    message: String,
+    last_search: Vec<char>,
+    saved_screen: Box<Screen>, // what was on screen before, put back on exit
+}
+
+impl Editor {
+    /// Opens `path` (absolute) in the editor and draws it. A file that isn't there yet gets made on save.
+    pub fn open(path: &str) -> Self {
+        let (text, message) = match VFS.lock().read(path) {
+            Ok(content) => (String::from_utf8_lossy(&content).into_owned(), String::new()),
+            Err(crate::vfs::VfsError::NotFound) => (String::new(), String::from("[ New File ]")),
+            Err(error) => (String::new(), format!("Can't read it: {}", error)),
+        };
+        // boxed after the interrupts are back on, allocating with them off can hang on the heap's lock
+        let saved_screen = Box::new(interrupts::without_interrupts(|| WRITER.lock().save_screen()));
+        let mut editor = Editor {
+            path: String::from(path),
+            lines: text.split('\n').map(|line| line.chars().collect()).collect(),
+            row: 0,
+            col: 0,
+            top: 0,
+            left: 0,
+            modified: false,
+            mode: Mode::Editing,
+            message,
+            last_search: Vec::new(),
+            saved_screen,
+        };
+        editor.render();
+        editor
+    }
+
+    /// Feeds one key to the editor. Returns false once it's been closed.
+    pub fn handle_key(&mut self, key: DecodedKey) -> bool {
+        let open = match self.mode {
+            Mode::Editing => self.edit_key(key),
+            Mode::Search(_) => {
+                self.search_key(key);
+                true
+            }
+            Mode::ConfirmExit => self.confirm_key(key),
+        };
+        if open {
+            self.render();
+        } else {
+            self.close();
+        }
+        open
+    }
+
+    fn edit_key(&mut self, key: DecodedKey) -> bool {
+        self.message.clear();
+        match key {
+            DecodedKey::Unicode(CTRL_X) if self.modified => {
+                self.mode = Mode::ConfirmExit;
+                self.message = String::from("Save modified buffer? (y/n, Esc to go back)");
+            }
+            DecodedKey::Unicode(CTRL_X) => return false,
+            DecodedKey::Unicode(CTRL_O) | DecodedKey::Unicode(CTRL_S) => {
+                self.save();
+            }
+            DecodedKey::Unicode(CTRL_W) => self.mode = Mode::Search(Vec::new()),
+            DecodedKey::Unicode('\n') => {
+                let rest = self.lines[self.row].split_off(self.col);
+                self.lines.insert(self.row + 1, rest);
+                self.row += 1;
+                self.col = 0;
+                self.modified = true;
+            }
+            DecodedKey::Unicode(BACKSPACE) => {
+                if self.col > 0 {
+                    self.col -= 1;
+                    self.lines[self.row].remove(self.col);
+                    self.modified = true;
+                } else if self.row > 0 {
+                    let line = self.lines.remove(self.row);
+                    self.row -= 1;
+                    self.col = self.lines[self.row].len();
+                    self.lines[self.row].extend(line);
+                    self.modified = true;
+                }
+            }
+            DecodedKey::Unicode(DELETE) => {
+                if self.col < self.lines[self.row].len() {
+                    self.lines[self.row].remove(self.col);
+                    self.modified = true;
+                } else if self.row + 1 < self.lines.len() {
+                    let line = self.lines.remove(self.row + 1);
+                    self.lines[self.row].extend(line);
+                    self.modified = true;
+                }
+            }
+            DecodedKey::Unicode('\t') => {
+                let spaces = TAB_WIDTH - self.col % TAB_WIDTH;
+                for _ in 0..spaces {
+                    self.insert(' ');
+                }
+            }
+            DecodedKey::Unicode(character) if !character.is_control() => self.insert(character),
+            DecodedKey::RawKey(KeyCode::ArrowLeft) => {
+                if self.col > 0 {
+                    self.col -= 1;
+                } else if self.row > 0 {
+                    self.row -= 1;
+                    self.col = self.lines[self.row].len();
+                }
+            }
+            DecodedKey::RawKey(KeyCode::ArrowRight) => {
+                if self.col < self.lines[self.row].len() {
+                    self.col += 1;
+                } else if self.row + 1 < self.lines.len() {
+                    self.row += 1;
+                    self.col = 0;
+                }
+            }
+            DecodedKey::RawKey(KeyCode::ArrowUp) => self.move_to_line(self.row.saturating_sub(1)),
+            DecodedKey::RawKey(KeyCode::ArrowDown) => self.move_to_line(self.row + 1),
+            DecodedKey::RawKey(KeyCode::PageUp) => self.move_to_line(self.row.saturating_sub(TEXT_ROWS)),
+            DecodedKey::RawKey(KeyCode::PageDown) => self.move_to_line(self.row + TEXT_ROWS),
+            DecodedKey::RawKey(KeyCode::Home) => self.col = 0,
+            DecodedKey::RawKey(KeyCode::End) => self.col = self.lines[self.row].len(),
+            _ => {} // other control characters and raw keys do nothing
+        }
+        true
+    }
+
+    fn search_key(&mut self, key: DecodedKey) {
+        let query = match &mut self.mode {
+            Mode::Search(query) => query,
+            _ => return,
+        };
+        match key {
+            DecodedKey::Unicode(ESCAPE) | DecodedKey::Unicode(CTRL_W) => {
+                self.mode = Mode::Editing;
+                self.message = String::from("[ Cancelled ]");
+            }
+            DecodedKey::Unicode('\n') => {
+                if !query.is_empty() {
+                    self.last_search = core::mem::take(query);
+                }
+                self.mode = Mode::Editing;
+                self.find_next();
+            }
+            DecodedKey::Unicode(BACKSPACE) => {
+                query.pop();
+            }
+            DecodedKey::Unicode(character) if !character.is_control() => query.push(character),
+            _ => {}
+        }
+    }
+
+    fn confirm_key(&mut self, key: DecodedKey) -> bool {
+        match key {
+            DecodedKey::Unicode('y') | DecodedKey::Unicode('Y') => {
+                self.mode = Mode::Editing;
+                !self.save() // stay open if it couldn't be saved
+            }
+            DecodedKey::Unicode('n') | DecodedKey::Unicode('N') => false,
+            DecodedKey::Unicode(ESCAPE) => {
+                self.mode = Mode::Editing;
+                self.message = String::from("[ Cancelled ]");
+                true
+            }
+            _ => true,
+        }
+    }
+
+    fn insert(&mut self, character: char) {
+        self.lines[self.row].insert(self.col, character);
+        self.col += 1;
+        self.modified = true;
+    }
+
+    /// Goes to another line, staying in the same column if that line is long enough.
+    fn move_to_line(&mut self, row: usize) {
+        self.row = row.min(self.lines.len() - 1);
+        self.col = self.col.min(self.lines[self.row].len());
+    }
+
+    /// Looks for the last search after the cursor, wrapping around at the end of the file.
+    fn find_next(&mut self) {
+        if self.last_search.is_empty() {
+            self.message = String::from("[ Nothing to search for ]");
+            return;
+        }
+        let count = self.lines.len();
+        for step in 0..=count {
+            let row = (self.row + step) % count;
+            let line = &self.lines[row];
+            let (from, to) = match step {
+                0 => (self.col + 1, line.len()), // not the match the cursor is already on
+                _ if step == count => (0, (self.col + 1).min(line.len())), // wrapped around to the cursor line
+                _ => (0, line.len()),
+            };
+            if let Some(col) = (from..to).find(|&col| line[col..].starts_with(&self.last_search)) {
+                if (row, col) == (self.row, self.col) {
+                    self.message = String::from("[ This is the only occurrence ]");
+                }
+                self.row = row;
+                self.col = col;
+                return;
+            }
+        }
+        let query: String = self.last_search.iter().collect();
+        self.message = format!("[ \"{}\" not found ]", query);
+    }
+
+    /// Writes the text back to the file. Returns whether that worked.
+    fn save(&mut self) -> bool {
+        let lines: Vec<String> = self.lines.iter().map(|line| line.iter().collect()).collect();
+        let text = lines.join("\n");
+        match VFS.lock().write(&self.path, text.as_bytes()) {
+            Ok(()) => {
+                self.modified = false;
+                self.message = format!("[ Wrote {} lines ]", self.lines.len());
+                true
+            }
+            Err(error) => {
+                self.message = format!("[ Can't save: {} ]", error);
+                false
+            }
+        }
+    }
+
+    /// Scrolls so the cursor is on screen.
+    fn scroll(&mut self) {
+        if self.row < self.top {
+            self.top = self.row;
+        } else if self.row >= self.top + TEXT_ROWS {
+            self.top = self.row + 1 - TEXT_ROWS;
+        }
+        if self.col < self.left {
+            self.left = self.col;
+        } else if self.col >= self.left + BUFFER_WIDTH {
+            self.left = self.col + 1 - BUFFER_WIDTH;
+        }
+    }
+
+    fn render(&mut self) {
+        self.scroll();
+        let bar = ColorCode::new(Color::Black, Color::LightGray);
+        let text = ColorCode::new(Color::White, Color::Black);
+        let message = ColorCode::new(Color::Yellow, Color::Black);
+
+        let title = format!("  STB nano    {}{}", self.path, if self.modified { "    Modified" } else { "" });
+        let bottom = match &self.mode {
+            Mode::Search(query) => {
+                let query: String = query.iter().collect();
+                format!("Search: {}", query)
+            }
+            _ => String::from(HELP),
+        };
+
+        interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            let mut draw_row = |row: usize, chars: &mut dyn Iterator<Item = char>, color: ColorCode| {
+                for col in 0..BUFFER_WIDTH {
+                    let byte = match chars.next() {
+                        Some(character @ ' '..='~') => character as u8,
+                        Some(_) => 0xfe,
+                        None => b' ',
+                    };
+                    writer.write_char_at(row, col, byte, color);
+                }
+            };
+            draw_row(0, &mut title.chars(), bar);
+            for screen_row in 0..TEXT_ROWS {
+                match self.lines.get(self.top + screen_row) {
+                    Some(line) => draw_row(TEXT_TOP + screen_row, &mut line.iter().copied().skip(self.left), text),
+                    None => draw_row(TEXT_TOP + screen_row, &mut core::iter::empty(), text),
+                }
+            }
+            draw_row(MESSAGE_ROW, &mut self.message.chars(), message);
+            draw_row(HELP_ROW, &mut bottom.chars(), bar);
+        });
+
+        match &self.mode {
+            Mode::Search(query) => set_cursor_position(HELP_ROW, ("Search: ".len() + query.len()).min(BUFFER_WIDTH - 1)),
+            _ => set_cursor_position(TEXT_TOP + self.row - self.top, self.col - self.left),
+        }
+    }
+
+    /// Puts the shell's screen back.
+    fn close(&mut self) {
+        interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            writer.restore_screen(&self.saved_screen);
+            writer.update_cursor();
+        });
+    }
+}
@@ -0,0 +1,1059 @@
+/* FAT12/16/32, so disks made on Linux with `mkfs.vfat` and `mtools` can be read and written here.
+   Only whole-disk volumes (no partition table), which is what `mkfs.vfat disk.img` makes.
+   Long file names are read and written, new files get a `NAME~1.TXT` style short name next to their long one.
+
+   Directories are named by their first cluster, 0 means the root (that's also what `..` of a
+   top level directory says). For the VFS a node is the spot its directory entry is at, see `ino_of`. */
+
+use crate::{ata, block::{BlockDevice, BlockError, BLOCK_SIZE}, println, rtc, vfs::{self, FileSystem, FileType, Ino, Stat, VfsError, VFS}};
+use alloc::{boxed::Box, format, string::String, vec, vec::Vec};
+use core::fmt;
+
+const DIR_ENTRY_SIZE: usize = 32;
+const ENTRIES_PER_SECTOR: usize = BLOCK_SIZE / DIR_ENTRY_SIZE;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+const ENTRY_END: u8 = 0x00; // this and everything after it is unused
+const ENTRY_DELETED: u8 = 0xE5;
+const LAST_LONG_ENTRY: u8 = 0x40;
+const CHARS_PER_LONG_ENTRY: usize = 13;
+
+// flags in byte 12 of a short entry, set by Linux for names like `notes.txt` that only differ in case
+const LOWERCASE_BASE: u8 = 0x08;
+const LOWERCASE_EXT: u8 = 0x10;
+
+const FAT_DATE_1980_01_01: u16 = (1 << 5) | 1; // the first day FAT can store
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// Cluster values from here on mean "end of chain".
+    fn end_of_chain(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xFF8,
+            FatType::Fat16 => 0xFFF8,
+            FatType::Fat32 => 0x0FFF_FFF8,
+        }
+    }
+}
+
+impl fmt::Display for FatType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FatType::Fat12 => "FAT12",
+            FatType::Fat16 => "FAT16",
+            FatType::Fat32 => "FAT32",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatError {
+    Io(BlockError),
+    NotFat,
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    InvalidName,
+    NotEmpty,
+    NoSpace,
+    Corrupt,
+}
+
+impl From<BlockError> for FatError {
+    fn from(error: BlockError) -> Self {
+        FatError::Io(error)
+    }
+}
+
+impl fmt::Display for FatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            FatError::Io(error) => return write!(f, "disk error: {}", error),
+            FatError::NotFat => "not a FAT volume",
+            FatError::NotFound => "no such file or directory",
+            FatError::NotADirectory => "not a directory",
+            FatError::IsADirectory => "is a directory",
+            FatError::AlreadyExists => "already exists",
+            FatError::InvalidName => "invalid name",
+            FatError::NotEmpty => "directory not empty",
+            FatError::NoSpace => "volume is full",
+            FatError::Corrupt => "volume is corrupt",
+        };
+        f.write_str(message)
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// A file or directory on a FAT volume.
+#[derive(Debug, Clone)]
+pub struct FatNode {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u32,
+    pub cluster: u32,             // first cluster, 0 for empty files and the root
+    pub created: u32,             // FAT date in the high half, FAT time in the low half, 0 if not set
+    pub modified: u32,            // same
+    short_name: [u8; 11],
+    slot: Option<(u64, usize)>,   // sector and byte offset of the short entry, None for the root
+}
+
+impl FatNode {
+    fn root() -> Self {
+        FatNode {
+            name: String::new(),
+            is_dir: true,
+            size: 0,
+            cluster: 0,
+            created: 0,
+            modified: 0,
+            short_name: [b' '; 11],
+            slot: None,
+        }
+    }
+}
+
+pub struct FatVolume<D: BlockDevice> {
+    device: D,
+    fat_type: FatType,
+    sectors_per_cluster: u32,
+    fat_start: u64,
+    fat_sectors: u32,
+    fat_count: u32,
+    root_start: u64,       // the fixed root directory of FAT12/16
+    root_sectors: u32,
+    root_cluster: u32,     // the root directory of FAT32, which is a normal cluster chain
+    data_start: u64,
+    cluster_count: u32,
+    fs_info: Option<u64>,  // FAT32 free cluster hint sector, invalidated on the first change
+    next_free: u32,
+    label: String,
+    cache: Option<(u64, [u8; BLOCK_SIZE])>, // the last sector touched, the FAT gets read a lot
+}
+
+impl<D: BlockDevice> FatVolume<D> {
+    /// Reads the boot sector and works out what kind of FAT it is.
+    pub fn mount(mut device: D) -> Result<Self, FatError> {
+        let mut boot = [0u8; BLOCK_SIZE];
+        device.read_block(0, &mut boot)?;
+        if boot[510] != 0x55 || boot[511] != 0xAA || read_u16(&boot, 11) as usize != BLOCK_SIZE {
+            return Err(FatError::NotFat);
+        }
+        let sectors_per_cluster = boot[13] as u32;
+        let reserved = read_u16(&boot, 14) as u32;
+        let fat_count = boot[16] as u32;
+        let root_entries = read_u16(&boot, 17) as u32;
+        let total_sectors = match read_u16(&boot, 19) {
+            0 => read_u32(&boot, 32),
+            small => small as u32,
+        };
+        let fat_sectors = match read_u16(&boot, 22) {
+            0 => read_u32(&boot, 36),
+            small => small as u32,
+        };
+        if !sectors_per_cluster.is_power_of_two() || reserved == 0 || fat_count == 0 || fat_sectors == 0 {
+            return Err(FatError::NotFat);
+        }
+
+        let root_sectors = (root_entries * DIR_ENTRY_SIZE as u32 + BLOCK_SIZE as u32 - 1) / BLOCK_SIZE as u32;
+        let root_start = reserved + fat_count * fat_sectors;
+        let data_start = root_start + root_sectors;
+        if total_sectors <= data_start || total_sectors as u64 > device.block_count() {
+            return Err(FatError::NotFat);
+        }
+        let cluster_count = (total_sectors - data_start) / sectors_per_cluster;
+
+        // the cluster count alone decides the type, that's how the spec wants it
+        let fat_type = if cluster_count < 4085 {
+            FatType::Fat12
+        } else if cluster_count < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+        let (root_cluster, fs_info, label_offset) = match fat_type {
+            FatType::Fat32 => {
+                let fs_info = match read_u16(&boot, 48) {
+                    0 | 0xFFFF => None,
+                    sector => Some(sector as u64),
+                };
+                (read_u32(&boot, 44), fs_info, 71)
+            }
+            _ => (0, None, 43),
+        };
+        let label: String = String::from_utf8_lossy(&boot[label_offset..label_offset + 11]).trim_end().into();
+
+        Ok(FatVolume {
+            device,
+            fat_type,
+            sectors_per_cluster,
+            fat_start: reserved as u64,
+            fat_sectors,
+            fat_count,
+            root_start: root_start as u64,
+            root_sectors,
+            root_cluster,
+            data_start: data_start as u64,
+            cluster_count,
+            fs_info,
+            next_free: 2,
+            label,
+            cache: None,
+        })
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn into_device(self) -> D {
+        self.device
+    }
+
+    fn read_sector(&mut self, sector: u64) -> Result<[u8; BLOCK_SIZE], FatError> {
+        if let Some((cached, data)) = &self.cache {
+            if *cached == sector {
+                return Ok(*data);
+            }
+        }
+        let mut data = [0u8; BLOCK_SIZE];
+        self.device.read_block(sector, &mut data)?;
+        self.cache = Some((sector, data));
+        Ok(data)
+    }
+
+    fn write_sector(&mut self, sector: u64, data: &[u8; BLOCK_SIZE]) -> Result<(), FatError> {
+        self.device.write_block(sector, data)?;
+        self.cache = Some((sector, *data));
+        Ok(())
+    }
+
+    fn cluster_bytes(&self) -> usize {
+        self.sectors_per_cluster as usize * BLOCK_SIZE
+    }
+
+    fn cluster_sector(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster as u64 - 2) * self.sectors_per_cluster as u64
+    }
+
+    /// Reads `buf.len()` bytes of the first FAT starting at `offset`, a FAT12 entry can straddle two sectors.
+    fn read_fat_bytes(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), FatError> {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let position = offset as usize + i;
+            let sector = self.read_sector(self.fat_start + (position / BLOCK_SIZE) as u64)?;
+            *byte = sector[position % BLOCK_SIZE];
+        }
+        Ok(())
+    }
+
+    /// Writes bytes to every copy of the FAT.
+    fn write_fat_bytes(&mut self, offset: u32, bytes: &[u8]) -> Result<(), FatError> {
+        for copy in 0..self.fat_count {
+            let start = self.fat_start + (copy * self.fat_sectors) as u64;
+            for (i, byte) in bytes.iter().enumerate() {
+                let position = offset as usize + i;
+                let sector = start + (position / BLOCK_SIZE) as u64;
+                let mut data = self.read_sector(sector)?;
+                data[position % BLOCK_SIZE] = *byte;
+                self.write_sector(sector, &data)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_fat(&mut self, cluster: u32) -> Result<u32, FatError> {
+        match self.fat_type {
+            FatType::Fat12 => {
+                let mut bytes = [0u8; 2];
+                self.read_fat_bytes(cluster + cluster / 2, &mut bytes)?;
+                let value = u16::from_le_bytes(bytes) as u32;
+                Ok(if cluster & 1 == 1 { value >> 4 } else { value & 0xFFF })
+            }
+            FatType::Fat16 => {
+                let mut bytes = [0u8; 2];
+                self.read_fat_bytes(cluster * 2, &mut bytes)?;
+                Ok(u16::from_le_bytes(bytes) as u32)
+            }
+            FatType::Fat32 => {
+                let mut bytes = [0u8; 4];
+                self.read_fat_bytes(cluster * 4, &mut bytes)?;
+                Ok(u32::from_le_bytes(bytes) & 0x0FFF_FFFF)
+            }
+        }
+    }
+
+    fn write_fat(&mut self, cluster: u32, value: u32) -> Result<(), FatError> {
+        match self.fat_type {
+            FatType::Fat12 => {
+                let offset = cluster + cluster / 2;
+                let mut bytes = [0u8; 2];
+                self.read_fat_bytes(offset, &mut bytes)?;
+                let old = u16::from_le_bytes(bytes);
+                let new = if cluster & 1 == 1 {
+                    (old & 0x000F) | ((value as u16) << 4)
+                } else {
+                    (old & 0xF000) | (value as u16 & 0x0FFF)
+                };
+                self.write_fat_bytes(offset, &new.to_le_bytes())
+            }
+            FatType::Fat16 => self.write_fat_bytes(cluster * 2, &(value as u16).to_le_bytes()),
+            FatType::Fat32 => {
+                let mut bytes = [0u8; 4];
+                self.read_fat_bytes(cluster * 4, &mut bytes)?;
+                let kept = u32::from_le_bytes(bytes) & 0xF000_0000; // the top 4 bits are reserved
+                self.write_fat_bytes(cluster * 4, &(kept | (value & 0x0FFF_FFFF)).to_le_bytes())
+            }
+        }
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.cluster_count + 2
+    }
+
+    /// All clusters of a chain, in order.
+    fn chain(&mut self, start: u32) -> Result<Vec<u32>, FatError> {
+        let mut clusters = Vec::new();
+        let mut current = start;
+        while current != 0 && current < self.fat_type.end_of_chain() {
+            if !self.is_valid_cluster(current) || clusters.len() > self.cluster_count as usize {
+                return Err(FatError::Corrupt); // bad cluster or a loop
+            }
+            clusters.push(current);
+            current = self.read_fat(current)?;
+        }
+        Ok(clusters)
+    }
+
+    /// Marks a free cluster as the end of a chain and hands it out.
+    fn alloc_cluster(&mut self) -> Result<u32, FatError> {
+        self.invalidate_fs_info()?;
+        let first = self.next_free;
+        let mut cluster = first;
+        loop {
+            if self.read_fat(cluster)? == 0 {
+                self.write_fat(cluster, self.fat_type.end_of_chain() | 0x7)?;
+                self.next_free = cluster;
+                return Ok(cluster);
+            }
+            cluster += 1;
+            if cluster >= self.cluster_count + 2 {
+                cluster = 2;
+            }
+            if cluster == first {
+                return Err(FatError::NoSpace);
+            }
+        }
+    }
+
+    fn free_chain(&mut self, start: u32) -> Result<(), FatError> {
+        self.invalidate_fs_info()?;
+        for cluster in self.chain(start)? {
+            self.write_fat(cluster, 0)?;
+        }
+        Ok(())
+    }
+
+    /// The FAT32 FSInfo sector keeps a free cluster count that we don't track, mark it unknown before changing anything.
+    fn invalidate_fs_info(&mut self) -> Result<(), FatError> {
+        if let Some(sector) = self.fs_info.take() {
+            let mut data = self.read_sector(sector)?;
+            if read_u32(&data, 0) == 0x4161_5252 {
+                write_u32(&mut data, 488, 0xFFFF_FFFF);
+                write_u32(&mut data, 492, 0xFFFF_FFFF);
+                self.write_sector(sector, &data)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn zero_cluster(&mut self, cluster: u32) -> Result<(), FatError> {
+        let first = self.cluster_sector(cluster);
+        for sector in first..first + self.sectors_per_cluster as u64 {
+            self.write_sector(sector, &[0u8; BLOCK_SIZE])?;
+        }
+        Ok(())
+    }
+
+    /// The sectors a directory is stored in, in order.
+    fn dir_sectors(&mut self, dir: u32) -> Result<Vec<u64>, FatError> {
+        let dir = match dir {
+            0 if self.fat_type == FatType::Fat32 => self.root_cluster,
+            0 => return Ok((self.root_start..self.root_start + self.root_sectors as u64).collect()),
+            cluster => cluster,
+        };
+        let mut sectors = Vec::new();
+        for cluster in self.chain(dir)? {
+            let first = self.cluster_sector(cluster);
+            sectors.extend(first..first + self.sectors_per_cluster as u64);
+        }
+        Ok(sectors)
+    }
+
+    /// Every directory entry spot of a directory as (sector, byte offset), in order.
+    fn dir_slots(&mut self, dir: u32) -> Result<Vec<(u64, usize)>, FatError> {
+        let mut slots = Vec::new();
+        for sector in self.dir_sectors(dir)? {
+            slots.extend((0..ENTRIES_PER_SECTOR).map(|index| (sector, index * DIR_ENTRY_SIZE)));
+        }
+        Ok(slots)
+    }
+
+    /// The node whose short entry is at `slot`, without its name (that would mean reading the whole directory).
+    fn node_at(&mut self, slot: (u64, usize)) -> Result<FatNode, FatError> {
+        let (sector, offset) = slot;
+        let data = self.read_sector(sector)?;
+        let entry = &data[offset..offset + DIR_ENTRY_SIZE];
+        if entry[0] == ENTRY_END || entry[0] == ENTRY_DELETED || entry[11] & ATTR_LONG_NAME == ATTR_LONG_NAME {
+            return Err(FatError::NotFound); // deleted since
+        }
+        let mut short_name = [0u8; 11];
+        short_name.copy_from_slice(&entry[..11]);
+        Ok(FatNode {
+            name: String::new(),
+            is_dir: entry[11] & ATTR_DIRECTORY != 0,
+            size: read_u32(entry, 28),
+            cluster: ((read_u16(entry, 20) as u32) << 16) | read_u16(entry, 26) as u32,
+            created: read_u32(entry, 14),
+            modified: read_u32(entry, 22),
+            short_name,
+            slot: Some(slot),
+        })
+    }
+
+    /// The node for a VFS node, the other way around from `ino_of`.
+    fn node(&mut self, ino: Ino) -> Result<FatNode, FatError> {
+        if ino == ROOT_INO {
+            return Ok(FatNode::root());
+        }
+        let index = ino - 1;
+        let sector = index / ENTRIES_PER_SECTOR as u64;
+        let offset = (index % ENTRIES_PER_SECTOR as u64) as usize * DIR_ENTRY_SIZE;
+        self.node_at((sector, offset))
+    }
+
+    /// Everything in a directory except `.` and `..`.
+    pub fn read_dir(&mut self, dir: u32) -> Result<Vec<FatNode>, FatError> {
+        let mut nodes = Vec::new();
+        let mut long_name: Vec<u16> = Vec::new();
+        let mut long_checksum = None;
+
+        'sectors: for sector in self.dir_sectors(dir)? {
+            let data = self.read_sector(sector)?;
+            for (index, entry) in data.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+                match entry[0] {
+                    ENTRY_END => break 'sectors,
+                    ENTRY_DELETED => {
+                        long_name.clear();
+                        continue;
+                    }
+                    _ => {}
+                }
+                let attributes = entry[11];
+                if attributes & ATTR_LONG_NAME == ATTR_LONG_NAME {
+                    // long name parts come last part first, each one goes in front of what we have
+                    if entry[0] & LAST_LONG_ENTRY != 0 {
+                        long_name.clear();
+                    }
+                    let mut part = long_name_part(entry);
+                    part.extend_from_slice(&long_name);
+                    long_name = part;
+                    long_checksum = Some(entry[13]);
+                    continue;
+                }
+                if attributes & ATTR_VOLUME_ID != 0 || entry[0] == b'.' {
+                    long_name.clear();
+                    continue;
+                }
+
+                let mut short_name = [0u8; 11];
+                short_name.copy_from_slice(&entry[..11]);
+                let name = if !long_name.is_empty() && long_checksum == Some(checksum(&short_name)) {
+                    String::from_utf16_lossy(&long_name)
+                } else {
+                    display_short_name(&short_name, entry[12])
+                };
+                long_name.clear();
+
+                nodes.push(FatNode {
+                    name,
+                    is_dir: attributes & ATTR_DIRECTORY != 0,
+                    size: read_u32(entry, 28),
+                    cluster: ((read_u16(entry, 20) as u32) << 16) | read_u16(entry, 26) as u32,
+                    created: read_u32(entry, 14),
+                    modified: read_u32(entry, 22),
+                    short_name,
+                    slot: Some((sector, index * DIR_ENTRY_SIZE)),
+                });
+            }
+        }
+        Ok(nodes)
+    }
+
+    /// Finds `name` in a directory, FAT names don't care about case.
+    pub fn lookup(&mut self, dir: &FatNode, name: &str) -> Result<FatNode, FatError> {
+        if !dir.is_dir {
+            return Err(FatError::NotADirectory);
+        }
+        self.read_dir(dir.cluster)?
+            .into_iter()
+            .find(|node| node.name.eq_ignore_ascii_case(name))
+            .ok_or(FatError::NotFound)
+    }
+
+    /// Walks a path from the root of the volume, like `docs/notes.txt`.
+    pub fn resolve(&mut self, path: &str) -> Result<FatNode, FatError> {
+        let mut stack = vec![FatNode::root()];
+        for component in path.split('/') {
+            match component {
+                "" | "." => {}
+                ".." => {
+                    if stack.len() > 1 {
+                        stack.pop();
+                    }
+                }
+                name => {
+                    let current = stack.last().ok_or(FatError::Corrupt)?;
+                    let next = self.lookup(current, name)?;
+                    stack.push(next);
+                }
+            }
+        }
+        stack.pop().ok_or(FatError::Corrupt)
+    }
+
+    pub fn read_file(&mut self, file: &FatNode) -> Result<Vec<u8>, FatError> {
+        if file.is_dir {
+            return Err(FatError::IsADirectory);
+        }
+        let mut data = Vec::with_capacity(file.size as usize);
+        'clusters: for cluster in self.chain(file.cluster)? {
+            let first = self.cluster_sector(cluster);
+            for sector in first..first + self.sectors_per_cluster as u64 {
+                let left = file.size as usize - data.len();
+                if left == 0 {
+                    break 'clusters;
+                }
+                let block = self.read_sector(sector)?;
+                data.extend_from_slice(&block[..left.min(BLOCK_SIZE)]);
+            }
+        }
+        if data.len() != file.size as usize {
+            return Err(FatError::Corrupt); // the chain is shorter than the file
+        }
+        Ok(data)
+    }
+
+    /// Replaces the content of a file.
+    pub fn write_file(&mut self, file: &mut FatNode, data: &[u8]) -> Result<(), FatError> {
+        if file.is_dir {
+            return Err(FatError::IsADirectory);
+        }
+        if file.cluster != 0 {
+            self.free_chain(file.cluster)?;
+            file.cluster = 0;
+        }
+
+        let mut previous = None;
+        for chunk in data.chunks(self.cluster_bytes()) {
+            let cluster = self.alloc_cluster()?;
+            match previous {
+                Some(previous) => self.write_fat(previous, cluster)?,
+                None => file.cluster = cluster,
+            }
+            previous = Some(cluster);
+
+            let first = self.cluster_sector(cluster);
+            for (i, part) in chunk.chunks(BLOCK_SIZE).enumerate() {
+                let mut block = [0u8; BLOCK_SIZE];
+                block[..part.len()].copy_from_slice(part);
+                self.write_sector(first + i as u64, &block)?;
+            }
+        }
+        file.size = data.len() as u32;
+        file.modified = fat_time(rtc::now());
+        self.update_entry(file)?;
+        self.device.flush()?;
+        Ok(())
+    }
+
+    /// Writes a node's size, first cluster and modification time back to its directory entry.
+    fn update_entry(&mut self, node: &FatNode) -> Result<(), FatError> {
+        let (sector, offset) = node.slot.ok_or(FatError::InvalidName)?;
+        let mut data = self.read_sector(sector)?;
+        let entry = &mut data[offset..offset + DIR_ENTRY_SIZE];
+        write_u16(entry, 20, (node.cluster >> 16) as u16);
+        write_u16(entry, 26, node.cluster as u16);
+        write_u32(entry, 28, if node.is_dir { 0 } else { node.size });
+        write_u32(entry, 22, node.modified);
+        write_u16(entry, 18, (node.modified >> 16) as u16); // accessed, which is just a date
+        self.write_sector(sector, &data)
+    }
+
+    /// Makes an empty file or directory in `dir`.
+    pub fn create(&mut self, dir: &FatNode, name: &str, is_dir: bool) -> Result<FatNode, FatError> {
+        if !is_valid_name(name) {
+            return Err(FatError::InvalidName);
+        }
+        if !dir.is_dir {
+            return Err(FatError::NotADirectory);
+        }
+        let existing = self.read_dir(dir.cluster)?;
+        if existing.iter().any(|node| node.name.eq_ignore_ascii_case(name)) {
+            return Err(FatError::AlreadyExists);
+        }
+
+        let (short_name, case_flags, long_name) = match plain_short_name(name) {
+            Some((short_name, case_flags)) => (short_name, case_flags, Vec::new()),
+            None => (
+                generated_short_name(name, &existing).ok_or(FatError::InvalidName)?,
+                0,
+                name.encode_utf16().collect(),
+            ),
+        };
+        let long_entries = (long_name.len() + CHARS_PER_LONG_ENTRY - 1) / CHARS_PER_LONG_ENTRY;
+        let slots = self.free_slots(dir.cluster, long_entries + 1)?;
+
+        let now = fat_time(rtc::now());
+        let cluster = if is_dir {
+            let cluster = self.alloc_cluster()?;
+            self.zero_cluster(cluster)?;
+            let mut data = [0u8; BLOCK_SIZE];
+            encode_short_entry(&mut data[..DIR_ENTRY_SIZE], b".          ", ATTR_DIRECTORY, cluster, now);
+            let dot_dot = &mut data[DIR_ENTRY_SIZE..2 * DIR_ENTRY_SIZE];
+            encode_short_entry(dot_dot, b"..         ", ATTR_DIRECTORY, dir.cluster, now);
+            self.write_sector(self.cluster_sector(cluster), &data)?;
+            cluster
+        } else {
+            0
+        };
+
+        let sum = checksum(&short_name);
+        for (i, &(sector, offset)) in slots[..long_entries].iter().enumerate() {
+            let sequence = (long_entries - i) as u8;
+            let mut data = self.read_sector(sector)?;
+            encode_long_entry(&mut data[offset..offset + DIR_ENTRY_SIZE], &long_name, sequence, i == 0, sum);
+            self.write_sector(sector, &data)?;
+        }
+        let (sector, offset) = slots[long_entries];
+        let mut data = self.read_sector(sector)?;
+        let attributes = if is_dir { ATTR_DIRECTORY } else { ATTR_ARCHIVE };
+        encode_short_entry(&mut data[offset..offset + DIR_ENTRY_SIZE], &short_name, attributes, cluster, now);
+        data[offset + 12] = case_flags;
+        self.write_sector(sector, &data)?;
+        self.device.flush()?;
+
+        Ok(FatNode {
+            name: String::from(name),
+            is_dir,
+            size: 0,
+            cluster,
+            created: now,
+            modified: now,
+            short_name,
+            slot: Some((sector, offset)),
+        })
+    }
+
+    /// Deletes a file or an empty directory from `dir`.
+    pub fn remove(&mut self, dir: &FatNode, name: &str) -> Result<(), FatError> {
+        let node = self.lookup(dir, name)?;
+        if node.is_dir && !self.read_dir(node.cluster)?.is_empty() {
+            return Err(FatError::NotEmpty);
+        }
+        let slot = node.slot.ok_or(FatError::InvalidName)?;
+
+        // its long name entries are right in front of the short one and go too
+        let slots = self.dir_slots(dir.cluster)?;
+        let index = slots.iter().position(|&other| other == slot).ok_or(FatError::Corrupt)?;
+        let sum = checksum(&node.short_name);
+        let mut first = index;
+        while first > 0 {
+            let (sector, offset) = slots[first - 1];
+            let data = self.read_sector(sector)?;
+            let is_ours = data[offset + 11] == ATTR_LONG_NAME && data[offset + 13] == sum && data[offset] != ENTRY_DELETED;
+            if !is_ours {
+                break;
+            }
+            first -= 1;
+        }
+        for &(sector, offset) in &slots[first..=index] {
+            let mut data = self.read_sector(sector)?;
+            data[offset] = ENTRY_DELETED;
+            self.write_sector(sector, &data)?;
+        }
+
+        if node.cluster != 0 {
+            self.free_chain(node.cluster)?;
+        }
+        self.device.flush()?;
+        Ok(())
+    }
+
+    /// Finds `count` unused entries in a row in a directory, growing it if there's no room.
+    fn free_slots(&mut self, dir: u32, count: usize) -> Result<Vec<(u64, usize)>, FatError> {
+        loop {
+            let mut run = Vec::new();
+            for sector in self.dir_sectors(dir)? {
+                let data = self.read_sector(sector)?;
+                for index in 0..ENTRIES_PER_SECTOR {
+                    match data[index * DIR_ENTRY_SIZE] {
+                        ENTRY_END | ENTRY_DELETED => run.push((sector, index * DIR_ENTRY_SIZE)),
+                        _ => run.clear(),
+                    }
+                    if run.len() == count {
+                        return Ok(run);
+                    }
+                }
+            }
+
+            // out of room, the fixed FAT12/16 root can't grow but everything else can get another cluster
+            let is_fixed_root = dir == 0 && self.fat_type != FatType::Fat32;
+            if is_fixed_root {
+                return Err(FatError::NoSpace);
+            }
+            let start = if dir == 0 { self.root_cluster } else { dir };
+            let last = *self.chain(start)?.last().ok_or(FatError::Corrupt)?;
+            let cluster = self.alloc_cluster()?;
+            self.zero_cluster(cluster)?;
+            self.write_fat(last, cluster)?;
+        }
+    }
+}
+
+/// The (up to) 13 characters stored in one long name entry.
+fn long_name_part(entry: &[u8]) -> Vec<u16> {
+    let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+    offsets
+        .iter()
+        .map(|&offset| read_u16(entry, offset))
+        .take_while(|&character| character != 0x0000 && character != 0xFFFF)
+        .collect()
+}
+
+fn encode_long_entry(entry: &mut [u8], name: &[u16], sequence: u8, last: bool, checksum: u8) {
+    let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+    let start = (sequence as usize - 1) * CHARS_PER_LONG_ENTRY;
+    for (i, &offset) in offsets.iter().enumerate() {
+        // the name ends with a 0 if there's room for it, the rest is padded with 0xFFFF
+        let character = match name.get(start + i) {
+            Some(&character) => character,
+            None if start + i == name.len() => 0x0000,
+            None => 0xFFFF,
+        };
+        write_u16(entry, offset, character);
+    }
+    entry[0] = sequence | if last { LAST_LONG_ENTRY } else { 0 };
+    entry[11] = ATTR_LONG_NAME;
+    entry[12] = 0;
+    entry[13] = checksum;
+    write_u16(entry, 26, 0);
+}
+
+/// A new, empty short entry made at `time` (a FAT date and time, see `fat_time`).
+fn encode_short_entry(entry: &mut [u8], name: &[u8; 11], attributes: u8, cluster: u32, time: u32) {
+    for byte in entry.iter_mut() {
+        *byte = 0;
+    }
+    entry[..11].copy_from_slice(name);
+    entry[11] = attributes;
+    write_u32(entry, 14, time); // created
+    write_u16(entry, 18, (time >> 16) as u16); // accessed
+    write_u16(entry, 20, (cluster >> 16) as u16);
+    write_u32(entry, 22, time); // modified
+    write_u16(entry, 26, cluster as u16);
+}
+
+/// The checksum of a short name that every long name entry belonging to it carries.
+fn checksum(short_name: &[u8; 11]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &byte| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(byte))
+}
+
+/// `NOTES   TXT` -> `NOTES.TXT` (or `notes.txt` when the case flags say so).
+fn display_short_name(short_name: &[u8; 11], flags: u8) -> String {
+    let mut base = short_name[..8].to_vec();
+    if base[0] == 0x05 {
+        base[0] = ENTRY_DELETED; // 0xE5 is a real first character that had to be escaped
+    }
+    let convert = |bytes: &[u8], lower: bool| -> String {
+        let text = String::from_utf8_lossy(bytes);
+        let text = text.trim_end();
+        if lower {
+            text.to_ascii_lowercase()
+        } else {
+            String::from(text)
+        }
+    };
+    let base = convert(&base, flags & LOWERCASE_BASE != 0);
+    let ext = convert(&short_name[8..], flags & LOWERCASE_EXT != 0);
+    if ext.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, ext)
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.encode_utf16().count() <= 255
+        && !name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c))
+}
+
+fn is_short_name_char(c: char) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || "!#$%&'()-@^_`{}~".contains(c)
+}
+
+/// The short name (and case flags) for names that already are a valid 8.3 name, those don't need a long name.
+/// Like Linux, `notes.txt` counts too, as long as each part is all upper or all lower case.
+fn plain_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, ""),
+    };
+    let fits = !base.is_empty() && base.len() <= 8 && ext.len() <= 3 && !name.ends_with('.');
+    if !fits || !base.chars().chain(ext.chars()).all(|c| is_short_name_char(c.to_ascii_uppercase())) {
+        return None;
+    }
+    let case_flag = |part: &str, flag: u8| -> Option<u8> {
+        if part.chars().all(|c| !c.is_ascii_lowercase()) {
+            Some(0)
+        } else if part.chars().all(|c| !c.is_ascii_uppercase()) {
+            Some(flag)
+        } else {
+            None // mixed case needs a long name to keep it
+        }
+    };
+    let case_flags = case_flag(base, LOWERCASE_BASE)? | case_flag(ext, LOWERCASE_EXT)?;
+
+    let mut short_name = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(base.to_ascii_uppercase().as_bytes());
+    short_name[8..8 + ext.len()].copy_from_slice(ext.to_ascii_uppercase().as_bytes());
+    Some((short_name, case_flags))
+}
+
+/// Makes up a `NOTES~1.TXT` style short name that doesn't clash with anything in the directory.
+fn generated_short_name(name: &str, existing: &[FatNode]) -> Option<[u8; 11]> {
+    let clean = |text: &str| -> Vec<u8> {
+        text.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| c.to_ascii_uppercase())
+            .map(|c| if is_short_name_char(c) { c as u8 } else { b'_' })
+            .collect()
+    };
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) if dot > 0 => (clean(&name[..dot]), clean(&name[dot + 1..])),
+        _ => (clean(name), Vec::new()),
+    };
+
+    for number in 1..=999_999u32 {
+        let tail = format!("~{}", number);
+        let keep = base.len().min(8 - tail.len()).max(1);
+        let mut short_name = [b' '; 11];
+        let base = if base.is_empty() { &b"_"[..] } else { &base[..keep.min(base.len())] };
+        short_name[..base.len()].copy_from_slice(base);
+        short_name[base.len()..base.len() + tail.len()].copy_from_slice(tail.as_bytes());
+        let ext_len = ext.len().min(3);
+        short_name[8..8 + ext_len].copy_from_slice(&ext[..ext_len]);
+        if !existing.iter().any(|node| node.short_name == short_name) {
+            return Some(short_name);
+        }
+    }
+    None
+}
+
+const ROOT_INO: Ino = 0;
+
+/// The VFS node for a FAT node, made from where its directory entry is so it stays the same between calls.
+fn ino_of(node: &FatNode) -> Ino {
+    match node.slot {
+        Some((sector, offset)) => sector * ENTRIES_PER_SECTOR as u64 + (offset / DIR_ENTRY_SIZE) as u64 + 1,
+        None => ROOT_INO,
+    }
+}
+
+impl<D: BlockDevice + Send> FileSystem for FatVolume<D> {
+    fn fs_type(&self) -> &'static str {
+        "fat"
+    }
+
+    fn root(&self) -> Ino {
+        ROOT_INO
+    }
+
+    fn lookup(&mut self, dir: Ino, name: &str) -> Result<Ino, VfsError> {
+        let dir = self.node(dir)?;
+        Ok(ino_of(&FatVolume::lookup(self, &dir, name)?))
+    }
+
+    fn stat(&mut self, node: Ino) -> Result<Stat, VfsError> {
+        let node = self.node(node)?;
+        Ok(Stat {
+            kind: if node.is_dir { FileType::Directory } else { FileType::File },
+            size: if node.is_dir { 0 } else { node.size as usize },
+            mode: if node.is_dir { vfs::DEFAULT_DIR_MODE } else { vfs::DEFAULT_FILE_MODE }, // FAT has no permissions
+            owner: 0,
+            created: unix_time(node.created),
+            modified: unix_time(node.modified),
+        })
+    }
+
+    fn read(&mut self, file: Ino) -> Result<Vec<u8>, VfsError> {
+        let file = self.node(file)?;
+        Ok(self.read_file(&file)?)
+    }
+
+    fn write(&mut self, file: Ino, data: &[u8]) -> Result<(), VfsError> {
+        let mut file = self.node(file)?;
+        Ok(self.write_file(&mut file, data)?)
+    }
+
+    fn readdir(&mut self, dir: Ino) -> Result<Vec<vfs::DirEntry>, VfsError> {
+        let dir = self.node(dir)?;
+        if !dir.is_dir {
+            return Err(VfsError::NotADirectory);
+        }
+        Ok(self
+            .read_dir(dir.cluster)?
+            .into_iter()
+            .map(|node| vfs::DirEntry {
+                name: node.name,
+                kind: if node.is_dir { FileType::Directory } else { FileType::File },
+            })
+            .collect())
+    }
+
+    fn create(&mut self, dir: Ino, name: &str) -> Result<Ino, VfsError> {
+        let dir = self.node(dir)?;
+        Ok(ino_of(&FatVolume::create(self, &dir, name, false)?))
+    }
+
+    fn mkdir(&mut self, dir: Ino, name: &str) -> Result<Ino, VfsError> {
+        let dir = self.node(dir)?;
+        Ok(ino_of(&FatVolume::create(self, &dir, name, true)?))
+    }
+
+    fn unlink(&mut self, dir: Ino, name: &str) -> Result<(), VfsError> {
+        let dir = self.node(dir)?;
+        Ok(self.remove(&dir, name)?)
+    }
+
+    fn rename(&mut self, _dir: Ino, _name: &str, _new_dir: Ino, _new_name: &str) -> Result<(), VfsError> {
+        Err(VfsError::Unsupported) // the VFS copies and removes instead
+    }
+}
+
+/// A date and time as FAT stores it, the date in the high half and the time (in 2 second steps) in the low half.
+/// FAT only has the years 1980 to 2107, anything earlier is the start of 1980.
+fn fat_time(date_time: rtc::DateTime) -> u32 {
+    if date_time.year < 1980 {
+        return (FAT_DATE_1980_01_01 as u32) << 16;
+    }
+    let year = (date_time.year - 1980).min(127);
+    let date = (year << 9) | (date_time.month as u16) << 5 | date_time.day as u16;
+    let time = (date_time.hour as u16) << 11 | (date_time.minute as u16) << 5 | (date_time.second / 2) as u16;
+    (date as u32) << 16 | time as u32
+}
+
+/// A FAT date and time (date in the high half) as unix seconds, 0 stays 0.
+fn unix_time(stamp: u32) -> u64 {
+    let (date, time) = ((stamp >> 16) as u16, stamp as u16);
+    if date == 0 {
+        return 0;
+    }
+    let date_time = rtc::DateTime {
+        year: 1980 + (date >> 9),
+        month: ((date >> 5) & 0x0F) as u8,
+        day: (date & 0x1F) as u8,
+        hour: (time >> 11) as u8,
+        minute: ((time >> 5) & 0x3F) as u8,
+        second: ((time & 0x1F) * 2) as u8,
+    };
+    date_time.unix_time()
+}
+
+/// Whether a disk has a FAT volume on it.
+pub fn is_fat(device: &mut dyn BlockDevice) -> bool {
+    let mut boot = [0u8; BLOCK_SIZE];
+    if device.read_block(0, &mut boot).is_err() {
+        return false;
+    }
+    let file_system = match read_u16(&boot, 22) {
+        0 => &boot[82..85], // FAT32 keeps its "FAT32   " further in
+        _ => &boot[54..57],
+    };
+    boot[510] == 0x55 && boot[511] == 0xAA && file_system == b"FAT"
+}
+
+/// Mounts every FAT disk at boot, the first one at `$/fat`, then `$/fat2` and so on.
+pub fn mount_volumes() {
+    let mut count = 0;
+    for mut drive in ata::data_disks() {
+        if !is_fat(&mut drive) {
+            continue;
+        }
+        let (name, model) = (drive.name(), String::from(drive.model()));
+        let volume = match FatVolume::mount(drive) {
+            Ok(volume) => volume,
+            Err(error) => {
+                println!("FAT: can't mount disk '{}': {}", model, error);
+                continue;
+            }
+        };
+
+        count += 1;
+        let path = match count {
+            1 => String::from("$/fat"),
+            n => format!("$/fat{}", n),
+        };
+        let (label, fat_type) = (String::from(volume.label()), volume.fat_type());
+        match VFS.lock().mount(&path, name, Box::new(volume)) {
+            Ok(()) => println!("FAT: mounted '{}' ({}) at {}", label, fat_type, path),
+            Err(error) => println!("FAT: can't mount disk '{}' at {}: {}", model, path, error),
+        }
+    }
+}
+
+#[test_case]
+fn test_fat_time() {
+    let date = rtc::DateTime { year: 2024, month: 2, day: 29, hour: 13, minute: 45, second: 7 };
+    let stamp = fat_time(date);
+    assert_eq!(stamp >> 16, (44 << 9) | (2 << 5) | 29);
+    assert_eq!(unix_time(stamp), date.unix_time() - 1); // seconds only come in steps of 2
+    let early = rtc::DateTime { year: 1970, month: 1, day: 1, hour: 0, minute: 0, second: 0 };
+    assert_eq!(unix_time(fat_time(early)), 315_532_800); // 1980-01-01
+}
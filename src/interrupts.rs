@@ -9,14 +9,24 @@ use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 use crate::println;
 use crate::print;
 use crate::gdt;
+use crate::thread;
+use crate::process::{self, Fault};
+use x86_64::PrivilegeLevel;
 use pic8259::ChainedPics;
 use crate::vga_buffer;
 use spin;
 use lazy_static::lazy_static;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
+// how often each interrupt fired since boot, for /proc/interrupts (the timer ones are the ticks in pit.rs)
+static KEYBOARD_COUNT: AtomicU64 = AtomicU64::new(0);
+static BREAKPOINT_COUNT: AtomicU64 = AtomicU64::new(0);
+static PAGE_FAULT_COUNT: AtomicU64 = AtomicU64::new(0);
+static SERIAL_COUNT: AtomicU64 = AtomicU64::new(0);
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
@@ -26,13 +36,26 @@ lazy_static! {
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
-        idt[InterruptIndex::Timer.as_usize()]
-           .set_handler_fn(timer_interrupt_handler);
+        unsafe {
+            // the timer switches threads, so it goes through the entry in thread.rs that saves every register
+            idt[InterruptIndex::Timer.as_usize()].set_handler_addr(thread::timer_entry());
+            idt[thread::YIELD_VECTOR as usize].set_handler_addr(thread::yield_entry());
+            // the one vector ring 3 may use, every other `int` from there is a general protection fault
+            idt[process::SYSCALL_VECTOR as usize]
+                .set_handler_addr(process::syscall_entry())
+                .set_privilege_level(PrivilegeLevel::Ring3);
+        }
 
         idt[InterruptIndex::Keyboard.as_usize()]
            .set_handler_fn(keyboard_interrupt_handler);
 
+        idt[InterruptIndex::Serial.as_usize()]
+           .set_handler_fn(serial_interrupt_handler);
+
         idt.page_fault.set_handler_fn(page_fault_handler);
+        idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.divide_error.set_handler_fn(divide_error_handler);
 
         idt
     };
@@ -45,12 +68,24 @@ pub static PICS: spin::Mutex<ChainedPics> =
     IDT.load();
 }
 
+/// (name, count) for every interrupt we handle.
+pub fn counts() -> [(&'static str, u64); 5] {
+    [
+        ("timer", crate::pit::ticks()),
+        ("keyboard", KEYBOARD_COUNT.load(Ordering::Relaxed)),
+        ("serial", SERIAL_COUNT.load(Ordering::Relaxed)),
+        ("breakpoint", BREAKPOINT_COUNT.load(Ordering::Relaxed)),
+        ("page fault", PAGE_FAULT_COUNT.load(Ordering::Relaxed)),
+    ]
+}
+
 extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, _error_code: u64) -> ! {
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
 
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    BREAKPOINT_COUNT.fetch_add(1, Ordering::Relaxed);
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
@@ -59,6 +94,10 @@ use crate::hlt_loop;
 
 extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame,error_code: PageFaultErrorCode,) {
     use x86_64::registers::control::Cr2;
+    PAGE_FAULT_COUNT.fetch_add(1, Ordering::Relaxed);
+    if from_user_mode(&stack_frame) {
+        process::fault(Fault::PageFault(Cr2::read()));
+    }
 
     println!("EXCEPTION: PAGE FAULT");
     println!("Accessed Address: {:?}", Cr2::read());
@@ -67,10 +106,38 @@ extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame,er
     hlt_loop();
 }
 
+/// Whether the exception came from a user program, which only ends that program.
+fn from_user_mode(stack_frame: &InterruptStackFrame) -> bool {
+    stack_frame.code_segment & 3 == 3
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    if from_user_mode(&stack_frame) {
+        process::fault(Fault::GeneralProtection);
+    }
+    panic!("EXCEPTION: GENERAL PROTECTION FAULT ({:#x})\n{:#?}", error_code, stack_frame);
+}
 
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    if from_user_mode(&stack_frame) {
+        process::fault(Fault::InvalidOpcode);
+    }
+    panic!("EXCEPTION: INVALID OPCODE\n{:#?}", stack_frame);
+}
 
-extern "x86-interrupt" fn timer_interrupt_handler( _stack_frame: InterruptStackFrame) {
+extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+    if from_user_mode(&stack_frame) {
+        process::fault(Fault::DivideError);
+    }
+    panic!("EXCEPTION: DIVIDE ERROR\n{:#?}", stack_frame);
+}
+
+
+
+/// The timer interrupt's own work, thread.rs calls this before it decides whether to switch threads.
+pub(crate) fn timer_tick() {
     // print!(".");
+    crate::pit::tick();
 
     unsafe {
         PICS.lock()
@@ -112,6 +179,7 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     let mut keyboard = KEYBOARD.lock();
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
+    KEYBOARD_COUNT.fetch_add(1, Ordering::Relaxed);
     crate::task::keyboard::add_scancode(scancode);
 
     unsafe {
@@ -120,11 +188,25 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     }
 }
 
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    SERIAL_COUNT.fetch_add(1, Ordering::Relaxed);
+    // the UART has a FIFO, take everything that's there
+    while let Some(byte) = crate::serial::try_receive() {
+        crate::task::serial::add_byte(byte);
+    }
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Serial.as_u8());
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Serial = PIC_1_OFFSET + 4, // COM1 is IRQ4
 }
 
 impl InterruptIndex {
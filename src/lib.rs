@@ -9,7 +9,11 @@
 #![reexport_test_harness_main = "test_main"]
 #![feature(const_mut_refs)]
 
+use bootloader::BootInfo;
 use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+#[cfg(test)]
+use bootloader::entry_point;
 
 pub mod task;
 pub mod interrupts;
@@ -20,14 +24,30 @@ pub mod memory;
 pub mod allocator;
 pub mod stbfs;
 pub mod getcpu;
+pub mod commands;
+pub mod line_editor;
+pub mod ata;
+pub mod fat;
+pub mod vfs;
+pub mod procfs;
+pub mod rtc;
+pub mod pit;
+pub mod acpi;
+pub mod thread;
+pub mod process;
+pub mod editor;
 
 extern crate alloc;
 
+pub use stbfs_core::block;
+
 pub fn init() { // this is the initialization of everything
     // shell::init_shell(); LMFAO this was me trying to add commands back when i was stupid
     interrupts::init_idt();
     gdt::init();
     unsafe { interrupts::PICS.lock().initialize()};
+    pit::init(); // a timer tick every millisecond
+    serial::init(); // COM1 input for the serial shell
     x86_64::instructions::interrupts::enable();
 }
 pub trait Testable {
@@ -83,16 +103,24 @@ pub fn exit_qemu(exit_code: QemuExitCode) {
     }
 }
 
-#[cfg(test)]
-use bootloader::{entry_point, BootInfo};
+/// Paging, the heap and the frame allocator, from what the bootloader hands over. main.rs does this itself, the
+/// tests that need a heap call this after `init`.
+pub fn init_memory(boot_info: &'static BootInfo) {
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::set_frame_allocator(frame_allocator);
+}
 
-// #[cfg(test)]
-// entry_point!(test_kernel_main);
+#[cfg(test)]
+entry_point!(test_kernel_main);
 
-// Entry point for `cargo xtest`
+// Entry point for `cargo test --lib`, the unit tests in the modules
 #[cfg(test)]
-fn kernel_main(boot_info: &'static BootInfo) -> ! {
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
     init();
+    init_memory(boot_info);
     test_main();
     hlt_loop();
 }
@@ -0,0 +1,314 @@
+/* The line editor for the shell. It keeps the line that's being typed plus a cursor into it, redraws the
+   bottom row of the screen on every change and remembers the commands you ran so Up/Down can bring them back.
+
+   Keys:  Left/Right, Home/End, Ctrl+A/Ctrl+E   move the cursor
+          Backspace/Delete                      delete before/under the cursor
+          Ctrl+U                                delete everything before the cursor
+          Ctrl+W                                delete the word before the cursor
+          Up/Down                               walk through the history
+          Tab                                   complete the command or file name  */
+
+use crate::{commands::Shell, println, vga_buffer::{set_cursor_position, BUFFER_HEIGHT, BUFFER_WIDTH, WRITER}};
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+use pc_keyboard::{DecodedKey, KeyCode};
+use x86_64::instructions::interrupts;
+
+const HISTORY_SIZE: usize = 32;
+
+const CTRL_A: char = '\u{1}';
+const CTRL_E: char = '\u{5}';
+const CTRL_U: char = '\u{15}';
+const CTRL_W: char = '\u{17}';
+const BACKSPACE: char = '\u{8}';
+const DELETE: char = '\u{7f}';
+
+/// Ring buffer of the last `HISTORY_SIZE` command lines, oldest first.
+pub struct History {
+    entries: VecDeque<String>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new()
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            entries: VecDeque::with_capacity(HISTORY_SIZE),
+        }
+    }
+
+    /// Remembers a line, skipping empty lines and repeats of the last one.
+    pub fn push(&mut self, line: &str) {
+        if line.trim().is_empty() || self.entries.back().map(|last| last.as_str()) == Some(line) {
+            return;
+        }
+        if self.entries.len() == HISTORY_SIZE {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(String::from(line));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(|entry| entry.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|entry| entry.as_str())
+    }
+}
+
+pub struct LineEditor {
+    prompt: String,
+    buffer: Vec<char>,
+    cursor: usize,                // index into `buffer` the next character is inserted at
+    origin: usize,                // screen column the line starts at
+    scroll: usize,                // first character of `buffer` that's on screen
+    history: History,
+    history_index: Option<usize>, // which history entry is shown, None = the line being typed
+    draft: Vec<char>,             // the line being typed, saved while browsing the history
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        LineEditor::new()
+    }
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        LineEditor {
+            prompt: String::new(),
+            buffer: Vec::new(),
+            cursor: 0,
+            origin: 0,
+            scroll: 0,
+            history: History::new(),
+            history_index: None,
+            draft: Vec::new(),
+        }
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Prints the prompt and starts editing a new line after it.
+    pub fn start(&mut self, prompt: &str) {
+        self.prompt = String::from(prompt);
+        self.buffer.clear();
+        self.cursor = 0;
+        self.scroll = 0;
+        self.history_index = None;
+        self.show_prompt();
+    }
+
+    fn show_prompt(&mut self) {
+        let prompt = &self.prompt;
+        self.origin = interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            writer.write_string(prompt);
+            if writer.column_position > BUFFER_WIDTH - 16 {
+                writer.write_byte(b'\n'); // not enough room left on this row to type anything useful
+            }
+            writer.column_position
+        });
+        self.render();
+    }
+
+    /// Feeds one key to the editor. Returns the finished line when Enter is pressed.
+    ///
+    /// `shell` is the shell the line is for, Tab asks it what can be completed.
+    pub fn handle_key(&mut self, key: DecodedKey, shell: &Shell) -> Option<String> {
+        match key {
+            DecodedKey::Unicode('\n') => return Some(self.submit()),
+            DecodedKey::Unicode(BACKSPACE) => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.buffer.remove(self.cursor);
+                }
+            }
+            DecodedKey::Unicode(DELETE) => {
+                if self.cursor < self.buffer.len() {
+                    self.buffer.remove(self.cursor);
+                }
+            }
+            DecodedKey::Unicode(CTRL_A) | DecodedKey::RawKey(KeyCode::Home) => self.cursor = 0,
+            DecodedKey::Unicode(CTRL_E) | DecodedKey::RawKey(KeyCode::End) => self.cursor = self.buffer.len(),
+            DecodedKey::Unicode(CTRL_U) => {
+                self.buffer.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            DecodedKey::Unicode(CTRL_W) => self.delete_word(),
+            DecodedKey::Unicode('\t') => self.complete(shell),
+            DecodedKey::Unicode(character) if !character.is_control() => {
+                self.buffer.insert(self.cursor, character);
+                self.cursor += 1;
+            }
+            DecodedKey::RawKey(KeyCode::ArrowLeft) => self.cursor = self.cursor.saturating_sub(1),
+            DecodedKey::RawKey(KeyCode::ArrowRight) => self.cursor = (self.cursor + 1).min(self.buffer.len()),
+            DecodedKey::RawKey(KeyCode::ArrowUp) => self.history_up(),
+            DecodedKey::RawKey(KeyCode::ArrowDown) => self.history_down(),
+            _ => return None, // other control characters and raw keys do nothing
+        }
+        self.render();
+        None
+    }
+
+    fn submit(&mut self) -> String {
+        let line: String = self.buffer.iter().collect();
+        self.history.push(&line);
+
+        // show the whole line one last time, then move on to the next row
+        self.cursor = self.buffer.len();
+        self.render();
+        interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            writer.write_byte(b'\n');
+            writer.update_cursor();
+        });
+        line
+    }
+
+    fn delete_word(&mut self) {
+        let mut start = self.cursor;
+        while start > 0 && self.buffer[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.buffer[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.buffer.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    /// Completes the word in front of the cursor. If there's more than one way to
+    /// complete it, fills in what all of them share or lists them when that's nothing.
+    fn complete(&mut self, shell: &Shell) {
+        let before_cursor: String = self.buffer[..self.cursor].iter().collect();
+        let completion = shell.complete(&before_cursor);
+        let typed = self.cursor - completion.start;
+
+        match completion.candidates.as_slice() {
+            [] => {}
+            [only] => {
+                let mut text = only.clone();
+                if !text.ends_with('/') {
+                    text.push(' '); // done with this word, directories stay open for the next path part
+                }
+                self.replace_word(completion.start, &text);
+            }
+            candidates => {
+                let common = common_prefix(candidates);
+                if common.chars().count() > typed {
+                    self.replace_word(completion.start, &common);
+                } else {
+                    self.list_candidates(candidates);
+                }
+            }
+        }
+    }
+
+    fn replace_word(&mut self, start: usize, text: &str) {
+        self.buffer.splice(start..self.cursor, text.chars());
+        self.cursor = start + text.chars().count();
+    }
+
+    fn list_candidates(&mut self, candidates: &[String]) {
+        println!();
+        let names: Vec<&str> = candidates.iter().map(|candidate| display_name(candidate)).collect();
+        println!("{}", names.join("  "));
+        self.show_prompt();
+    }
+
+    fn history_up(&mut self) {
+        let index = match self.history_index {
+            Some(0) => return, // already at the oldest entry
+            Some(index) => index - 1,
+            None if self.history.is_empty() => return,
+            None => {
+                self.draft = core::mem::take(&mut self.buffer);
+                self.history.len() - 1
+            }
+        };
+        self.show_history_entry(index);
+    }
+
+    fn history_down(&mut self) {
+        match self.history_index {
+            None => {}
+            Some(index) if index + 1 < self.history.len() => self.show_history_entry(index + 1),
+            Some(_) => {
+                // walked past the newest entry, bring back what was being typed
+                self.history_index = None;
+                self.buffer = core::mem::take(&mut self.draft);
+                self.cursor = self.buffer.len();
+            }
+        }
+    }
+
+    fn show_history_entry(&mut self, index: usize) {
+        self.history_index = Some(index);
+        self.buffer = self.history.get(index).unwrap_or("").chars().collect();
+        self.cursor = self.buffer.len();
+    }
+
+    /// Redraws the line on the bottom row. Lines longer than the row scroll sideways to keep the cursor visible.
+    fn render(&mut self) {
+        let width = BUFFER_WIDTH - self.origin - 1; // keep the last column free for the cursor
+        if self.cursor < self.scroll {
+            self.scroll = self.cursor;
+        } else if self.cursor > self.scroll + width {
+            self.scroll = self.cursor - width;
+        }
+
+        let row = BUFFER_HEIGHT - 1;
+        let visible = self.buffer.iter().skip(self.scroll).take(width);
+        interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            let mut col = self.origin;
+            for &character in visible {
+                let byte = match character {
+                    ' '..='~' => character as u8,
+                    _ => 0xfe,
+                };
+                writer.write_byte_at(row * BUFFER_WIDTH + col, byte);
+                col += 1;
+            }
+            writer.column_position = col;
+            for col in col..BUFFER_WIDTH {
+                writer.clear_character(row, col);
+            }
+        });
+        set_cursor_position(row, self.origin + self.cursor - self.scroll);
+    }
+}
+
+/// The longest string every candidate starts with.
+fn common_prefix(candidates: &[String]) -> String {
+    let mut prefix: Vec<char> = candidates[0].chars().collect();
+    for candidate in &candidates[1..] {
+        let shared = prefix.iter().zip(candidate.chars()).take_while(|(a, b)| **a == *b).count();
+        prefix.truncate(shared);
+    }
+    prefix.into_iter().collect()
+}
+
+/// What to show for a candidate in the list, just the last part of a path.
+fn display_name(candidate: &str) -> &str {
+    let trimmed = candidate.trim_end_matches('/');
+    match trimmed.rfind('/') {
+        Some(slash) => &candidate[slash + 1..],
+        None => candidate,
+    }
+}
@@ -1,9 +1,11 @@
 /* Yeah Memory type stuff, not gonna write much up here cause i have wrote some stuff down there */
 
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use conquer_once::spin::OnceCell;
+use spin::Mutex;
 use x86_64::{
     structures::paging::{
-        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
     },
     PhysAddr, VirtAddr,
 };
@@ -15,10 +17,19 @@ use x86_64::{
 /// `physical_memory_offset`. Also, this function must be only called once
 /// to avoid aliasing `&mut` references (which is undefined behavior).
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    let _ = PHYSICAL_MEMORY_OFFSET.try_init_once(|| physical_memory_offset);
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
 
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+
+/// Where a physical address can be reached, the bootloader maps all of physical memory at an offset. None before
+/// `init`.
+pub fn phys_to_virt(address: PhysAddr) -> Option<VirtAddr> {
+    PHYSICAL_MEMORY_OFFSET.get().map(|offset| *offset + address.as_u64())
+}
+
 /// Returns a mutable reference to the active level 4 table.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -64,10 +75,18 @@ unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
     }
 }
 
+static MEMORY_MAP: OnceCell<&'static MemoryMap> = OnceCell::uninit();
+
+/// The memory map the bootloader gave us, once the frame allocator has been set up.
+pub fn memory_map() -> Option<&'static MemoryMap> {
+    MEMORY_MAP.get().copied()
+}
+
 /// A FrameAllocator that returns usable frames from the bootloader's memory map.
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
     next: usize,
+    free: Option<PhysFrame>, // frames given back, each one holds the address of the next one in its first 8 bytes
 }
 
 impl BootInfoFrameAllocator {
@@ -77,9 +96,11 @@ impl BootInfoFrameAllocator {
     /// memory map is valid. The main requirement is that all frames that are marked
     /// as `USABLE` in it are really unused.
     pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
+        let _ = MEMORY_MAP.try_init_once(|| memory_map);
         BootInfoFrameAllocator {
             memory_map,
             next: 0,
+            free: None,
         }
     }
 
@@ -99,8 +120,50 @@ impl BootInfoFrameAllocator {
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if let Some(frame) = self.free {
+            let link: *const u64 = phys_to_virt(frame.start_address())?.as_ptr();
+            let next = unsafe { link.read() };
+            self.free = if next == 0 { None } else { Some(PhysFrame::containing_address(PhysAddr::new(next))) };
+            return Some(frame);
+        }
         let frame = self.usable_frames().nth(self.next);
         self.next += 1;
         frame
     }
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        // without the physical memory mapping there's nowhere to keep the link, the frame is lost then
+        if let Some(link) = phys_to_virt(frame.start_address()) {
+            let next = self.free.map_or(0, |next| next.start_address().as_u64());
+            link.as_mut_ptr::<u64>().write(next);
+            self.free = Some(frame);
+        }
+    }
+}
+
+static FRAME_ALLOCATOR: Mutex<Option<BootInfoFrameAllocator>> = Mutex::new(None);
+
+/// Keeps the frame allocator after the heap has been set up, processes need frames for their memory and page
+/// tables.
+pub fn set_frame_allocator(allocator: BootInfoFrameAllocator) {
+    *FRAME_ALLOCATOR.lock() = Some(allocator);
+}
+
+/// The allocator given to `set_frame_allocator`. Never use it with the interrupts off, like the heap.
+pub struct GlobalFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for GlobalFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        FRAME_ALLOCATOR.lock().as_mut()?.allocate_frame()
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for GlobalFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        if let Some(allocator) = FRAME_ALLOCATOR.lock().as_mut() {
+            allocator.deallocate_frame(frame);
+        }
+    }
 }
\ No newline at end of file
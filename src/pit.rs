@@ -0,0 +1,82 @@
+/* The programmable interval timer, the chip behind IRQ0. Its channel 0 counts down from a divisor at 1193182 Hz
+   and fires the timer interrupt every time it hits zero, so the divisor decides how often that is. Out of the box
+   it's 65536 (about 18.2 Hz), we set it to FREQUENCY_HZ so a tick is a millisecond.
+
+   Every timer interrupt calls `tick`, that count is the kernel's clock: uptime, `delay` and the `sleep` future in
+   task/timer.rs all go by it. */
+
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use x86_64::instructions::{hlt, interrupts, port::Port};
+
+const CHANNEL_0: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+
+// channel 0, low byte then high byte of the divisor, mode 3 (square wave), binary
+const COMMAND_CHANNEL_0_RATE: u8 = 0b0011_0110;
+
+/// What the PIT counts down with.
+pub const BASE_HZ: u64 = 1_193_182;
+/// Timer interrupts per second, roughly: the divisor is a whole number so it's really 1000.15.
+pub const FREQUENCY_HZ: u64 = 1000;
+const DIVISOR: u64 = BASE_HZ / FREQUENCY_HZ;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Sets channel 0 to FREQUENCY_HZ, before the interrupts are turned on.
+pub fn init() {
+    let mut command: Port<u8> = Port::new(COMMAND);
+    let mut channel_0: Port<u8> = Port::new(CHANNEL_0);
+    interrupts::without_interrupts(|| unsafe {
+        command.write(COMMAND_CHANNEL_0_RATE);
+        channel_0.write(DIVISOR as u8);
+        channel_0.write((DIVISOR >> 8) as u8);
+    });
+}
+
+/// Called by the timer interrupt handler.
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// How long `ticks` timer interrupts take.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    let nanos = ticks as u128 * DIVISOR as u128 * 1_000_000_000 / BASE_HZ as u128;
+    Duration::from_nanos(nanos as u64)
+}
+
+/// How many ticks it takes for at least `duration` to pass.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let ticks = (duration.as_nanos() * BASE_HZ as u128 + DIVISOR as u128 * 1_000_000_000 - 1)
+        / (DIVISOR as u128 * 1_000_000_000);
+    ticks as u64
+}
+
+/// Time since the interrupts got turned on.
+pub fn uptime() -> Duration {
+    ticks_to_duration(ticks())
+}
+
+/// Waits for `duration` with the CPU halted between ticks. Nothing else runs meanwhile, tasks should await
+/// `task::timer::sleep` instead. The interrupts have to be on, or this never returns.
+pub fn delay(duration: Duration) {
+    let deadline = ticks() + duration_to_ticks(duration);
+    while ticks() < deadline {
+        hlt();
+    }
+}
+
+#[test_case]
+fn test_tick_conversion() {
+    assert_eq!(duration_to_ticks(Duration::from_millis(0)), 0);
+    assert_eq!(duration_to_ticks(ticks_to_duration(5)), 5);
+    assert_eq!(duration_to_ticks(Duration::from_secs(1)), 1001); // a tick is a little shorter than 1 ms
+    assert!(ticks_to_duration(duration_to_ticks(Duration::from_millis(250))) >= Duration::from_millis(250));
+}
@@ -0,0 +1,866 @@
+/* User programs. A process is a kernel thread that goes down to ring 3 to run an ELF program, with a page table of
+   its own so the program can only get at its own memory and has to ask the kernel for everything else.
+
+   The process's level 4 table starts as a copy of the kernel's, so the kernel is there in every process (the
+   interrupts and syscalls need it), but none of it is USER_ACCESSIBLE. The program gets the one entry at
+   USER_START, 512 GiB that no other process can see: its segments, the heap `sbrk` grows after them and the stack
+   at the very top. Programs have to be linked to load there, with rust-lld that's
+   `-C link-arg=--image-base=0x200000000000` (and `-C relocation-model=static`, there's no dynamic linking). They
+   also have to be built for a soft-float target such as `x86_64-unknown-none`, like the kernel itself: SSE isn't
+   turned on and thread switches only save the general purpose registers, so SSE code is an invalid opcode here.
+
+   A program asks for things with `int 0x80`: the syscall number in rax, the arguments in rdi, rsi and rdx, and the
+   result comes back in rax, negative ones are a `SyscallError`. Its `_start` gets argc in rdi and argv in rsi,
+   C style: pointers to NUL-terminated strings with a null pointer after the last one.
+
+   `process_enter` saves where the thread's kernel stack was and irets into ring 3, and everything that ends the
+   process (the exit syscall, a fault, `kill`) goes back there with `process_leave`, so `run` carries on as if
+   process_enter had returned. Interrupts from ring 3 can't use the thread's stack for that reason (it's in use
+   further up), they get a stack of their own, which comes with the thread's slot (see thread.rs).
+
+   `kill` can't stop a process's thread right where it is, it might be in the kernel holding a lock. A thread that
+   got interrupted in ring 3 is made to carry on in `killed` instead (see thread::divert_from_user_mode), one in
+   the middle of a syscall is stopped when the syscall is done. */
+
+use crate::{
+    commands::{Output, Terminal},
+    gdt,
+    memory::{self, GlobalFrameAllocator},
+    thread::{self, JoinHandle, SavedFrame, ThreadId},
+    vfs::{self, VfsError, VFS},
+};
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec, vec::Vec};
+use core::{
+    arch::global_asm,
+    fmt::{self, Write},
+    mem,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts,
+    registers::control::Cr3,
+    structures::paging::{
+        mapper::TranslateResult, FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable,
+        PageTableFlags, PhysFrame, Size4KiB, Translate,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// The interrupt programs make syscalls with, the only one they're allowed to use.
+pub const SYSCALL_VECTOR: u8 = 0x80;
+
+/// Where user memory starts, level 4 entry 64.
+pub const USER_START: u64 = 0x2000_0000_0000;
+/// Where it ends, one level 4 entry later.
+pub const USER_END: u64 = USER_START + (1 << 39);
+const USER_L4_INDEX: usize = (USER_START >> 39) as usize;
+
+const PAGE_SIZE: u64 = 4096;
+const STACK_SIZE: u64 = 64 * 1024; // the program's stack, at the top of user memory
+const HEAP_LIMIT: u64 = 16 * 1024 * 1024; // how far sbrk can go, if the stack isn't in the way first
+const MAX_FILES: usize = 16;
+const MAX_IO: u64 = 64 * 1024; // the most a read or write moves at once, the rest needs another call
+const MAX_PATH: u64 = 4096;
+
+/// Syscall numbers, what goes in rax.
+pub const SYS_EXIT: u64 = 0; // exit(status), doesn't come back
+pub const SYS_WRITE: u64 = 1; // write(fd, buffer, length) -> bytes written
+pub const SYS_READ: u64 = 2; // read(fd, buffer, length) -> bytes read, 0 at the end of the file
+pub const SYS_OPEN: u64 = 3; // open(path, path length, mode) -> fd
+pub const SYS_CLOSE: u64 = 4; // close(fd) -> 0
+pub const SYS_SBRK: u64 = 5; // sbrk(increment) -> the old end of the heap
+
+/// Modes for `SYS_OPEN`. What's written to a file shows up in it when it's closed (or when the process ends).
+pub const OPEN_READ: u64 = 0;
+pub const OPEN_WRITE: u64 = 1; // made empty first, or created
+pub const OPEN_APPEND: u64 = 2;
+
+/// Why a syscall failed, programs get the negative `code`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallError {
+    NoSuchSyscall = 1,
+    BadAddress = 2, // a pointer that isn't to the program's own memory
+    BadFile = 3,    // not an open fd, or not open for that
+    NotFound = 4,
+    IsADirectory = 5,
+    InvalidArgument = 6,
+    TooManyFiles = 7,
+    NoMemory = 8,
+    Io = 9, // anything else the filesystem didn't like
+}
+
+impl SyscallError {
+    pub fn code(self) -> i64 {
+        -(self as i64)
+    }
+}
+
+impl From<VfsError> for SyscallError {
+    fn from(error: VfsError) -> Self {
+        match error {
+            VfsError::NotFound => SyscallError::NotFound,
+            VfsError::IsADirectory => SyscallError::IsADirectory,
+            VfsError::InvalidName | VfsError::NameTooLong => SyscallError::InvalidArgument,
+            _ => SyscallError::Io,
+        }
+    }
+}
+
+/// Why a program couldn't be started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessError {
+    NotElf,
+    NotExecutable,     // an ELF file, but not a static x86_64 executable
+    BadSegment,        // something in it has to go outside user memory
+    ArgumentsTooLong,  // they don't fit on the stack
+    NoMemory,
+    AddressSpaceTaken, // the kernel uses USER_START itself
+    TooManyThreads,
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ProcessError::NotElf => "not an ELF file",
+            ProcessError::NotExecutable => "not an x86_64 executable",
+            ProcessError::BadSegment => "it doesn't load at the user address",
+            ProcessError::ArgumentsTooLong => "the arguments are too long",
+            ProcessError::NoMemory => "out of memory",
+            ProcessError::AddressSpaceTaken => "the kernel uses the user address range",
+            ProcessError::TooManyThreads => "there are too many threads already",
+        })
+    }
+}
+
+/// What a program did that ended it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    PageFault(VirtAddr),
+    GeneralProtection,
+    InvalidOpcode,
+    DivideError,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::PageFault(address) => write!(f, "page fault at {:#x}", address.as_u64()),
+            Fault::GeneralProtection => f.write_str("general protection fault"),
+            Fault::InvalidOpcode => f.write_str("invalid opcode"),
+            Fault::DivideError => f.write_str("division by zero"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Exited(i64),
+    Faulted(Fault),
+    Killed,
+}
+
+/// What `join` on a process gives back.
+pub struct Finished {
+    pub status: ExitStatus,
+    pub output: Output, // what the program wrote to fd 1, if it went to a buffer
+}
+
+/// A program to start and what it's connected to.
+pub struct Program<'a> {
+    pub image: &'a [u8],     // the ELF file
+    pub args: &'a [&'a str], // args[0] is the program's name
+    pub cwd: &'a str,        // relative paths in `SYS_OPEN` start here
+    pub input: Vec<u8>,      // what fd 0 reads, then the end of the file
+    pub output: Output,      // where fd 1 goes
+    pub terminal: Terminal,  // where fd 2 goes
+}
+
+global_asm!(
+    ".global process_syscall_entry",
+    "process_syscall_entry:",
+    "    push rax",
+    "    lea rax, [rip + process_syscall]",
+    "    jmp thread_switch",
+    "",
+    // rdi: the Start, rsi: where to save the kernel stack pointer
+    ".global process_enter",
+    "process_enter:",
+    "    push rbx",
+    "    push rbp",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov [rsi], rsp",
+    "    push qword ptr [rdi + 24]", // ss
+    "    push qword ptr [rdi + 8]",  // rsp
+    "    push 0x202",                // rflags, with the interrupts on
+    "    push qword ptr [rdi + 16]", // cs
+    "    push qword ptr [rdi]",      // rip
+    "    mov rsi, [rdi + 40]",
+    "    mov rdi, [rdi + 32]",
+    // nothing of the kernel's goes up to ring 3
+    "    xor eax, eax",
+    "    xor ebx, ebx",
+    "    xor ecx, ecx",
+    "    xor edx, edx",
+    "    xor ebp, ebp",
+    "    xor r8d, r8d",
+    "    xor r9d, r9d",
+    "    xor r10d, r10d",
+    "    xor r11d, r11d",
+    "    xor r12d, r12d",
+    "    xor r13d, r13d",
+    "    xor r14d, r14d",
+    "    xor r15d, r15d",
+    "    iretq",
+    "",
+    // rdi: the stack pointer process_enter saved, returns from process_enter
+    ".global process_leave",
+    "process_leave:",
+    "    mov rsp, rdi",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop rbp",
+    "    pop rbx",
+    "    ret",
+);
+
+extern "C" {
+    fn process_syscall_entry();
+    fn process_enter(start: *const Start, kernel_rsp: *mut u64);
+    fn process_leave(kernel_rsp: u64) -> !;
+}
+
+/// Where SYSCALL_VECTOR goes, for the IDT.
+pub(crate) fn syscall_entry() -> VirtAddr {
+    VirtAddr::new(process_syscall_entry as *const () as u64)
+}
+
+/// What process_enter puts in the registers for ring 3.
+#[repr(C)]
+struct Start {
+    rip: u64,
+    rsp: u64,
+    cs: u64,
+    ss: u64,
+    argc: u64,
+    argv: u64,
+}
+
+/// A process's page table and the memory it maps, all of which is freed when it's dropped.
+struct AddressSpace {
+    page_table: PhysFrame,
+}
+
+fn table_at(frame: PhysFrame) -> *mut PageTable {
+    memory::phys_to_virt(frame.start_address())
+        .expect("memory::init hasn't been called")
+        .as_mut_ptr()
+}
+
+fn zeroed_frame() -> Result<PhysFrame, ProcessError> {
+    let frame = GlobalFrameAllocator.allocate_frame().ok_or(ProcessError::NoMemory)?;
+    unsafe { table_at(frame).write(PageTable::new()) }; // a page table of zeroes is a page of zeroes
+    Ok(frame)
+}
+
+impl AddressSpace {
+    /// The kernel's mappings and nothing else. Called from a kernel thread, it copies the active table.
+    fn new() -> Result<Self, ProcessError> {
+        let page_table = zeroed_frame()?;
+        let (kernel, _) = Cr3::read();
+        unsafe {
+            let table = &mut *table_at(page_table);
+            *table = (*table_at(kernel)).clone();
+            if !table[USER_L4_INDEX].is_unused() {
+                GlobalFrameAllocator.deallocate_frame(page_table);
+                return Err(ProcessError::AddressSpaceTaken);
+            }
+        }
+        Ok(AddressSpace { page_table })
+    }
+
+    fn mapper(&mut self) -> OffsetPageTable<'_> {
+        let offset = memory::phys_to_virt(PhysAddr::new(0)).expect("memory::init hasn't been called");
+        unsafe { OffsetPageTable::new(&mut *table_at(self.page_table), offset) }
+    }
+
+    /// Maps zeroed pages over `start..end`, pages that are mapped already are kept.
+    fn map(&mut self, start: u64, end: u64, writable: bool) -> Result<(), ProcessError> {
+        if start >= end {
+            return Ok(());
+        }
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        if writable {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        let parent_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        let first = Page::<Size4KiB>::containing_address(VirtAddr::new(start));
+        let last = Page::<Size4KiB>::containing_address(VirtAddr::new(end - 1));
+        let mut mapper = self.mapper();
+        for page in Page::range_inclusive(first, last) {
+            if let TranslateResult::Mapped { flags: old, .. } = mapper.translate(page.start_address()) {
+                if !old.contains(flags) {
+                    unsafe { mapper.update_flags(page, old | flags).map_err(|_| ProcessError::NoMemory)?.flush() };
+                }
+                continue;
+            }
+            let frame = zeroed_frame()?;
+            let mapped = unsafe { mapper.map_to_with_table_flags(page, frame, flags, parent_flags, &mut GlobalFrameAllocator) };
+            match mapped {
+                Ok(flush) => flush.flush(),
+                Err(_) => {
+                    unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+                    return Err(ProcessError::NoMemory);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Unmaps and frees the pages in `start..end`.
+    fn unmap(&mut self, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+        let first = Page::<Size4KiB>::containing_address(VirtAddr::new(start));
+        let last = Page::<Size4KiB>::containing_address(VirtAddr::new(end - 1));
+        let mut mapper = self.mapper();
+        for page in Page::range_inclusive(first, last) {
+            if let Ok((frame, flush)) = mapper.unmap(page) {
+                flush.flush();
+                unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+            }
+        }
+    }
+
+    /// Where `address` is in physical memory, if it's user memory mapped with `flags`.
+    fn translate(&mut self, address: u64, flags: PageTableFlags) -> Option<VirtAddr> {
+        if !(USER_START..USER_END).contains(&address) {
+            return None;
+        }
+        match self.mapper().translate(VirtAddr::new(address)) {
+            TranslateResult::Mapped { frame, offset, flags: mapped } if mapped.contains(flags) => {
+                memory::phys_to_virt(frame.start_address() + offset)
+            }
+            _ => None,
+        }
+    }
+
+    /// Copies `data` to `address`, a page at a time. False if some of it isn't mapped with `flags`.
+    fn copy_to(&mut self, address: u64, data: &[u8], flags: PageTableFlags) -> bool {
+        let mut done = 0;
+        while done < data.len() {
+            let at = address + done as u64;
+            let len = ((PAGE_SIZE - at % PAGE_SIZE) as usize).min(data.len() - done);
+            match self.translate(at, flags) {
+                Some(target) => unsafe {
+                    core::ptr::copy_nonoverlapping(data[done..].as_ptr(), target.as_mut_ptr::<u8>(), len);
+                },
+                None => return false,
+            }
+            done += len;
+        }
+        true
+    }
+
+    /// Copies `len` bytes from `address` in user memory, None if some of it isn't mapped.
+    fn copy_from(&mut self, address: u64, len: u64) -> Option<Vec<u8>> {
+        let mut data = vec![0u8; len as usize];
+        let mut done = 0;
+        while done < data.len() {
+            let at = address.checked_add(done as u64)?;
+            let chunk = ((PAGE_SIZE - at % PAGE_SIZE) as usize).min(data.len() - done);
+            let source = self.translate(at, PageTableFlags::USER_ACCESSIBLE)?;
+            unsafe { core::ptr::copy_nonoverlapping(source.as_ptr::<u8>(), data[done..].as_mut_ptr(), chunk) };
+            done += chunk;
+        }
+        Some(data)
+    }
+}
+
+/// Frees a page table and everything below it, `level` 1 is the one that points at the pages.
+unsafe fn free_table(frame: PhysFrame, level: u8) {
+    for entry in (*table_at(frame)).iter() {
+        if let Ok(child) = entry.frame() {
+            if level > 1 {
+                free_table(child, level - 1);
+            } else {
+                GlobalFrameAllocator.deallocate_frame(child);
+            }
+        }
+    }
+    GlobalFrameAllocator.deallocate_frame(frame);
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        // the kernel's part is shared, only the user entry belongs to us
+        assert_ne!(Cr3::read().0, self.page_table, "dropping the address space that's in use");
+        unsafe {
+            let table = &*table_at(self.page_table);
+            if let Ok(level_3) = table[USER_L4_INDEX].frame() {
+                free_table(level_3, 3);
+            }
+            GlobalFrameAllocator.deallocate_frame(self.page_table);
+        }
+    }
+}
+
+fn align_up(address: u64) -> u64 {
+    (address + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+/// A loadable part of an ELF file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Segment {
+    address: u64,
+    mem_size: u64,
+    offset: usize,
+    file_size: usize,
+    writable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Elf {
+    entry: u64,
+    segments: Vec<Segment>,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    bytes.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    bytes.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let mut value = [0u8; 8];
+    value.copy_from_slice(bytes.get(offset..offset + 8)?);
+    Some(u64::from_le_bytes(value))
+}
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELF_CLASS_64: u8 = 2;
+const ELF_LITTLE_ENDIAN: u8 = 1;
+const ELF_EXECUTABLE: u16 = 2;
+const ELF_X86_64: u16 = 0x3e;
+const PT_LOAD: u32 = 1;
+const PF_W: u32 = 2;
+
+/// The entry point and the PT_LOAD segments of an ELF file, checked to fit in user memory below the stack.
+fn parse_elf(image: &[u8]) -> Result<Elf, ProcessError> {
+    if image.get(..4) != Some(ELF_MAGIC) || image.get(4) != Some(&ELF_CLASS_64) || image.get(5) != Some(&ELF_LITTLE_ENDIAN)
+    {
+        return Err(ProcessError::NotElf);
+    }
+    if read_u16(image, 16) != Some(ELF_EXECUTABLE) || read_u16(image, 18) != Some(ELF_X86_64) {
+        return Err(ProcessError::NotExecutable);
+    }
+    let header = |offset| read_u64(image, offset).ok_or(ProcessError::NotElf);
+    let entry = header(24)?;
+    let table = header(32)? as usize;
+    let entry_size = read_u16(image, 54).ok_or(ProcessError::NotElf)? as usize;
+    let count = read_u16(image, 56).ok_or(ProcessError::NotElf)? as usize;
+    if table > image.len() || entry_size < 56 {
+        return Err(ProcessError::NotElf);
+    }
+
+    let mut segments = Vec::new();
+    for index in 0..count {
+        let at = table + index * entry_size;
+        let field = |offset| read_u64(image, at + offset).ok_or(ProcessError::NotElf);
+        if read_u32(image, at).ok_or(ProcessError::NotElf)? != PT_LOAD {
+            continue;
+        }
+        let flags = read_u32(image, at + 4).ok_or(ProcessError::NotElf)?;
+        let (offset, address, file_size, mem_size) = (field(8)?, field(16)?, field(32)?, field(40)?);
+        let end = address.checked_add(mem_size).ok_or(ProcessError::BadSegment)?;
+        if address < USER_START || end > USER_END - STACK_SIZE || file_size > mem_size {
+            return Err(ProcessError::BadSegment);
+        }
+        if offset.checked_add(file_size).is_none_or(|end| end > image.len() as u64) {
+            return Err(ProcessError::NotElf);
+        }
+        segments.push(Segment {
+            address,
+            mem_size,
+            offset: offset as usize,
+            file_size: file_size as usize,
+            writable: flags & PF_W != 0,
+        });
+    }
+    let inside = |segment: &Segment| (segment.address..segment.address + segment.mem_size).contains(&entry);
+    if !segments.iter().any(inside) {
+        return Err(ProcessError::BadSegment);
+    }
+    Ok(Elf { entry, segments })
+}
+
+/// Lays out argc and argv for a stack that ends at `top`: the strings at the top, then the pointers to them.
+/// Returns the bytes that go right below `top`, the stack pointer to start with and argv.
+fn build_args(args: &[&str], top: u64) -> (Vec<u8>, u64, u64) {
+    let strings_size: usize = args.iter().map(|arg| arg.len() + 1).sum();
+    let strings_start = (top - strings_size as u64) & !7;
+    let argv = strings_start - 8 * (args.len() as u64 + 1);
+    let rsp = (argv & !0xF) - 8; // as if _start had been called, the return address slot (0) is below 16 bytes
+    let mut data = vec![0u8; (top - rsp) as usize];
+    let mut string = strings_start;
+    for (index, arg) in args.iter().enumerate() {
+        let at = (string - rsp) as usize;
+        data[at..at + arg.len()].copy_from_slice(arg.as_bytes());
+        let pointer = (argv - rsp) as usize + index * 8;
+        data[pointer..pointer + 8].copy_from_slice(&string.to_le_bytes());
+        string += arg.len() as u64 + 1;
+    }
+    (data, rsp, argv)
+}
+
+/// An open fd.
+enum File {
+    Read { data: Vec<u8>, position: usize },
+    Write { path: String, data: Vec<u8>, append: bool },
+    Output,   // fd 1
+    Terminal, // fd 2
+}
+
+impl File {
+    /// Writes what's been written to a file to the filesystem.
+    fn flush(self) -> Result<(), SyscallError> {
+        match self {
+            File::Write { path, data, append: false } => Ok(VFS.lock().write(&path, &data)?),
+            File::Write { path, data, append: true } => Ok(VFS.lock().append(&path, &data)?),
+            _ => Ok(()),
+        }
+    }
+}
+
+struct ProcessState {
+    space: AddressSpace,
+    heap_start: u64,
+    heap_end: u64, // the break, what sbrk moves
+    files: Vec<Option<File>>,
+    cwd: String,
+    output: Output,
+    terminal: Terminal,
+    status: Option<ExitStatus>,
+}
+
+struct Process {
+    state: Mutex<ProcessState>,
+    kernel_rsp: AtomicU64, // where process_enter left the thread's stack
+    parent: ThreadId,      // the thread that started it
+    killed: AtomicBool,
+}
+
+lazy_static! {
+    static ref PROCESSES: Mutex<BTreeMap<ThreadId, Arc<Process>>> = Mutex::new(BTreeMap::new());
+}
+
+/// The process the running thread belongs to.
+fn current() -> Option<Arc<Process>> {
+    PROCESSES.lock().get(&thread::current()).cloned()
+}
+
+/// Loads a program and starts it in a thread of its own. `join` on the handle waits for it to end.
+pub fn spawn(program: Program) -> Result<JoinHandle<Finished>, ProcessError> {
+    let elf = parse_elf(program.image)?;
+    let mut space = AddressSpace::new()?;
+    let mut heap_start = USER_START;
+    for segment in &elf.segments {
+        space.map(segment.address, segment.address + segment.mem_size, segment.writable)?;
+        let data = &program.image[segment.offset..segment.offset + segment.file_size];
+        space.copy_to(segment.address, data, PageTableFlags::USER_ACCESSIBLE);
+        heap_start = heap_start.max(align_up(segment.address + segment.mem_size));
+    }
+
+    let (args, rsp, argv) = build_args(program.args, USER_END);
+    if args.len() as u64 > STACK_SIZE / 2 {
+        return Err(ProcessError::ArgumentsTooLong);
+    }
+    space.map(USER_END - STACK_SIZE, USER_END, true)?;
+    space.copy_to(rsp, &args, PageTableFlags::USER_ACCESSIBLE);
+
+    let (code, data) = gdt::user_selectors();
+    let start = Start {
+        rip: elf.entry,
+        rsp,
+        cs: code.0 as u64,
+        ss: data.0 as u64,
+        argc: program.args.len() as u64,
+        argv,
+    };
+    let process = Arc::new(Process {
+        state: Mutex::new(ProcessState {
+            space,
+            heap_start,
+            heap_end: heap_start,
+            files: vec![
+                Some(File::Read { data: program.input, position: 0 }),
+                Some(File::Output),
+                Some(File::Terminal),
+            ],
+            cwd: String::from(program.cwd),
+            output: program.output,
+            terminal: program.terminal,
+            status: None,
+        }),
+        kernel_rsp: AtomicU64::new(0),
+        parent: thread::current(),
+        killed: AtomicBool::new(false),
+    });
+    thread::spawn("process", move || run(process, start)).map_err(|_| ProcessError::TooManyThreads)
+}
+
+/// The process's thread, from start to end.
+fn run(process: Arc<Process>, start: Start) -> Finished {
+    PROCESSES.lock().insert(thread::current(), process.clone());
+    let page_table = process.state.lock().space.page_table;
+    thread::set_user_context(Some(page_table));
+    if process.killed.load(Ordering::SeqCst) {
+        process.state.lock().status = Some(ExitStatus::Killed); // before it even started
+    } else {
+        unsafe { process_enter(&start, process.kernel_rsp.as_ptr()) };
+    }
+    interrupts::enable(); // a fault comes back with them off
+    thread::set_user_context(None);
+    PROCESSES.lock().remove(&thread::current());
+
+    let mut state = process.state.lock();
+    for file in mem::take(&mut state.files).into_iter().flatten() {
+        let _ = file.flush(); // nobody left to tell if it fails
+    }
+    Finished {
+        status: state.status.unwrap_or(ExitStatus::Exited(0)),
+        output: mem::replace(&mut state.output, Output::Buffer(String::new())),
+    }
+}
+
+/// Ends the running process and goes back to `run`.
+fn leave(status: ExitStatus) -> ! {
+    let kernel_rsp = {
+        let process = current().expect("only a process can leave ring 3");
+        process.state.lock().status = Some(status);
+        process.kernel_rsp.load(Ordering::SeqCst)
+    }; // nothing may be left to drop, process_leave throws the stack away
+    unsafe { process_leave(kernel_rsp) }
+}
+
+/// Called by the exception handlers for an exception in ring 3, ends the process that caused it.
+pub(crate) fn fault(fault: Fault) -> ! {
+    // the process itself can't be holding a lock, but a thread it interrupted can
+    interrupts::enable();
+    leave(ExitStatus::Faulted(fault))
+}
+
+/// Stops every process `parent` started, Ctrl+C uses it. A process that's just starting can get past this, so
+/// it's called again until the parent is done with them.
+pub fn kill_started_by(parent: ThreadId) {
+    let started: Vec<(ThreadId, Arc<Process>)> = PROCESSES
+        .lock()
+        .iter()
+        .filter(|(_, process)| process.parent == parent)
+        .map(|(id, process)| (*id, process.clone()))
+        .collect();
+    for (id, process) in started {
+        process.killed.store(true, Ordering::SeqCst);
+        thread::divert_from_user_mode(id, killed); // if it's in a syscall instead, that ends it
+    }
+}
+
+/// Where a killed process's thread goes instead of back to ring 3.
+extern "C" fn killed() -> ! {
+    interrupts::enable(); // like `fault`
+    leave(ExitStatus::Killed)
+}
+
+/// Called by process_syscall_entry with the stack pointer of the registers it saved.
+#[no_mangle]
+extern "C" fn process_syscall(rsp: u64) -> u64 {
+    interrupts::enable(); // so it can use the heap, and a slow syscall doesn't stop the other threads
+    let frame = unsafe { &mut *(rsp as *mut SavedFrame) };
+    frame.rax = match syscall(frame.rax, frame.rdi, frame.rsi, frame.rdx) {
+        Ok(value) => value as u64,
+        Err(error) => error.code() as u64,
+    };
+    if current().is_some_and(|process| process.killed.load(Ordering::SeqCst)) {
+        leave(ExitStatus::Killed);
+    }
+    interrupts::disable();
+    rsp
+}
+
+fn syscall(number: u64, arg0: u64, arg1: u64, arg2: u64) -> Result<i64, SyscallError> {
+    if number == SYS_EXIT {
+        leave(ExitStatus::Exited(arg0 as i64));
+    }
+    let process = current().ok_or(SyscallError::NoSuchSyscall)?;
+    let mut state = process.state.lock();
+    match number {
+        SYS_WRITE => state.write(arg0, arg1, arg2),
+        SYS_READ => state.read(arg0, arg1, arg2),
+        SYS_OPEN => state.open(arg0, arg1, arg2),
+        SYS_CLOSE => state.close(arg0),
+        SYS_SBRK => state.sbrk(arg0 as i64),
+        _ => Err(SyscallError::NoSuchSyscall),
+    }
+}
+
+impl ProcessState {
+    fn file(&mut self, fd: u64) -> Result<&mut File, SyscallError> {
+        self.files.get_mut(fd as usize).and_then(Option::as_mut).ok_or(SyscallError::BadFile)
+    }
+
+    fn write(&mut self, fd: u64, buffer: u64, len: u64) -> Result<i64, SyscallError> {
+        let len = len.min(MAX_IO);
+        let data = self.space.copy_from(buffer, len).ok_or(SyscallError::BadAddress)?;
+        // not through `file`, the output is borrowed next to the fd
+        match self.files.get_mut(fd as usize).and_then(Option::as_mut) {
+            Some(File::Write { data: content, .. }) => content.extend_from_slice(&data),
+            Some(File::Output) => {
+                let _ = self.output.write_str(&String::from_utf8_lossy(&data));
+            }
+            Some(File::Terminal) => {
+                let _ = self.terminal.write_str(&String::from_utf8_lossy(&data));
+            }
+            _ => return Err(SyscallError::BadFile),
+        }
+        Ok(len as i64)
+    }
+
+    fn read(&mut self, fd: u64, buffer: u64, len: u64) -> Result<i64, SyscallError> {
+        let chunk = match self.file(fd)? {
+            File::Read { data, position } => {
+                let end = data.len().min(*position + len.min(MAX_IO) as usize);
+                let chunk = data[*position..end].to_vec();
+                *position = end;
+                chunk
+            }
+            _ => return Err(SyscallError::BadFile),
+        };
+        let flags = PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE;
+        if !self.space.copy_to(buffer, &chunk, flags) {
+            return Err(SyscallError::BadAddress);
+        }
+        Ok(chunk.len() as i64)
+    }
+
+    fn open(&mut self, path: u64, path_len: u64, mode: u64) -> Result<i64, SyscallError> {
+        if path_len > MAX_PATH {
+            return Err(SyscallError::InvalidArgument);
+        }
+        let path = self.space.copy_from(path, path_len).ok_or(SyscallError::BadAddress)?;
+        let path = core::str::from_utf8(&path).map_err(|_| SyscallError::InvalidArgument)?;
+        let path = vfs::normalize(&self.cwd, path);
+        let file = match mode {
+            OPEN_READ => File::Read { data: VFS.lock().read(&path)?, position: 0 },
+            OPEN_WRITE => {
+                VFS.lock().write(&path, &[])?;
+                File::Write { path, data: Vec::new(), append: false }
+            }
+            OPEN_APPEND => {
+                VFS.lock().append(&path, &[])?;
+                File::Write { path, data: Vec::new(), append: true }
+            }
+            _ => return Err(SyscallError::InvalidArgument),
+        };
+        let fd = match self.files.iter().position(Option::is_none) {
+            Some(fd) => fd,
+            None if self.files.len() < MAX_FILES => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+            None => return Err(SyscallError::TooManyFiles),
+        };
+        self.files[fd] = Some(file);
+        Ok(fd as i64)
+    }
+
+    fn close(&mut self, fd: u64) -> Result<i64, SyscallError> {
+        let file = self.files.get_mut(fd as usize).and_then(Option::take).ok_or(SyscallError::BadFile)?;
+        file.flush().map(|()| 0)
+    }
+
+    fn sbrk(&mut self, increment: i64) -> Result<i64, SyscallError> {
+        let old = self.heap_end;
+        let new = old.checked_add_signed(increment).ok_or(SyscallError::InvalidArgument)?;
+        if new < self.heap_start {
+            return Err(SyscallError::InvalidArgument);
+        }
+        if new > self.heap_start + HEAP_LIMIT || new > USER_END - STACK_SIZE {
+            return Err(SyscallError::NoMemory);
+        }
+        if new > old {
+            self.space.map(old, new, true).map_err(|_| SyscallError::NoMemory)?;
+        } else {
+            self.space.unmap(align_up(new), align_up(old));
+        }
+        self.heap_end = new;
+        Ok(old as i64)
+    }
+}
+
+#[test_case]
+fn test_parse_elf() {
+    assert_eq!(parse_elf(b"#!/bin/sh\n"), Err(ProcessError::NotElf));
+    let mut image = vec![0u8; 64 + 56 + 4];
+    image[..4].copy_from_slice(ELF_MAGIC);
+    image[4] = ELF_CLASS_64;
+    image[5] = ELF_LITTLE_ENDIAN;
+    image[16..18].copy_from_slice(&ELF_EXECUTABLE.to_le_bytes());
+    image[18..20].copy_from_slice(&0x28u16.to_le_bytes()); // ARM
+    assert_eq!(parse_elf(&image), Err(ProcessError::NotExecutable));
+
+    image[18..20].copy_from_slice(&ELF_X86_64.to_le_bytes());
+    image[24..32].copy_from_slice(&(USER_START + 120).to_le_bytes()); // the entry
+    image[32..40].copy_from_slice(&64u64.to_le_bytes()); // the program headers
+    image[54..56].copy_from_slice(&56u16.to_le_bytes());
+    image[56..58].copy_from_slice(&1u16.to_le_bytes());
+    image[64..68].copy_from_slice(&PT_LOAD.to_le_bytes());
+    image[80..88].copy_from_slice(&USER_START.to_le_bytes());
+    image[96..104].copy_from_slice(&124u64.to_le_bytes());
+    image[104..112].copy_from_slice(&124u64.to_le_bytes());
+    let elf = parse_elf(&image).expect("a valid ELF file");
+    assert_eq!(elf.entry, USER_START + 120);
+    assert_eq!(elf.segments, [Segment { address: USER_START, mem_size: 124, offset: 0, file_size: 124, writable: false }]);
+
+    image[80..88].copy_from_slice(&0x20_0000u64.to_le_bytes()); // where the kernel is
+    assert_eq!(parse_elf(&image), Err(ProcessError::BadSegment));
+}
+
+#[test_case]
+fn test_sbrk_stops_at_the_stack() {
+    let heap_start = USER_END - STACK_SIZE - PAGE_SIZE; // a program whose segments go right up to the stack
+    let mut state = ProcessState {
+        space: AddressSpace::new().expect("room for a page table"),
+        heap_start,
+        heap_end: heap_start,
+        files: Vec::new(),
+        cwd: String::new(),
+        output: Output::Buffer(String::new()),
+        terminal: Terminal::Serial,
+        status: None,
+    };
+    assert_eq!(state.sbrk(PAGE_SIZE as i64), Ok(heap_start as i64));
+    assert_eq!(state.sbrk(1), Err(SyscallError::NoMemory));
+    assert_eq!(state.sbrk(0), Ok((USER_END - STACK_SIZE) as i64));
+}
+
+#[test_case]
+fn test_build_args() {
+    let top = USER_END;
+    let (data, rsp, argv) = build_args(&["hello", "-v"], top);
+    assert_eq!(rsp % 16, 8);
+    assert_eq!(rsp + data.len() as u64, top);
+    let pointer = |index: u64| read_u64(&data, (argv - rsp + index * 8) as usize).unwrap();
+    let string = |address: u64, len: usize| &data[(address - rsp) as usize..(address - rsp) as usize + len + 1];
+    assert_eq!(string(pointer(0), 5), b"hello\0");
+    assert_eq!(string(pointer(1), 2), b"-v\0");
+    assert_eq!(pointer(2), 0);
+}
@@ -0,0 +1,194 @@
+/* /proc, a read-only filesystem where every file is made up on the spot from what the kernel knows right now.
+   `/sw $/proc/heap` shows how full the heap is, `/lf $/proc` shows what there is. Nothing here ever
+   touches the disk, and it must never lock the VFS since the VFS is already locked when it calls us. */
+
+use crate::{
+    allocator, getcpu, interrupts, memory, pit, println, rtc,
+    task::executor,
+    thread,
+    vfs::{DirEntry, FileSystem, FileType, Ino, Stat, VfsError, VFS},
+};
+use alloc::{boxed::Box, format, string::String, vec::Vec};
+use core::fmt::Write;
+
+const ROOT: Ino = 0;
+const PATH: &str = "$/proc";
+
+/// A file in /proc: its name and what makes its content.
+type ProcFile = (&'static str, fn() -> String);
+
+/// The files in /proc, their ino is their index here plus one.
+const FILES: [ProcFile; 7] = [
+    ("cpu", cpu),
+    ("heap", heap),
+    ("interrupts", interrupt_counts),
+    ("memmap", memory_map),
+    ("tasks", tasks),
+    ("threads", threads),
+    ("uptime", uptime),
+];
+
+pub struct ProcFs;
+
+impl ProcFs {
+    fn generate(file: Ino) -> Result<String, VfsError> {
+        match file {
+            ROOT => Err(VfsError::IsADirectory),
+            _ => FILES
+                .get(file as usize - 1)
+                .map(|(_, generate)| generate())
+                .ok_or(VfsError::NotFound),
+        }
+    }
+}
+
+impl FileSystem for ProcFs {
+    fn fs_type(&self) -> &'static str {
+        "proc"
+    }
+
+    fn root(&self) -> Ino {
+        ROOT
+    }
+
+    fn lookup(&mut self, dir: Ino, name: &str) -> Result<Ino, VfsError> {
+        if dir != ROOT {
+            return Err(VfsError::NotADirectory);
+        }
+        FILES
+            .iter()
+            .position(|(file, _)| *file == name)
+            .map(|index| index as Ino + 1)
+            .ok_or(VfsError::NotFound)
+    }
+
+    fn stat(&mut self, node: Ino) -> Result<Stat, VfsError> {
+        let now = rtc::unix_time(); // it's all made up right now
+        match node {
+            ROOT => Ok(Stat {
+                kind: FileType::Directory,
+                size: 0,
+                mode: 0o555,
+                owner: 0,
+                created: now,
+                modified: now,
+            }),
+            _ => Ok(Stat {
+                kind: FileType::File,
+                size: ProcFs::generate(node)?.len(),
+                mode: 0o444,
+                owner: 0,
+                created: now,
+                modified: now,
+            }),
+        }
+    }
+
+    fn read(&mut self, file: Ino) -> Result<Vec<u8>, VfsError> {
+        ProcFs::generate(file).map(String::into_bytes)
+    }
+
+    fn write(&mut self, _file: Ino, _data: &[u8]) -> Result<(), VfsError> {
+        Err(VfsError::ReadOnly)
+    }
+
+    fn readdir(&mut self, dir: Ino) -> Result<Vec<DirEntry>, VfsError> {
+        if dir != ROOT {
+            return Err(VfsError::NotADirectory);
+        }
+        Ok(FILES
+            .iter()
+            .map(|(name, _)| DirEntry {
+                name: String::from(*name),
+                kind: FileType::File,
+            })
+            .collect())
+    }
+
+    fn create(&mut self, _dir: Ino, _name: &str) -> Result<Ino, VfsError> {
+        Err(VfsError::ReadOnly)
+    }
+
+    fn mkdir(&mut self, _dir: Ino, _name: &str) -> Result<Ino, VfsError> {
+        Err(VfsError::ReadOnly)
+    }
+
+    fn unlink(&mut self, _dir: Ino, _name: &str) -> Result<(), VfsError> {
+        Err(VfsError::ReadOnly)
+    }
+
+    fn rename(&mut self, _dir: Ino, _name: &str, _new_dir: Ino, _new_name: &str) -> Result<(), VfsError> {
+        Err(VfsError::ReadOnly)
+    }
+}
+
+fn cpu() -> String {
+    let name = getcpu::get_cpu_name().unwrap_or([0; 48]);
+    let name = core::str::from_utf8(&name).unwrap_or("<unknown>");
+    format!("{}\n", name.trim_matches(|c: char| c == '\0' || c.is_whitespace()))
+}
+
+fn heap() -> String {
+    let stats = allocator::stats();
+    format!(
+        "size: {} bytes\nused: {} bytes\nfree: {} bytes\n",
+        stats.size,
+        stats.used,
+        stats.free()
+    )
+}
+
+fn interrupt_counts() -> String {
+    let mut text = String::new();
+    for (name, count) in interrupts::counts().iter() {
+        let _ = writeln!(text, "{:<12}{}", name, count);
+    }
+    text
+}
+
+fn memory_map() -> String {
+    let map = match memory::memory_map() {
+        Some(map) => map,
+        None => return String::from("no memory map\n"),
+    };
+    let mut text = String::new();
+    for region in map.iter() {
+        let _ = writeln!(
+            text,
+            "{:#012x}-{:#012x} {:?}",
+            region.range.start_addr(),
+            region.range.end_addr(),
+            region.region_type
+        );
+    }
+    text
+}
+
+fn tasks() -> String {
+    let mut text = String::from("id  polls     name\n");
+    for task in executor::tasks() {
+        let _ = writeln!(text, "{:<4}{:<10}{}", task.id, task.polls, task.name);
+    }
+    text
+}
+
+fn threads() -> String {
+    let mut text = String::from("id  ticks     state       name\n");
+    for thread in thread::threads() {
+        let state = format!("{}", thread.state);
+        let _ = writeln!(text, "{:<4}{:<10}{:<12}{}", thread.id, thread.ticks, state, thread.name);
+    }
+    text
+}
+
+fn uptime() -> String {
+    let uptime = pit::uptime();
+    format!("{}.{:03} seconds ({} ticks)\n", uptime.as_secs(), uptime.subsec_millis(), pit::ticks())
+}
+
+/// Puts /proc at `$/proc`, the kernel does this at boot.
+pub fn mount() {
+    if let Err(error) = VFS.lock().mount(PATH, "proc", Box::new(ProcFs)) {
+        println!("PROC: can't mount at {}: {}", PATH, error);
+    }
+}
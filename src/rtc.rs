@@ -0,0 +1,189 @@
+/* The clock in the CMOS chip, the one that keeps running when the machine is off. It's read through ports 0x70
+   (which register) and 0x71 (its value). The kernel only reads it, setting the clock is up to the BIOS.
+
+   The BIOS decides how the values are stored: BCD or plain binary, and 12 or 24 hour, status register B says
+   which. `now` and `unix_time` are what the rest of the kernel uses, file timestamps and the prompt for example. */
+
+use core::fmt;
+use x86_64::instructions::{interrupts, port::Port};
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+// registers
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+const STATUS_A_UPDATING: u8 = 1 << 7; // the clock is ticking over, the values may be half old half new
+const STATUS_B_24_HOUR: u8 = 1 << 1; // hours go 0-23, otherwise 1-12 with HOUR_PM set in the afternoon
+const STATUS_B_BINARY: u8 = 1 << 2; // values are plain binary instead of BCD
+const HOUR_PM: u8 = 1 << 7;
+
+const WEEKDAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+/// A date and time, in UTC as far as we know (the CMOS doesn't say).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8, // 1-12
+    pub day: u8,   // 1-31
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01 00:00:00.
+    pub fn unix_time(&self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        days as u64 * 86400 + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64
+    }
+
+    /// The day of the week, "Monday" and so on.
+    pub fn weekday(&self) -> &'static str {
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        WEEKDAYS[(days + 4).rem_euclid(7) as usize] // 1970-01-01 was a Thursday
+    }
+
+    /// The other way around from `unix_time`.
+    pub fn from_unix(time: u64) -> Self {
+        let (year, month, day) = civil_from_days((time / 86400) as i64);
+        let seconds = time % 86400;
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+// days since 1970-01-01 for a date, and back. From Howard Hinnant's date algorithms.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn read_register(register: u8) -> u8 {
+    let mut address = Port::<u8>::new(CMOS_ADDRESS);
+    let mut data = Port::<u8>::new(CMOS_DATA);
+    unsafe {
+        address.write(register);
+        data.read()
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+/// Seconds, minutes, hours, day, month and year as they are in the registers.
+fn read_raw() -> [u8; 6] {
+    while read_register(REG_STATUS_A) & STATUS_A_UPDATING != 0 {}
+    [
+        read_register(REG_SECONDS),
+        read_register(REG_MINUTES),
+        read_register(REG_HOURS),
+        read_register(REG_DAY),
+        read_register(REG_MONTH),
+        read_register(REG_YEAR),
+    ]
+}
+
+/// Turns the registers into a date, `status_b` says how they're stored.
+fn decode(raw: [u8; 6], status_b: u8) -> DateTime {
+    let binary = status_b & STATUS_B_BINARY != 0;
+    let convert = |value: u8| if binary { value } else { from_bcd(value) };
+    let [second, minute, hour, day, month, year] = raw;
+    let mut hour = convert(hour & !HOUR_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        hour %= 12; // 12 AM is midnight
+        if raw[2] & HOUR_PM != 0 {
+            hour += 12;
+        }
+    }
+    DateTime {
+        year: 2000 + convert(year) as u16,
+        month: convert(month),
+        day: convert(day),
+        hour,
+        minute: convert(minute),
+        second: convert(second),
+    }
+}
+
+/// Reads the clock.
+pub fn now() -> DateTime {
+    interrupts::without_interrupts(|| {
+        // an update can still start halfway through reading, so read until two in a row are the same
+        let mut raw = read_raw();
+        loop {
+            let again = read_raw();
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+        decode(raw, read_register(REG_STATUS_B))
+    })
+}
+
+/// Seconds since 1970, what file timestamps are stored as.
+pub fn unix_time() -> u64 {
+    now().unix_time()
+}
+
+#[test_case]
+fn test_unix_time() {
+    let date = DateTime { year: 2024, month: 2, day: 29, hour: 13, minute: 45, second: 7 };
+    assert_eq!(date.unix_time(), 1_709_214_307);
+    assert_eq!(DateTime::from_unix(date.unix_time()), date);
+    assert_eq!(alloc::format!("{}", DateTime::from_unix(0)), "1970-01-01 00:00:00");
+    assert_eq!(date.weekday(), "Thursday");
+}
+
+#[test_case]
+fn test_decode() {
+    let date = DateTime { year: 2024, month: 12, day: 31, hour: 23, minute: 59, second: 58 };
+    let binary_24 = STATUS_B_BINARY | STATUS_B_24_HOUR;
+    assert_eq!(decode([58, 59, 23, 31, 12, 24], binary_24), date);
+    assert_eq!(decode([0x58, 0x59, 0x23, 0x31, 0x12, 0x24], STATUS_B_24_HOUR), date);
+    assert_eq!(decode([0x58, 0x59, HOUR_PM | 0x11, 0x31, 0x12, 0x24], 0), date); // 11 PM
+    assert_eq!(decode([58, 59, 12, 31, 12, 24], STATUS_B_BINARY).hour, 0); // 12 AM
+    assert_eq!(decode([58, 59, HOUR_PM | 12, 31, 12, 24], STATUS_B_BINARY).hour, 12); // 12 PM
+}
@@ -1,17 +1,49 @@
 /*Yeah uhh, dont remember this being here, do i have alzheimers? no ofc not! */
 
+use crate::interrupts::PICS;
 use lazy_static::lazy_static;
 use spin::Mutex;
 use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
+
+const COM1: u16 = 0x3F8;
+const COM1_IRQ: u8 = 4;
+const LINE_STATUS: u16 = COM1 + 5;
+const DATA_READY: u8 = 1; // line status bit, a received byte is waiting
 
 lazy_static! {
     pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+        let mut serial_port = unsafe { SerialPort::new(COM1) }; // init() also turns on the "byte received" interrupt
         serial_port.init();
         Mutex::new(serial_port)
     };
 }
 
+/// Sets up COM1 and lets its interrupt (IRQ4) through the PIC, so typed bytes reach the serial shell.
+///
+/// Call after the PICs are initialized.
+pub fn init() {
+    lazy_static::initialize(&SERIAL1);
+    unsafe {
+        let mut pics = PICS.lock();
+        let [master, slave] = pics.read_masks();
+        pics.write_masks(master & !(1 << COM1_IRQ), slave);
+    }
+}
+
+/// The next byte that came in on COM1, if there is one. Only for the interrupt handler.
+pub(crate) fn try_receive() -> Option<u8> {
+    let mut status = Port::<u8>::new(LINE_STATUS);
+    let mut data = Port::<u8>::new(COM1);
+    unsafe {
+        if status.read() & DATA_READY != 0 {
+            Some(data.read())
+        } else {
+            None
+        }
+    }
+}
+
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
@@ -1,172 +1,791 @@
-/* The infamous STBFS, its quite simple actually, its an in-memory filesystem. */
-
-/* todo:
-    remove unnecesary imports
-    make the filesystem not in-memory
-    make cd.. work 
-*/
-use crate::{print, println, vga_buffer::{print_shutdown}}; 
-use alloc::string::String;
+/* The infamous STBFS, its quite simple actually, the whole tree lives in memory and if there's a disk
+   attached every change is written straight through to it (the on-disk format is in stbfs-core/src/disk.rs).
+   Every file and directory is a `Node` in one big arena (`Filesystem::nodes`) and gets a `NodeId` that never
+   changes, directories just keep the ids of their children. That way moving something never copies it and an
+   open `FileHandle` stays good wherever its file goes.
+
+   Paths look like `$/kernl/stbos.uff`: `$/` is the root, `.` is the directory itself, `..` the parent,
+   and anything not starting with `$` is relative to the current directory.
+
+   Every node also has `Metadata`: permission bits, an owner and when it was made and last changed, with the
+   time coming from the CMOS clock. The operations check the bits against `Filesystem::user`, which is always
+   root for now, so nothing is refused until there are other users.
+
+   The shell doesn't use this directly anymore, `FS` is mounted at `$/` in the VFS (see vfs.rs), and its current
+   directory is just an absolute path (`Shell::cwd`). */
+
+use crate::{ata, block::BlockDevice, fat, println, vfs::{self, FileSystem, FileType, Ino, Stat, VfsError, VFS}};
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use core::fmt;
+use disk::{default_mode, DirEntry, DiskError, DiskFs, Inode, InodeKind, MAX_NAME_LEN, ROOT_INODE};
 use lazy_static::lazy_static;
 use spin::Mutex;
-use crate::alloc::string::ToString;
-use core::{
-    pin::Pin,
-    task::{Context, Poll},
-};
-use futures_util::{
-    stream::{Stream, StreamExt},
-    task::AtomicWaker,
-};
-use x86_64::instructions::hlt;
-use alloc::{boxed::Box, vec, vec::Vec, rc::Rc};
-
-// Define a file structure
-#[derive(Clone)]
-pub struct File {
-    name: String,
-    content: String,
-}
-
-// Define a directory structure
-#[derive(Clone)]
-pub struct Directory {
-    name: String,
-    files: Vec<File>,
-    subdirectories: Vec<Directory>,
-    parent: Option<usize>,
-}
-
-// Create the root directory
-lazy_static! {
-    static ref ROOT: Mutex<Directory> = Mutex::new(Directory {
-        name: "$/".to_string(),
-        files: vec![
-            File {
-                name: "file1.txt".to_string(),
-                content: "This is file 1.".to_string(),
-            },
-            File {
-                name: "file2.txt".to_string(),
-                content: "This is file 2.".to_string(),
-            },
-        ],
-        subdirectories: vec![
-            Directory {
-                name: "kernl".to_string(),
-                files: vec![File {
-                    name: "stbos.uff".to_string(),
-                    content: "This is the Unreadable File Format, UFF for short".to_string(),
-                }],
-                subdirectories: vec![],
-                parent: Some(0), // Set the parent to the index of the parent directory ("$/")
-            },
-        ],
-        parent: None, // The root directory has no parent.
-    });
-}
-
-// Function to list files in the current directory
-pub fn ls() {
-    let current_directory = ROOT.lock();
-    println!("Contents of directory '{}':", current_directory.name);
-    for file in &current_directory.files {
-        println!("File: {}", file.name);
-    }
-    for subdir in &current_directory.subdirectories {
-        println!("Directory: {}", subdir.name);
-    }
-}
-
-pub fn cat(filename: &str) {
-    let current_directory = ROOT.lock();
-    if let Some(file) = current_directory.files.iter().find(|f| f.name == filename) {
-        println!("\n{}", file.content);
-    } else {
-        println!("\nFile '{}' not found.", filename);
-    }
-}
-
-// Function to change the current directory
-pub fn cd(new_directory: &str) {
-    let mut current_directory = ROOT.lock();
-
-    if new_directory == ".." {
-        if let Some(parent_index) = current_directory.parent {
-            // Print some debug information
-            println!("Changing to parent directory (index: {})", parent_index);
-            println!("Current directory name: {}", current_directory.name);
-            println!("Parent directory name: {}", current_directory.subdirectories[parent_index].name);
-        
-            // Change the current directory to the parent directory
-            current_directory.parent = current_directory.subdirectories[parent_index].parent;
-            *current_directory = current_directory.subdirectories[parent_index].clone();
-        
-            println!("New current directory name: {}", current_directory.name);
+
+pub use stbfs_core::disk;
+
+/// The biggest a file can get, the most an inode on the disk can point at.
+pub const MAX_FILE_SIZE: usize = disk::MAX_FILE_BLOCKS * crate::block::BLOCK_SIZE;
+
+/// The disk STBFS is saved to.
+pub type Disk = DiskFs<Box<dyn BlockDevice + Send>>;
+
+/// Handle to a node in the filesystem, stays the same for as long as the node exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+pub enum NodeKind {
+    File { content: Vec<u8> }, // any bytes, not just text
+    Directory { children: Vec<NodeId> },
+}
+
+/// The user id of root, who gets to do everything.
+pub const ROOT_USER: u16 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub mode: u16,     // permission bits like 0o644, owner then group then everyone else
+    pub owner: u16,    // user id
+    pub created: u64,  // unix seconds
+    pub modified: u64, // unix seconds, changes when a file is written or a directory's entries change
+}
+
+impl Metadata {
+    fn new(kind: InodeKind, owner: u16) -> Self {
+        let now = crate::rtc::unix_time();
+        Metadata {
+            mode: default_mode(kind),
+            owner,
+            created: now,
+            modified: now,
+        }
+    }
+
+    fn from_inode(inode: &Inode) -> Self {
+        Metadata {
+            mode: inode.mode,
+            owner: inode.owner,
+            created: inode.created,
+            modified: inode.modified,
+        }
+    }
+}
+
+/// What an operation wants to do with a node, the values are the matching rwx bits of a mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read = 4,
+    Write = 2,
+    Execute = 1, // for directories: look things up in it
+}
+
+pub struct Node {
+    pub name: String,
+    pub parent: Option<NodeId>, // None only for the root
+    pub kind: NodeKind,
+    pub meta: Metadata,
+}
+
+impl Node {
+    pub fn is_dir(&self) -> bool {
+        match self.kind {
+            NodeKind::Directory { .. } => true,
+            NodeKind::File { .. } => false,
+        }
+    }
+
+    /// Size in bytes, 0 for directories.
+    pub fn size(&self) -> usize {
+        match &self.kind {
+            NodeKind::File { content } => content.len(),
+            NodeKind::Directory { .. } => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    InvalidName,
+    NameTooLong,
+    NotEmpty,
+    InvalidMove,
+    InvalidSeek,
+    PermissionDenied,
+    Disk(DiskError),
+}
+
+impl From<DiskError> for FsError {
+    fn from(error: DiskError) -> Self {
+        FsError::Disk(error)
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            FsError::NotFound => "no such file or directory",
+            FsError::NotADirectory => "not a directory",
+            FsError::IsADirectory => "is a directory",
+            FsError::AlreadyExists => "already exists",
+            FsError::InvalidName => "invalid name",
+            FsError::NameTooLong => "name too long",
+            FsError::NotEmpty => "directory not empty",
+            FsError::InvalidMove => "a directory can't go inside itself",
+            FsError::InvalidSeek => "can't seek before the start of the file",
+            FsError::PermissionDenied => "permission denied",
+            FsError::Disk(error) => return write!(f, "{}", error),
+        };
+        f.write_str(message)
+    }
+}
+
+pub struct Filesystem {
+    nodes: Vec<Option<Node>>, // indexed by NodeId, a removed node leaves a None behind so the other ids stay valid
+    disk: Option<Disk>,       // when there is one, NodeId n is stored in inode n
+    user: u16,                // who the operations are done as
+}
+
+impl Default for Filesystem {
+    fn default() -> Self {
+        Filesystem::new()
+    }
+}
+
+impl Filesystem {
+    pub const ROOT: NodeId = NodeId(0);
+
+    /// Creates a filesystem with nothing but an empty root directory.
+    pub fn new() -> Self {
+        Filesystem {
+            nodes: vec![Some(Node {
+                name: String::from("$"),
+                parent: None,
+                kind: NodeKind::Directory { children: Vec::new() },
+                meta: Metadata::new(InodeKind::Directory, ROOT_USER),
+            })],
+            disk: None,
+            user: ROOT_USER,
+        }
+    }
+
+    /// Reads the whole tree from a disk, which then keeps getting every change.
+    pub fn load(mut disk: Disk) -> Result<Self, DiskError> {
+        let root = disk.read_inode(ROOT_INODE)?;
+        if root.kind != InodeKind::Directory {
+            return Err(DiskError::Corrupt);
+        }
+        let mut fs = Filesystem::new();
+        if let Some(node) = &mut fs.nodes[Self::ROOT.0] {
+            node.meta = Metadata::from_inode(&root);
+        }
+        let mut pending = vec![Self::ROOT];
+        while let Some(dir) = pending.pop() {
+            let inode = disk.read_inode(dir.0 as u32)?;
+            for entry in disk.read_dir(&inode)? {
+                let id = NodeId(entry.inode as usize);
+                if fs.node(id).is_ok() {
+                    return Err(DiskError::Corrupt); // the same inode twice, the tree has a loop
+                }
+                let child = disk.read_inode(entry.inode)?;
+                let kind = match child.kind {
+                    InodeKind::File => {
+                        NodeKind::File { content: disk.read_data(&child)? }
+                    }
+                    InodeKind::Directory => {
+                        pending.push(id);
+                        NodeKind::Directory { children: Vec::new() }
+                    }
+                    InodeKind::Free => return Err(DiskError::Corrupt),
+                };
+
+                if fs.nodes.len() <= id.0 {
+                    fs.nodes.resize_with(id.0 + 1, || None);
+                }
+                fs.nodes[id.0] = Some(Node {
+                    name: entry.name,
+                    parent: Some(dir),
+                    kind,
+                    meta: Metadata::from_inode(&child),
+                });
+                if let Some(Node { kind: NodeKind::Directory { children }, .. }) = &mut fs.nodes[dir.0] {
+                    children.push(id);
+                }
+            }
+        }
+        fs.disk = Some(disk);
+        Ok(fs)
+    }
+
+    /// Writes the whole tree to a freshly formatted disk and keeps writing every change to it.
+    pub fn attach(&mut self, disk: Disk) -> Result<(), FsError> {
+        if self.nodes.len() > disk.superblock().inode_count as usize {
+            return Err(FsError::Disk(DiskError::NoInodes));
+        }
+        self.disk = Some(disk);
+        for index in 0..self.nodes.len() {
+            if self.nodes[index].is_some() {
+                self.sync(NodeId(index))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops writing changes to the disk and hands it back.
+    pub fn detach(&mut self) -> Option<Disk> {
+        self.disk.take()
+    }
+
+    pub fn disk(&self) -> Option<&Disk> {
+        self.disk.as_ref()
+    }
+
+    /// Writes a node (its inode and its data) to the disk, if there is one.
+    fn sync(&mut self, id: NodeId) -> Result<(), FsError> {
+        let disk = match &mut self.disk {
+            Some(disk) => disk,
+            None => return Ok(()),
+        };
+        let nodes = &self.nodes;
+        let node = nodes.get(id.0).and_then(|node| node.as_ref()).ok_or(FsError::NotFound)?;
+
+        let mut inode = disk.read_inode(id.0 as u32)?;
+        match &node.kind {
+            NodeKind::File { content } => {
+                inode.kind = InodeKind::File;
+                disk.write_data(&mut inode, content)?;
+            }
+            NodeKind::Directory { children } => {
+                let entries: Vec<DirEntry> = children
+                    .iter()
+                    .filter_map(|child| {
+                        let name = nodes[child.0].as_ref()?.name.clone();
+                        Some(DirEntry { inode: child.0 as u32, name })
+                    })
+                    .collect();
+                inode.kind = InodeKind::Directory;
+                disk.write_dir(&mut inode, &entries)?;
+            }
+        }
+        inode.parent = node.parent.unwrap_or(id).0 as u32;
+        inode.mode = node.meta.mode;
+        inode.owner = node.meta.owner;
+        inode.created = node.meta.created;
+        inode.modified = node.meta.modified;
+        disk.write_inode(id.0 as u32, &inode)?;
+        disk.flush()?;
+        Ok(())
+    }
+
+    /// Who the operations are done as from now on. There's only root for now.
+    pub fn set_user(&mut self, user: u16) {
+        self.user = user;
+    }
+
+    /// Whether the current user may do `access` to a node. Root may always, otherwise the owner
+    /// gets the owner bits of the mode and everyone else the last three.
+    pub fn check(&self, id: NodeId, access: Access) -> Result<(), FsError> {
+        if self.user == ROOT_USER {
+            return Ok(());
+        }
+        let meta = &self.node(id)?.meta;
+        let bits = if meta.owner == self.user { meta.mode >> 6 } else { meta.mode };
+        if bits & access as u16 != 0 {
+            Ok(())
         } else {
-            println!("\nAlready at the root directory.");
+            Err(FsError::PermissionDenied)
         }
-         
-    } else {
-        if let Some(sub_index) = current_directory.subdirectories.iter().position(|dir| dir.name == new_directory) {
-            // Print some debug information
-            println!("Changing to subdirectory (index: {})", sub_index);
+    }
+
+    /// Marks a node as changed just now.
+    fn touch(&mut self, id: NodeId) -> Result<(), FsError> {
+        self.node_mut(id)?.meta.modified = crate::rtc::unix_time();
+        Ok(())
+    }
+
+    /// Changes the permission bits, only the owner and root can.
+    pub fn set_mode(&mut self, id: NodeId, mode: u16) -> Result<(), FsError> {
+        self.change_meta(id, |meta| meta.mode = mode & 0o777)
+    }
+
+    /// Gives a node to another user, only the owner and root can.
+    pub fn set_owner(&mut self, id: NodeId, owner: u16) -> Result<(), FsError> {
+        self.change_meta(id, |meta| meta.owner = owner)
+    }
+
+    fn change_meta(&mut self, id: NodeId, change: impl FnOnce(&mut Metadata)) -> Result<(), FsError> {
+        let user = self.user;
+        let meta = &mut self.node_mut(id)?.meta;
+        if user != ROOT_USER && meta.owner != user {
+            return Err(FsError::PermissionDenied);
+        }
+        change(meta);
+        self.sync(id)
+    }
+
+    pub fn node(&self, id: NodeId) -> Result<&Node, FsError> {
+        self.nodes.get(id.0).and_then(|node| node.as_ref()).ok_or(FsError::NotFound)
+    }
 
-            // Change the current directory to the specified subdirectory
-            current_directory.parent = Some(sub_index);
-            *current_directory = current_directory.subdirectories[sub_index].clone();
+    fn node_mut(&mut self, id: NodeId) -> Result<&mut Node, FsError> {
+        self.nodes.get_mut(id.0).and_then(|node| node.as_mut()).ok_or(FsError::NotFound)
+    }
+
+    /// The ids of everything in a directory.
+    pub fn children(&self, dir: NodeId) -> Result<&[NodeId], FsError> {
+        match &self.node(dir)?.kind {
+            NodeKind::Directory { children } => Ok(children),
+            NodeKind::File { .. } => Err(FsError::NotADirectory),
+        }
+    }
+
+    /// Finds the entry called `name` in a directory.
+    pub fn lookup(&self, dir: NodeId, name: &str) -> Result<NodeId, FsError> {
+        self.children(dir)?
+            .iter()
+            .copied()
+            .find(|child| self.nodes[child.0].as_ref().is_some_and(|node| node.name == name))
+            .ok_or(FsError::NotFound)
+    }
+
+    /// Turns a path into a node, relative paths start at `cwd`.
+    pub fn resolve(&self, cwd: NodeId, path: &str) -> Result<NodeId, FsError> {
+        let (mut current, rest) = self.start_of(cwd, path);
+        for component in rest.split('/') {
+            current = self.step(current, component)?;
+        }
+        Ok(current)
+    }
+
+    /// Resolves everything but the last part of a path, for creating things.
+    ///
+    /// Returns the directory the last part lives in and the last part itself.
+    pub fn resolve_parent<'p>(&self, cwd: NodeId, path: &'p str) -> Result<(NodeId, &'p str), FsError> {
+        let path = path.trim_end_matches('/');
+        let (dir, name) = match path.rfind('/') {
+            Some(slash) => (self.resolve(cwd, &path[..=slash])?, &path[slash + 1..]),
+            None if path.starts_with('$') => return Err(FsError::InvalidName), // the root has no parent
+            None => (cwd, path),
+        };
+        if !self.node(dir)?.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+        Ok((dir, name))
+    }
+
+    /// Splits off the `$/` of an absolute path, picking the directory the rest is relative to.
+    fn start_of<'p>(&self, cwd: NodeId, path: &'p str) -> (NodeId, &'p str) {
+        if path == "$" || path.starts_with("$/") {
+            (Self::ROOT, &path[1..])
         } else {
-            println!("\nDirectory '{}' not found.", new_directory);
+            (cwd, path)
+        }
+    }
+
+    /// Moves one path component from `current`.
+    fn step(&self, current: NodeId, component: &str) -> Result<NodeId, FsError> {
+        match component {
+            "" | "." => {
+                self.children(current)?; // `file.txt/.` isn't a thing
+                Ok(current)
+            }
+            ".." => {
+                self.children(current)?;
+                Ok(self.node(current)?.parent.unwrap_or(Self::ROOT)) // `..` of the root is the root
+            }
+            name => self.lookup(current, name),
+        }
+    }
+
+    /// The absolute path of a node, like `$/kernl/stbos.uff`.
+    pub fn path_of(&self, id: NodeId) -> String {
+        let mut names = Vec::new();
+        let mut current = Some(id);
+        while let Some(node) = current.and_then(|id| self.node(id).ok()) {
+            if node.parent.is_some() {
+                names.push(node.name.as_str());
+            }
+            current = node.parent;
+        }
+        names.reverse();
+        let mut path = String::from("$/");
+        path.push_str(&names.join("/"));
+        path
+    }
+
+    pub fn create_dir(&mut self, parent: NodeId, name: &str) -> Result<NodeId, FsError> {
+        self.insert(parent, name, NodeKind::Directory { children: Vec::new() }, InodeKind::Directory)
+    }
+
+    pub fn create_file(&mut self, parent: NodeId, name: &str, content: &[u8]) -> Result<NodeId, FsError> {
+        self.insert(parent, name, NodeKind::File { content: content.to_vec() }, InodeKind::File)
+    }
+
+    fn insert(&mut self, parent: NodeId, name: &str, kind: NodeKind, inode_kind: InodeKind) -> Result<NodeId, FsError> {
+        self.check(parent, Access::Write)?;
+        if !is_valid_name(name) {
+            return Err(FsError::InvalidName);
+        }
+        if name.len() > MAX_NAME_LEN {
+            return Err(FsError::NameTooLong);
+        }
+        match Filesystem::lookup(self, parent, name) {
+            Ok(_) => return Err(FsError::AlreadyExists),
+            Err(FsError::NotFound) => {}
+            Err(error) => return Err(error),
+        }
+
+        let id = self.free_slot()?;
+        self.nodes[id.0] = Some(Node {
+            name: String::from(name),
+            parent: Some(parent),
+            kind,
+            meta: Metadata::new(inode_kind, self.user),
+        });
+        if let NodeKind::Directory { children } = &mut self.node_mut(parent)?.kind {
+            children.push(id);
+        }
+        self.touch(parent)?;
+        self.sync(id)?;
+        self.sync(parent)?;
+        Ok(id)
+    }
+
+    /// Finds an unused NodeId, making room for a new one if there's none.
+    fn free_slot(&mut self) -> Result<NodeId, FsError> {
+        if let Some(index) = self.nodes.iter().position(|node| node.is_none()) {
+            return Ok(NodeId(index));
+        }
+        if let Some(disk) = &self.disk {
+            if self.nodes.len() >= disk.superblock().inode_count as usize {
+                return Err(FsError::Disk(DiskError::NoInodes));
+            }
+        }
+        self.nodes.push(None);
+        Ok(NodeId(self.nodes.len() - 1))
+    }
+
+    /// The content of a file.
+    pub fn read(&self, file: NodeId) -> Result<&[u8], FsError> {
+        let content = self.content(file)?;
+        self.check(file, Access::Read)?;
+        Ok(content)
+    }
+
+    fn content(&self, file: NodeId) -> Result<&[u8], FsError> {
+        match &self.node(file)?.kind {
+            NodeKind::File { content } => Ok(content),
+            NodeKind::Directory { .. } => Err(FsError::IsADirectory),
+        }
+    }
+
+    /// The content of a file to change it, if the user may.
+    fn content_mut(&mut self, file: NodeId) -> Result<&mut Vec<u8>, FsError> {
+        self.content(file)?;
+        self.check(file, Access::Write)?;
+        self.touch(file)?;
+        match &mut self.node_mut(file)?.kind {
+            NodeKind::File { content } => Ok(content),
+            NodeKind::Directory { .. } => Err(FsError::IsADirectory),
+        }
+    }
+
+    /// Replaces the content of a file.
+    pub fn write(&mut self, file: NodeId, content: &[u8]) -> Result<(), FsError> {
+        *self.content_mut(file)? = content.to_vec();
+        self.sync(file)
+    }
+
+    /// Size of a file in bytes.
+    pub fn size(&self, file: NodeId) -> Result<usize, FsError> {
+        Ok(self.content(file)?.len())
+    }
+
+    /// Reads from `offset` into `buf`, returns how many bytes that was (0 at the end of the file).
+    pub fn read_at(&self, file: NodeId, offset: usize, buf: &mut [u8]) -> Result<usize, FsError> {
+        let content = self.read(file)?;
+        let start = offset.min(content.len());
+        let count = buf.len().min(content.len() - start);
+        buf[..count].copy_from_slice(&content[start..start + count]);
+        Ok(count)
+    }
+
+    /// Writes `data` at `offset`, the file grows if needed (with zeros if `offset` is past the end), up to
+    /// `MAX_FILE_SIZE`.
+    pub fn write_at(&mut self, file: NodeId, offset: usize, data: &[u8]) -> Result<usize, FsError> {
+        let end = match offset.checked_add(data.len()) {
+            Some(end) if end <= MAX_FILE_SIZE => end,
+            _ => return Err(FsError::Disk(DiskError::FileTooBig)),
+        };
+        let content = self.content_mut(file)?;
+        if content.len() < end {
+            content.resize(end, 0);
+        }
+        content[offset..end].copy_from_slice(data);
+        self.sync(file)?;
+        Ok(data.len())
+    }
+
+    /// Cuts a file down to `len` bytes, or makes it longer with zeros (up to `MAX_FILE_SIZE`).
+    pub fn truncate(&mut self, file: NodeId, len: usize) -> Result<(), FsError> {
+        if len > MAX_FILE_SIZE {
+            return Err(FsError::Disk(DiskError::FileTooBig));
+        }
+        self.content_mut(file)?.resize(len, 0);
+        self.sync(file)
+    }
+
+    /// Opens a file for reading and writing at a position, starting at the beginning.
+    pub fn open(&self, file: NodeId) -> Result<FileHandle, FsError> {
+        self.content(file)?; // directories can't be opened
+        Ok(FileHandle { file, position: 0 })
+    }
+
+    /// Removes a file or an empty directory from `dir`.
+    pub fn remove(&mut self, dir: NodeId, name: &str) -> Result<(), FsError> {
+        self.check(dir, Access::Write)?;
+        let id = Filesystem::lookup(self, dir, name)?;
+        if let NodeKind::Directory { children } = &self.node(id)?.kind {
+            if !children.is_empty() {
+                return Err(FsError::NotEmpty);
+            }
+        }
+        self.nodes[id.0] = None;
+        if let NodeKind::Directory { children } = &mut self.node_mut(dir)?.kind {
+            children.retain(|child| *child != id);
+        }
+        if let Some(disk) = &mut self.disk {
+            let mut inode = disk.read_inode(id.0 as u32)?;
+            disk.free_data(&mut inode)?;
+            disk.write_inode(id.0 as u32, &Inode::new(InodeKind::Free, ROOT_INODE))?;
         }
+        self.touch(dir)?;
+        self.sync(dir)
     }
+
+    /// Moves `name` in `dir` to `new_name` in `new_dir`, it keeps its NodeId so nothing gets copied.
+    pub fn rename(&mut self, dir: NodeId, name: &str, new_dir: NodeId, new_name: &str) -> Result<(), FsError> {
+        if !is_valid_name(new_name) {
+            return Err(FsError::InvalidName);
+        }
+        if new_name.len() > MAX_NAME_LEN {
+            return Err(FsError::NameTooLong);
+        }
+        self.check(dir, Access::Write)?;
+        self.check(new_dir, Access::Write)?;
+        let id = Filesystem::lookup(self, dir, name)?;
+        match Filesystem::lookup(self, new_dir, new_name) {
+            Ok(existing) if existing == id => return Ok(()), // moved onto itself
+            Ok(_) => return Err(FsError::AlreadyExists),
+            Err(FsError::NotFound) => {}
+            Err(error) => return Err(error),
+        }
+        // a directory can't end up below itself, it'd be cut off from the root
+        let mut current = Some(new_dir);
+        while let Some(ancestor) = current {
+            if ancestor == id {
+                return Err(FsError::InvalidMove);
+            }
+            current = self.node(ancestor)?.parent;
+        }
+
+        if let NodeKind::Directory { children } = &mut self.node_mut(dir)?.kind {
+            children.retain(|child| *child != id);
+        }
+        if let NodeKind::Directory { children } = &mut self.node_mut(new_dir)?.kind {
+            children.push(id);
+        }
+        let node = self.node_mut(id)?;
+        node.name = String::from(new_name);
+        node.parent = Some(new_dir);
+        self.touch(dir)?;
+        self.touch(new_dir)?;
+        self.sync(id)?;
+        self.sync(dir)?;
+        if new_dir != dir {
+            self.sync(new_dir)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where `FileHandle::seek` counts from, like `std::io::SeekFrom`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(usize),
+    End(isize),
+    Current(isize),
 }
 
-pub fn mkdir(new_directory: &str) {
-    let mut current_directory = ROOT.lock();
+/// An open file with a position, reads and writes continue where the last one stopped.
+///
+/// It only remembers the NodeId, so every call gets the filesystem it was opened on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileHandle {
+    file: NodeId,
+    position: usize,
+}
+
+impl FileHandle {
+    pub fn node(&self) -> NodeId {
+        self.file
+    }
 
-    // Check if the new directory name is empty
-    if new_directory.is_empty() {
-        println!("\nDirectory name cannot be empty.");
-        return;
+    pub fn position(&self) -> usize {
+        self.position
     }
 
-    // Check if the new directory name already exists
-    if current_directory.subdirectories.iter().any(|dir| dir.name == new_directory) {
-        println!("\nDirectory '{}' already exists.", new_directory);
-        return;
+    pub fn read(&mut self, fs: &Filesystem, buf: &mut [u8]) -> Result<usize, FsError> {
+        let count = fs.read_at(self.file, self.position, buf)?;
+        self.position += count;
+        Ok(count)
     }
 
-    // Create the new directory and add it to the current directory
-    current_directory.subdirectories.push(Directory {
-        name: new_directory.to_string(),
-        files: vec![],
-        subdirectories: vec![],
-        parent: Some(0), // Set the parent to the current directory
-    });
+    pub fn write(&mut self, fs: &mut Filesystem, data: &[u8]) -> Result<usize, FsError> {
+        let count = fs.write_at(self.file, self.position, data)?;
+        self.position += count;
+        Ok(count)
+    }
 
-    println!("\nDirectory '{}' created.", new_directory);
+    /// Moves the position and returns the new one. Going past the end is fine, before the start isn't.
+    pub fn seek(&mut self, fs: &Filesystem, from: SeekFrom) -> Result<usize, FsError> {
+        let (base, offset) = match from {
+            SeekFrom::Start(offset) => (0, offset as isize),
+            SeekFrom::End(offset) => (fs.size(self.file)?, offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+        let position = match (base as isize).checked_add(offset) {
+            Some(position) if position >= 0 => position,
+            _ => return Err(FsError::InvalidSeek),
+        };
+        self.position = position as usize;
+        Ok(self.position)
+    }
 }
 
-pub fn touch(filename: &str, content: &str) {
-    let mut current_directory = ROOT.lock();
+impl FileSystem for Filesystem {
+    fn fs_type(&self) -> &'static str {
+        "stbfs"
+    }
+
+    fn root(&self) -> Ino {
+        Self::ROOT.0 as Ino
+    }
+
+    fn lookup(&mut self, dir: Ino, name: &str) -> Result<Ino, VfsError> {
+        self.check(NodeId(dir as usize), Access::Execute)?;
+        Ok(Filesystem::lookup(self, NodeId(dir as usize), name)?.0 as Ino)
+    }
+
+    fn stat(&mut self, node: Ino) -> Result<Stat, VfsError> {
+        let node = self.node(NodeId(node as usize))?;
+        Ok(Stat {
+            kind: if node.is_dir() { FileType::Directory } else { FileType::File },
+            size: node.size(),
+            mode: node.meta.mode,
+            owner: node.meta.owner,
+            created: node.meta.created,
+            modified: node.meta.modified,
+        })
+    }
+
+    fn read(&mut self, file: Ino) -> Result<Vec<u8>, VfsError> {
+        Ok(Filesystem::read(self, NodeId(file as usize))?.to_vec())
+    }
+
+    fn write(&mut self, file: Ino, data: &[u8]) -> Result<(), VfsError> {
+        Ok(Filesystem::write(self, NodeId(file as usize), data)?)
+    }
+
+    fn readdir(&mut self, dir: Ino) -> Result<Vec<vfs::DirEntry>, VfsError> {
+        self.check(NodeId(dir as usize), Access::Read)?;
+        let children = self.children(NodeId(dir as usize))?;
+        Ok(children
+            .iter()
+            .filter_map(|child| self.node(*child).ok())
+            .map(|node| vfs::DirEntry {
+                name: node.name.clone(),
+                kind: if node.is_dir() { FileType::Directory } else { FileType::File },
+            })
+            .collect())
+    }
+
+    fn create(&mut self, dir: Ino, name: &str) -> Result<Ino, VfsError> {
+        Ok(self.create_file(NodeId(dir as usize), name, b"")?.0 as Ino)
+    }
+
+    fn mkdir(&mut self, dir: Ino, name: &str) -> Result<Ino, VfsError> {
+        Ok(self.create_dir(NodeId(dir as usize), name)?.0 as Ino)
+    }
 
-    // Check if the new file name is empty
-    if filename.is_empty() {
-        println!("\nFile name cannot be empty.");
-        return;
+    fn unlink(&mut self, dir: Ino, name: &str) -> Result<(), VfsError> {
+        Ok(self.remove(NodeId(dir as usize), name)?)
     }
 
-    // Check if the new file name already exists
-    if current_directory.files.iter().any(|file| file.name == filename) {
-        println!("\nFile '{}' already exists.", filename);
-        return;
+    fn rename(&mut self, dir: Ino, name: &str, new_dir: Ino, new_name: &str) -> Result<(), VfsError> {
+        Ok(Filesystem::rename(self, NodeId(dir as usize), name, NodeId(new_dir as usize), new_name)?)
     }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/') && !name.starts_with('$')
+}
+
+// Create the filesystem with the files that come with the OS
+lazy_static! {
+    pub static ref FS: Mutex<Filesystem> = {
+        let mut fs = Filesystem::new();
+        let root = Filesystem::ROOT;
+        fs.create_file(root, "file1.txt", b"This is file 1.").unwrap();
+        fs.create_file(root, "file2.txt", b"This is file 2.").unwrap();
+        let kernl = fs.create_dir(root, "kernl").unwrap();
+        fs.create_file(kernl, "stbos.uff", b"This is the Unreadable File Format, UFF for short").unwrap();
+        Mutex::new(fs)
+    };
+}
+
+/// Loads STBFS from a disk, replacing whatever is in `FS` right now.
+pub fn mount(device: Box<dyn BlockDevice + Send>) -> Result<(), DiskError> {
+    let fs = Filesystem::load(DiskFs::mount(device)?)?;
+    *FS.lock() = fs;
+    Ok(())
+}
 
-    // Create the new file and add it to the current directory
-    current_directory.files.push(File {
-        name: filename.to_string(),
-        content: content.to_string(),
-    });
+/// Formats a disk and copies the current tree onto it. From then on every change gets written to it.
+pub fn format(device: Box<dyn BlockDevice + Send>) -> Result<(), FsError> {
+    let disk = DiskFs::format(device)?;
+    FS.lock().attach(disk)
+}
+
+/// The disk STBFS goes on, the first data disk that isn't taken by a FAT volume.
+pub fn find_disk() -> Option<ata::AtaDrive> {
+    ata::data_disks()
+        .into_iter()
+        .find_map(|mut drive| if fat::is_fat(&mut drive) { None } else { Some(drive) })
+}
 
-    println!("\nFile '{}' created.", filename);
-}
\ No newline at end of file
+/// Looks for the STBFS disk at boot and mounts it.
+pub fn mount_data_disk() {
+    let drive = match find_disk() {
+        Some(drive) => drive,
+        None => return println!("STBFS: no data disk found, files only live in memory"),
+    };
+    println!("STBFS: found disk '{}' ({} KiB)", drive.model(), drive.block_count() / 2);
+    let name = drive.name();
+    match mount(Box::new(drive)) {
+        Ok(()) => {
+            VFS.lock().set_source(vfs::ROOT_PATH, name);
+            println!("STBFS: mounted");
+        }
+        Err(DiskError::NotStbfs) => println!("STBFS: disk isn't formatted, use /mkfs to format it"),
+        Err(error) => println!("STBFS: can't mount disk: {}", error),
+    }
+}
@@ -1,7 +1,27 @@
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use super::{timer, Task, TaskId};
+use alloc::{collections::BTreeMap, sync::Arc, task::Wake, vec::Vec};
 use core::task::{Context, Poll, Waker};
 use crossbeam_queue::ArrayQueue;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+/// What /proc/tasks shows about a task that's still running.
+#[derive(Debug, Clone, Copy)]
+pub struct TaskInfo {
+    pub id: u64,
+    pub name: &'static str,
+    pub polls: u64,
+}
+
+lazy_static! {
+    // every task any executor has, so we can list them without getting hold of the executor
+    static ref TASKS: Mutex<BTreeMap<TaskId, TaskInfo>> = Mutex::new(BTreeMap::new());
+}
+
+/// All tasks that haven't finished yet.
+pub fn tasks() -> Vec<TaskInfo> {
+    TASKS.lock().values().copied().collect()
+}
 
 pub struct Executor {
     tasks: BTreeMap<TaskId, Task>,
@@ -20,6 +40,11 @@ impl Executor {
 
     pub fn spawn(&mut self, task: Task) {
         let task_id = task.id;
+        TASKS.lock().insert(task_id, TaskInfo {
+            id: task_id.0,
+            name: task.name,
+            polls: 0,
+        });
         if self.tasks.insert(task.id, task).is_some() {
             panic!("task with same ID already in tasks");
         }
@@ -28,6 +53,7 @@ impl Executor {
 
     pub fn run(&mut self) -> ! {
         loop {
+            timer::wake_expired();
             self.run_ready_tasks();
             self.sleep_if_idle();
         }
@@ -50,11 +76,15 @@ impl Executor {
                 .entry(task_id)
                 .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
             let mut context = Context::from_waker(waker);
+            if let Some(info) = TASKS.lock().get_mut(&task_id) {
+                info.polls += 1;
+            }
             match task.poll(&mut context) {
                 Poll::Ready(()) => {
                     // task done -> remove it and its cached waker
                     tasks.remove(&task_id);
                     waker_cache.remove(&task_id);
+                    TASKS.lock().remove(&task_id);
                 }
                 Poll::Pending => {}
             }
@@ -0,0 +1,56 @@
+/* Command lines from the shells on the executor run in a thread of their own. The executor runs every task on
+   thread 0, so a command that takes a while, a program started with /exec most of all, would hold up every other
+   task until it's done: the other shell, sleeps, the keys. The shell's task waits for the thread with short sleeps
+   instead, and keeps reading its input meanwhile, which is how Ctrl+C gets through. */
+
+use super::timer;
+use crate::{commands::Shell, process, thread};
+use alloc::sync::Arc;
+use core::time::Duration;
+use futures_util::{
+    future::{self, Either},
+    stream::{Stream, StreamExt},
+};
+use spin::Mutex;
+
+/// How often a waiting shell checks whether the thread is done.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Runs `run` on the shell in a new thread and gives the shell back when it's done. Everything that comes from
+/// `input` meanwhile goes to `on_input`, which returns true for Ctrl+C: that stops the programs the thread started
+/// (see process::kill_started_by). If there's no room for another thread, `run` runs right here instead.
+pub async fn run<S, F>(shell: Shell, input: &mut S, mut on_input: impl FnMut(S::Item) -> bool, run: F) -> Shell
+where
+    S: Stream + Unpin,
+    F: FnOnce(&mut Shell) + Send + 'static,
+{
+    // shared, so they can be had back if the thread can't start
+    let work = Arc::new(Mutex::new(Some((shell, run))));
+    let handle = thread::spawn("shell", {
+        let work = work.clone();
+        move || {
+            let (mut shell, run) = work.lock().take().expect("the work is taken once");
+            run(&mut shell);
+            shell
+        }
+    });
+    let handle = match handle {
+        Ok(handle) => handle,
+        Err(_) => {
+            let (mut shell, run) = work.lock().take().expect("the thread never started");
+            run(&mut shell);
+            return shell;
+        }
+    };
+
+    let mut interrupted = false;
+    while !handle.is_finished() {
+        if interrupted {
+            process::kill_started_by(handle.id()); // again, in case one was just starting last time
+        }
+        if let Either::Left((Some(item), _)) = future::select(input.next(), timer::sleep(POLL_INTERVAL)).await {
+            interrupted |= on_input(item);
+        }
+    }
+    handle.join()
+}
@@ -1,16 +1,10 @@
-/* This is probably the most important code(except for vga buffer and main), this adds keyboard support and commands! */
+/* This is probably the most important code(except for vga buffer and main), this adds keyboard support and feeds the line editor and the shell! */
 
 // some imports
-use crate::{print, println, task::getcpu::{get_cpu_name, print_cpu_name}, vga_buffer::{print_shutdown, ascii, print_error1, print_all_ascii, print_smiley_face}, stbfs::{ls, cd, mkdir, touch, cat}};
+use super::foreground;
+use crate::{println, commands::{script, Shell}, line_editor::LineEditor, vga_buffer::enable_cursor};
+use alloc::collections::VecDeque;
 use conquer_once::spin::OnceCell;
-use alloc::string::String;
-use lazy_static::lazy_static;
-use spin::Mutex;
-use pc_keyboard::KeyCode;
-use core::arch::asm;
-use core::str::FromStr;
-use alloc::string::ToString;
-use alloc::format;
 use core::{
     pin::Pin,
     task::{Context, Poll},
@@ -20,18 +14,13 @@ use futures_util::{
     stream::{Stream, StreamExt},
     task::AtomicWaker,
 };
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-use x86_64::instructions::hlt;
-use crate::vga_buffer::WRITER;
-use crate::vga_buffer::BUFFER_HEIGHT;
-use bootloader::{BootInfo, entry_point};
-use alloc::{boxed::Box, vec, vec::Vec, rc::Rc};
+use pc_keyboard::{layouts::{self, Us104Key}, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+
+const CTRL_C: char = '\u{3}';
 
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 static WAKER: AtomicWaker = AtomicWaker::new();
 
-const OSVER: &str = "0.9.8.5";
-
 pub(crate) fn add_scancode(scancode: u8) {
     if let Ok(queue) = SCANCODE_QUEUE.try_get() {
         if let Err(_) = queue.push(scancode) {
@@ -81,174 +70,82 @@ impl Stream for ScancodeStream {
     }
 }
 
-pub fn print_binary_character(binary_string: &str) {
-    // Remove the '0b' prefix if present and parse the binary string
-    if let Ok(character) = u8::from_str_radix(&binary_string.replace("0b", ""), 2) {
-        // Convert the u8 value to a char
-        if let Some(ascii_char) = core::char::from_u32(character.into()) {
-            // Print the character
-            println!("\n{}", ascii_char);
-        } else {
-            println!("\nInvalid ASCII character");
+pub async fn print_keypresses() {
+    let mut scancodes = ScancodeStream::new();
+    // MapLettersToUnicode turns Ctrl+<letter> into control characters, the line editor uses those for Ctrl+A/E/U/W
+    let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::MapLettersToUnicode);
+    let mut editor = LineEditor::new(); // lives as long as the shell, so the history stays for the whole session
+    let mut shell = Shell::new(); // the commands themselves live in commands.rs
+    let mut typed_ahead = VecDeque::new(); // keys that came in while a command ran, they're handled after it
+
+    enable_cursor();
+    // the disk's own setup, before the first prompt
+    shell = run_command(shell, &mut scancodes, &mut keyboard, &mut typed_ahead, script::autoexec).await;
+    if shell.editor.is_none() { // unless it left an /edit open, then the prompt comes when that closes
+        editor.start(&shell.prompt());
+    }
+    loop {
+        let key = match typed_ahead.pop_front() {
+            Some(key) => key,
+            None => match next_key(&mut scancodes, &mut keyboard).await {
+                Some(key) => key,
+                None => break,
+            },
+        };
+        if let Some(open_editor) = &mut shell.editor {
+            // a full-screen /edit is open, it gets the keys until it closes
+            if !open_editor.handle_key(key) {
+                shell.editor = None;
+                editor.start(&shell.prompt());
+            }
+            continue;
+        }
+        if let Some(line) = editor.handle_key(key, &shell) {
+            // User pressed Enter, hand the line over to the shell
+            let run = move |shell: &mut Shell| shell.execute(&line);
+            shell = run_command(shell, &mut scancodes, &mut keyboard, &mut typed_ahead, run).await;
+            editor.start(&shell.prompt());
         }
-    } else {
-        println!("\nInvalid binary representation");
     }
 }
 
-
-pub async fn print_keypresses() {
-    let mut scancodes = ScancodeStream::new();
-    let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore);
-    let mut user_input = String::new();  // Buffer to store user input
-    let mut echo_text = String::new();
-    let new_dir = String::new();
-
+/// Reads scancodes until they make up a key.
+async fn next_key(scancodes: &mut ScancodeStream, keyboard: &mut Keyboard<Us104Key, ScancodeSet1>) -> Option<DecodedKey> {
     while let Some(scancode) = scancodes.next().await {
         if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
             if let Some(key) = keyboard.process_keyevent(key_event) {
-                match key {
-                    DecodedKey::Unicode(character) => {
-                        if character == '\n' {
-                            // User pressed Enter
-                            if user_input.trim() == "/shutdown" {
-                                // User typed "/exit," execute shutdown logic
-                                for n in 1..26 {
-                                    println!("\n");
-                                }
-                                print_shutdown();
-                                hlt();
-                                loop {}
-                            } else if user_input.trim() == "/sysinf" {
-                                println!("\n=======System Information========\n");
-                                ascii();
-                                println!("OS: S.T.B. OS by Admiralix      ");
-                                println!("OS VERSION: {} Build 09866      ", OSVER );
-                                if let Some(cpu_name) = get_cpu_name() {
-                                    print_cpu_name(&cpu_name);
-                                } else {
-                                    println!("Failed to retrieve CPU name.");
-                                }
-                                println!("RES: 80x25px                    ");
-                                println!("RAM Size: UNKNOWN");
-                                println!("=================================");
-                            } else if user_input.trim() == "/syshelp" {
-                                println!("\n");
-                                println!("==========System Help==================   \n");
-                                println!("/syshelp = Display's This Information    ");
-                                println!("/cls = Clears the Screen               ");
-                                println!("/sysinf = Shows System Information       ");
-                                println!("/shutdown = 'Shuts' PC down              ");
-                                println!("/echo = Echoes text                      ");
-                                println!("/refr echo = references the echo input   ");
-                                println!("EXPERIMENTAL:                             ");
-                                println!("/cd = change dir.                         ");
-                                println!("/lf = list files                          ");
-                                println!("/sw = show content of files               ");
-                                println!("/mkdir = makes a dir.                     ");
-                                println!("/tch = makes a new file.                  ");
-                                println!("=======================================   ");
-                            } else if user_input.trim() == "/who" {
-                                println!("\nUSER: AOS User");
-                                println!("USER PRIVILEGES: Administrator");
-                            } else if user_input.starts_with("/echo ") {
-                                // Echo command
-                                echo_text = user_input[6..].trim().to_string();
-                                if !echo_text.is_empty() {
-                                    println!("\n{}", echo_text);
-                                }
-                            } else if user_input.trim() == "/refr echo" {
-                                println!("\n--->    {}", echo_text);
-                            } else if user_input.trim() == "/cls" {
-                                for n in 1..26 {
-                                    println!("\n");
-                                }
-                            } else if user_input.trim() == "/lf" {
-                                println!("\n");
-                                ls();
-                            } else if user_input.starts_with("/cd ") {
-                                let new_directory = &user_input[4..].trim();
-                                cd(new_directory);
-                            } else if user_input.starts_with("/sw ") {
-                                let filename = &user_input[4..].trim();
-                                cat(filename);
-                            } else if user_input.starts_with("/mkdir ") {
-                                let filename = &user_input[6..].trim();
-                                mkdir(filename);
-                            } else if user_input.starts_with("/asciitest") {
-                                // print_all_ascii();
-                                print_smiley_face()
-                            } else if user_input.starts_with("*print ") {
-                                // Extract the binary string after the command
-                                let binary_string = &user_input[7..].trim();
-                                print_binary_character(binary_string);
-                            } else if user_input.starts_with("/tch ") {
-                                let input = &user_input[5..].trim(); // Trim additional spaces
-                                let parts: Vec<&str> = input.splitn(2, ' ').collect();
-                            
-                                if parts.len() != 2 {
-                                    println!("\nUsage: /tch <filename> <content>");
-                                } else {
-                                    let filename = parts[0];
-                                    let content = parts[1];
-                                    touch(filename, content);
-                                }
-                            } else if user_input.trim() == "/1000_1C3" {
-                                println!("\nThanks for Using S.T.B. OS!\n");
-                                println!("Admiralix Team:               ");
-                                println!("icewallowpiz - Leader of Project and Lead Programmer\n");
-                                println!("Contributors:                 ");
-                                println!("DAWOOD - Lead Website Designer\n");
-                                println!("Pr1thv1 - Fixed a Keyboard problem");
-                                println!("Special Thanks to:");
-                                println!("Snneezou");
-                                
-
-                            } else if character == '\u{0008}' {
-                                // Handle Backspace key
-                                if !user_input.is_empty() {
-                                    // Remove the last character from the input buffer
-                                    user_input.pop();
-                                    // Print the backspace character to erase the character on the screen
-                                    // print!("\u{0008} \u{0008}");
-                                }
-                            } else {
-                                // Unknown command
-                                println!("\nUnknown Command: '{}'", user_input.trim());
-                                print_error1();
-                            }
-
-                            // Add a newline character to go to the next line
-                            user_input.clear();  // Clear the input buffer
-
-                        } else if character == '\u{0008}' {
-                            // Handle Backspace key
-                            if !user_input.is_empty() {
-                                // Remove the last character from the input buffer
-                                user_input.pop();
-                                // Print the backspace character to erase the character on the screen
-                                print!("\u{0008} \u{0008}");
-                            }
-                        } else {
-                            // Append typed character to the input buffer
-                            user_input.push(character);
-                            print!("{}", character);
-                        }
-                    }
-                    DecodedKey::RawKey(key) => { // Use RawKey from pc-keyboard crate
-                        // Filter out unwanted keys
-                        match key {
-                            KeyCode::ArrowLeft | KeyCode::ArrowRight | KeyCode::ArrowUp | KeyCode::ArrowDown | KeyCode::Backspace => {
-                                // Ignore arrow keys
-                            }
-                            _ => {
-                                // Handle other raw keys
-                                print!("{:?}", key);
-                            }
-                        }
-                    }
-                }
+                return Some(key);
             }
         }
     }
-}
\ No newline at end of file
+    None
+}
+
+/// Runs a command in a thread of its own so the other tasks go on meanwhile (see foreground.rs). Ctrl+C stops the
+/// programs it started, other keys wait in `typed_ahead`.
+async fn run_command(
+    shell: Shell,
+    scancodes: &mut ScancodeStream,
+    keyboard: &mut Keyboard<Us104Key, ScancodeSet1>,
+    typed_ahead: &mut VecDeque<DecodedKey>,
+    run: impl FnOnce(&mut Shell) + Send + 'static,
+) -> Shell {
+    let on_scancode = |scancode| {
+        let key = match keyboard.add_byte(scancode) {
+            Ok(Some(key_event)) => keyboard.process_keyevent(key_event),
+            _ => None,
+        };
+        match key {
+            Some(DecodedKey::Unicode(CTRL_C)) => {
+                println!("^C");
+                true
+            }
+            Some(key) => {
+                typed_ahead.push_back(key);
+                false
+            }
+            None => false,
+        }
+    };
+    foreground::run(shell, scancodes, on_scancode, run).await
+}
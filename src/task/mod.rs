@@ -5,18 +5,28 @@ use core::sync::atomic::{AtomicU64, Ordering};
 
 pub mod simple_executor;
 pub mod keyboard;
+pub mod serial;
 pub mod executor;
+pub mod timer;
+pub mod foreground;
 mod getcpu;
 
 pub struct Task {
     id: TaskId,
+    name: &'static str,
     future: Pin<Box<dyn Future<Output = ()>>>,
 }
 
 impl Task {
     pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+        Task::with_name("task", future)
+    }
+
+    /// Same as `new`, but the name shows up in /proc/tasks.
+    pub fn with_name(name: &'static str, future: impl Future<Output = ()> + 'static) -> Task {
         Task{
             id: TaskId::new(),
+            name,
             future: Box::pin(future),
         }
     }
@@ -0,0 +1,145 @@
+/* The serial console: bytes typed on COM1 come in through IRQ4, get queued here and feed a second shell that runs
+   next to the keyboard one. With `qemu -serial stdio` that's the terminal qemu was started from, so the OS can be
+   driven without a screen, by a person or by a test feeding it commands. */
+
+use super::foreground;
+use crate::{
+    commands::{Shell, Terminal},
+    println, serial_print,
+};
+use alloc::{collections::VecDeque, string::String};
+use conquer_once::spin::OnceCell;
+use core::{
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::{
+    stream::{Stream, StreamExt},
+    task::AtomicWaker,
+};
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7F; // what most terminals send for the backspace key
+const CTRL_C: u8 = 0x03;
+
+static BYTE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Called by the COM1 interrupt handler for every byte that came in.
+pub(crate) fn add_byte(byte: u8) {
+    if let Ok(queue) = BYTE_QUEUE.try_get() {
+        if queue.push(byte).is_err() {
+            println!("WARNING: serial queue full; dropping input");
+        } else {
+            WAKER.wake();
+        }
+    } // nobody listening yet, the serial shell starts with the executor
+}
+
+pub struct SerialStream {
+    _private: (),
+}
+
+impl Default for SerialStream {
+    fn default() -> Self {
+        SerialStream::new()
+    }
+}
+
+impl SerialStream {
+    pub fn new() -> Self {
+        BYTE_QUEUE
+            .try_init_once(|| ArrayQueue::new(256))
+            .expect("SerialStream::new should only be called once");
+        SerialStream { _private: () }
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        let queue = BYTE_QUEUE.try_get().expect("serial queue not initialized");
+
+        // fast path
+        if let Ok(byte) = queue.pop() {
+            return Poll::Ready(Some(byte));
+        }
+
+        WAKER.register(cx.waker());
+        match queue.pop() {
+            Ok(byte) => {
+                WAKER.take();
+                Poll::Ready(Some(byte))
+            }
+            Err(crossbeam_queue::PopError) => Poll::Pending,
+        }
+    }
+}
+
+/// A shell on COM1. Lines are edited the simple way, typing and backspace, and every finished line goes to the same
+/// `Shell::execute` as the keyboard shell.
+pub async fn serial_shell() {
+    let mut bytes = SerialStream::new();
+    let mut shell = Shell::with_terminal(Terminal::Serial);
+    let mut line = String::new();
+    let mut last = 0u8;
+    let mut typed_ahead = VecDeque::new(); // bytes that came in while a command ran, they're handled after it
+
+    serial_print!("{}", shell.prompt());
+    loop {
+        let byte = match typed_ahead.pop_front() {
+            Some(byte) => byte,
+            None => match bytes.next().await {
+                Some(byte) => byte,
+                None => break,
+            },
+        };
+        match byte {
+            b'\n' if last == b'\r' => {} // \r\n is one Enter
+            b'\r' | b'\n' => {
+                serial_print!("\r\n");
+                let line = mem::take(&mut line);
+                let run = move |shell: &mut Shell| shell.execute(&line);
+                shell = run_command(shell, &mut bytes, &mut typed_ahead, run).await;
+                serial_print!("{}", shell.prompt());
+            }
+            BACKSPACE | DELETE => {
+                if line.pop().is_some() {
+                    serial_print!("\x08 \x08");
+                }
+            }
+            CTRL_C => {
+                line.clear();
+                serial_print!("^C\r\n{}", shell.prompt());
+            }
+            byte if byte.is_ascii_graphic() || byte == b' ' || byte == b'\t' => {
+                line.push(byte as char);
+                serial_print!("{}", byte as char);
+            }
+            _ => {} // escape sequences and other control bytes
+        }
+        last = byte;
+    }
+}
+
+/// Runs a command in a thread of its own so the other tasks go on meanwhile (see foreground.rs). Ctrl+C stops the
+/// programs it started, other bytes wait in `typed_ahead`.
+async fn run_command(
+    shell: Shell,
+    bytes: &mut SerialStream,
+    typed_ahead: &mut VecDeque<u8>,
+    run: impl FnOnce(&mut Shell) + Send + 'static,
+) -> Shell {
+    let on_byte = |byte| {
+        if byte == CTRL_C {
+            serial_print!("^C\r\n");
+            return true;
+        }
+        typed_ahead.push_back(byte);
+        false
+    };
+    foreground::run(shell, bytes, on_byte, run).await
+}
@@ -0,0 +1,73 @@
+/* Sleeping without a busy loop. A `Sleep` future leaves its waker here with the tick it's waiting for, and the
+   executor wakes every one that's due each time around its loop. The executor halts between interrupts, and the
+   timer interrupt comes every millisecond, so it's never more than a tick late. The interrupt handler itself
+   doesn't touch any of this, so nothing here has to worry about being interrupted while the lock is held. */
+
+use crate::pit;
+use alloc::collections::BTreeMap;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+lazy_static! {
+    // (the tick to wake up at, which Sleep) -> its waker, the earliest comes first
+    static ref SLEEPERS: Mutex<BTreeMap<(u64, u64), Waker>> = Mutex::new(BTreeMap::new());
+}
+
+/// Waits for at least `duration`, while other tasks keep running. Only works on the `Executor`, the simple one
+/// never wakes it up.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep::until(pit::ticks() + pit::duration_to_ticks(duration))
+}
+
+pub struct Sleep {
+    deadline: u64,
+    id: u64,
+}
+
+impl Sleep {
+    /// Waits until `pit::ticks()` gets to `deadline`.
+    pub fn until(deadline: u64) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Sleep {
+            deadline,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if pit::ticks() >= self.deadline {
+            return Poll::Ready(());
+        }
+        SLEEPERS.lock().insert((self.deadline, self.id), cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        SLEEPERS.lock().remove(&(self.deadline, self.id)); // given up on before it was due
+    }
+}
+
+/// Wakes every task whose sleep is over, the executor calls this.
+pub(crate) fn wake_expired() {
+    let now = pit::ticks();
+    let mut sleepers = SLEEPERS.lock();
+    while let Some(entry) = sleepers.first_entry() {
+        if entry.key().0 > now {
+            break;
+        }
+        entry.remove().wake();
+    }
+}
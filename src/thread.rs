@@ -0,0 +1,599 @@
+/* Kernel threads. Every thread has its own stack and the timer interrupt switches between them, so a thread stuck
+   in a loop can't hold up the others the way a task that never awaits holds up the executor. The code that booted
+   (kernel_main, which goes on to run the async executor) becomes thread 0 when `init` is called.
+
+   A switch happens in the interrupt entry in the global_asm! below: it pushes every register onto the stack of the
+   thread that got interrupted, hands that stack pointer to the scheduler and pops the registers from whatever
+   stack pointer it gets back, so the `iretq` at the end continues that thread instead. A new thread's stack is
+   made to look like it got interrupted right at the start of `thread_start`.
+
+   Every ready thread gets QUANTUM_TICKS timer ticks in turn. The scheduler only runs with the interrupts off, and
+   never allocates or frees memory while they are: the thread that got interrupted might be holding the heap's
+   lock, and with the interrupts off nothing would ever make it let go. That's why the thread slots and the ready
+   queue are made at their full size in `init`.
+
+   Every slot has its own stack at STACKS_START, mapped in `init` and used by each thread that gets the slot. Below
+   each one is a page that's left unmapped, so a thread that runs off the end of its stack gets a page fault there.
+   That can't be handled on the stack that's full, so it ends in the double fault handler (which has a stack of its
+   own, see gdt.rs) instead of quietly writing over the next stack down.
+
+   Threads that run a user program (process.rs) have a page table of their own and a stack for the interrupts that
+   come in while they're in ring 3, both get put in place on every switch. That stack belongs to the slot as well,
+   it's the one MAX_THREADS slots further up, with a guard page of its own. */
+
+use crate::{
+    gdt,
+    memory::{self, GlobalFrameAllocator},
+    pit,
+};
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
+use core::{arch::global_asm, fmt, mem};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::{
+    instructions::{
+        hlt, interrupts,
+        segmentation::{Segment, CS, SS},
+    },
+    registers::control::Cr3,
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags, PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// Timer ticks a thread gets to run before the next one's turn.
+pub const QUANTUM_TICKS: u64 = 10;
+/// The interrupt `yield_now` uses to get to the scheduler, it's not one the hardware uses.
+pub const YIELD_VECTOR: u8 = 0x81;
+
+/// How many threads there can be at once, counting thread 0 and the idle thread.
+pub const MAX_THREADS: usize = 32;
+const STACK_SIZE: u64 = 64 * 1024;
+const GUARD_SIZE: u64 = 4096; // the unmapped page below every stack
+const STACKS_START: u64 = 0x_5555_0000_0000; // slot 0's guard page, then its stack, then slot 1's guard page...
+const RFLAGS_INTERRUPTS: u64 = 1 << 9;
+const RFLAGS_RESERVED: u64 = 1 << 1; // always set
+
+global_asm!(
+    ".global thread_timer_entry",
+    "thread_timer_entry:",
+    "    push rax",
+    "    lea rax, [rip + thread_timer]",
+    "    jmp thread_switch",
+    "",
+    ".global thread_yield_entry",
+    "thread_yield_entry:",
+    "    push rax",
+    "    lea rax, [rip + thread_yield]",
+    "    jmp thread_switch",
+    "",
+    // rax is pushed and holds the function to call: fn(rsp) -> the rsp to continue with. process.rs uses it too
+    ".global thread_switch",
+    "thread_switch:",
+    "    push rbx",
+    "    push rcx",
+    "    push rdx",
+    "    push rsi",
+    "    push rdi",
+    "    push rbp",
+    "    push r8",
+    "    push r9",
+    "    push r10",
+    "    push r11",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov rdi, rsp",
+    "    call rax",
+    "    mov rsp, rax",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop r11",
+    "    pop r10",
+    "    pop r9",
+    "    pop r8",
+    "    pop rbp",
+    "    pop rdi",
+    "    pop rsi",
+    "    pop rdx",
+    "    pop rcx",
+    "    pop rbx",
+    "    pop rax",
+    "    iretq",
+);
+
+extern "C" {
+    fn thread_timer_entry();
+    fn thread_yield_entry();
+}
+
+/// What's on a thread's stack while it isn't running: the registers thread_switch pushed, then what the CPU pushed
+/// for the interrupt.
+#[repr(C)]
+pub(crate) struct SavedFrame {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rbp: u64,
+    pub(crate) rdi: u64,
+    pub(crate) rsi: u64,
+    pub(crate) rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    pub(crate) rax: u64,
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+/// Where the timer interrupt goes, for the IDT.
+pub(crate) fn timer_entry() -> VirtAddr {
+    VirtAddr::new(thread_timer_entry as *const () as u64)
+}
+
+/// Where YIELD_VECTOR goes, for the IDT.
+pub(crate) fn yield_entry() -> VirtAddr {
+    VirtAddr::new(thread_yield_entry as *const () as u64)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Running,
+    Ready,
+    Joining(ThreadId), // waiting for that one to finish
+    Finished,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            State::Running => f.write_str("running"),
+            State::Ready => f.write_str("ready"),
+            State::Joining(id) => write!(f, "joining {}", id),
+            State::Finished => f.write_str("finished"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    TooManyThreads, // all MAX_THREADS slots are taken
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpawnError::TooManyThreads => write!(f, "can't have more than {} threads", MAX_THREADS),
+        }
+    }
+}
+
+/// What /proc/threads shows about a thread.
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    pub name: &'static str,
+    pub state: State,
+    pub ticks: u64, // timer ticks it has been running for
+}
+
+struct Thread {
+    info: ThreadInfo,
+    rsp: u64,              // where its SavedFrame is, while it isn't running
+    page_table: PhysFrame, // the level 4 table, the kernel's unless it's running a process
+    ring3_stack: u64,      // the top of the stack for interrupts from ring 3, 0 unless it's running a process
+}
+
+struct Scheduler {
+    threads: Vec<Option<Thread>>, // MAX_THREADS slots, a thread keeps its slot until it's reaped
+    ready: VecDeque<usize>,       // slots waiting for their turn, not counting the running one or the idle one
+    current: usize,
+    idle: usize, // runs when nothing else can, halting until the next interrupt
+    slice_start: u64,
+    next_id: u64,
+    kernel_page_table: PhysFrame,
+}
+
+lazy_static! {
+    static ref SCHEDULER: Mutex<Option<Scheduler>> = Mutex::new(None);
+}
+
+type Entry = Box<dyn FnOnce() + Send>;
+
+impl Scheduler {
+    fn slot(&self, id: ThreadId) -> Option<usize> {
+        self.threads
+            .iter()
+            .position(|thread| matches!(thread, Some(thread) if thread.info.id == id))
+    }
+
+    fn add(&mut self, name: &'static str, entry: *mut Entry) -> Result<ThreadId, SpawnError> {
+        let slot = self
+            .threads
+            .iter()
+            .position(Option::is_none)
+            .ok_or(SpawnError::TooManyThreads)?;
+        let id = ThreadId(self.next_id);
+        self.next_id += 1;
+        self.threads[slot] = Some(Thread {
+            info: ThreadInfo { id, name, state: State::Ready, ticks: 0 },
+            rsp: new_frame(slot, entry),
+            page_table: self.kernel_page_table,
+            ring3_stack: 0,
+        });
+        self.ready.push_back(slot);
+        Ok(id)
+    }
+
+    /// Saves where the current thread stopped and picks the next one, returning its stack pointer.
+    fn switch(&mut self, rsp: u64) -> u64 {
+        let current = self.current;
+        if let Some(thread) = &mut self.threads[current] {
+            thread.rsp = rsp;
+            if thread.info.state == State::Running {
+                thread.info.state = State::Ready;
+                if current != self.idle {
+                    self.ready.push_back(current);
+                }
+            }
+        }
+        let next = self.ready.pop_front().unwrap_or(self.idle);
+        self.current = next;
+        self.slice_start = pit::ticks();
+        let thread = self.threads[next].as_mut().expect("a thread in the ready queue is gone");
+        thread.info.state = State::Running;
+        if thread.ring3_stack != 0 {
+            gdt::set_kernel_stack(VirtAddr::new(thread.ring3_stack));
+        }
+        let (page_table, flags) = Cr3::read();
+        if page_table != thread.page_table {
+            unsafe { Cr3::write(thread.page_table, flags) };
+        }
+        thread.rsp
+    }
+
+    fn finish_current(&mut self) {
+        let id = match &mut self.threads[self.current] {
+            Some(thread) => {
+                thread.info.state = State::Finished;
+                thread.info.id
+            }
+            None => return,
+        };
+        for (slot, thread) in self.threads.iter_mut().enumerate() {
+            if let Some(thread) = thread {
+                if thread.info.state == State::Joining(id) {
+                    thread.info.state = State::Ready;
+                    self.ready.push_back(slot);
+                }
+            }
+        }
+    }
+
+    /// Frees the slots of the finished threads, except the running one's: it's still on the slot's stack.
+    fn reap(&mut self) {
+        let current = self.current;
+        for (slot, thread) in self.threads.iter_mut().enumerate() {
+            if slot != current && matches!(thread, Some(thread) if thread.info.state == State::Finished) {
+                *thread = None;
+            }
+        }
+    }
+}
+
+/// Called by thread_timer_entry with the interrupted thread's stack pointer.
+#[no_mangle]
+extern "C" fn thread_timer(rsp: u64) -> u64 {
+    crate::interrupts::timer_tick();
+    // everything else that locks this has the interrupts off, so it's only locked if something went very wrong
+    let mut scheduler = match SCHEDULER.try_lock() {
+        Some(scheduler) => scheduler,
+        None => return rsp,
+    };
+    match scheduler.as_mut() {
+        Some(scheduler) => {
+            if let Some(thread) = &mut scheduler.threads[scheduler.current] {
+                thread.info.ticks += 1;
+            }
+            let slice_over = pit::ticks() - scheduler.slice_start >= QUANTUM_TICKS;
+            if slice_over || scheduler.current == scheduler.idle {
+                scheduler.switch(rsp)
+            } else {
+                rsp
+            }
+        }
+        None => rsp, // init hasn't been called, there's only the one thread
+    }
+}
+
+/// Called by thread_yield_entry, like thread_timer.
+#[no_mangle]
+extern "C" fn thread_yield(rsp: u64) -> u64 {
+    let mut scheduler = match SCHEDULER.try_lock() {
+        Some(scheduler) => scheduler,
+        None => return rsp,
+    };
+    match scheduler.as_mut() {
+        Some(scheduler) => scheduler.switch(rsp),
+        None => rsp,
+    }
+}
+
+/// Where every new thread starts, with its entry from `spawn` in rdi.
+extern "C" fn thread_start(entry: *mut Entry) -> ! {
+    let entry = unsafe { Box::from_raw(entry) };
+    entry();
+    interrupts::without_interrupts(|| {
+        if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+            scheduler.finish_current();
+        }
+    });
+    loop {
+        yield_now(); // it's finished, so the scheduler never comes back here
+    }
+}
+
+/// The top of a slot's stack.
+fn stack_top(slot: usize) -> u64 {
+    STACKS_START + (slot as u64 + 1) * (GUARD_SIZE + STACK_SIZE)
+}
+
+/// The top of a slot's stack for interrupts from ring 3.
+fn ring3_stack_top(slot: usize) -> u64 {
+    stack_top(MAX_THREADS + slot)
+}
+
+/// Maps the stacks of all the slots but thread 0's, which runs on the bootloader's stack, and leaves their guard
+/// pages out. This happens before there are any processes, so the page tables they copy from the kernel's have the
+/// stacks in them too.
+fn map_stacks() -> Result<(), MapToError<Size4KiB>> {
+    let offset = memory::phys_to_virt(PhysAddr::new(0)).expect("memory::init hasn't been called");
+    let (level_4_table, _) = Cr3::read();
+    let table = (offset + level_4_table.start_address().as_u64()).as_mut_ptr();
+    let mut mapper = unsafe { OffsetPageTable::new(&mut *table, offset) };
+    for top in (1..MAX_THREADS).flat_map(|slot| [stack_top(slot), ring3_stack_top(slot)]) {
+        let start = Page::<Size4KiB>::containing_address(VirtAddr::new(top - STACK_SIZE));
+        let end = Page::containing_address(VirtAddr::new(top));
+        for page in Page::range(start, end) {
+            let frame = GlobalFrameAllocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+            unsafe { mapper.map_to(page, frame, flags, &mut GlobalFrameAllocator)?.flush() };
+        }
+    }
+    Ok(())
+}
+
+/// Sets up a slot's stack so switching to it starts `entry` in thread_start. Returns the stack pointer to switch to.
+fn new_frame(slot: usize, entry: *mut Entry) -> u64 {
+    let top = stack_top(slot);
+    let frame = SavedFrame {
+        r15: 0,
+        r14: 0,
+        r13: 0,
+        r12: 0,
+        r11: 0,
+        r10: 0,
+        r9: 0,
+        r8: 0,
+        rbp: 0,
+        rdi: entry as u64,
+        rsi: 0,
+        rdx: 0,
+        rcx: 0,
+        rbx: 0,
+        rax: 0,
+        rip: thread_start as *const () as u64,
+        cs: CS::get_reg().0 as u64,
+        rflags: RFLAGS_INTERRUPTS | RFLAGS_RESERVED,
+        rsp: top - 8, // as if thread_start was called: the return address slot, then a 16 byte aligned stack
+        ss: SS::get_reg().0 as u64,
+    };
+    let rsp = top - 16 - mem::size_of::<SavedFrame>() as u64;
+    unsafe { (rsp as *mut SavedFrame).write(frame) };
+    rsp
+}
+
+/// Turns what's running now into thread 0 and starts switching threads. Needs the heap and the frame allocator.
+pub fn init() {
+    map_stacks().expect("can't map the thread stacks");
+    let idle: Entry = Box::new(|| loop {
+        hlt();
+    });
+    let mut threads = Vec::with_capacity(MAX_THREADS);
+    threads.resize_with(MAX_THREADS, || None);
+    let (kernel_page_table, _) = Cr3::read();
+    threads[0] = Some(Thread {
+        info: ThreadInfo { id: ThreadId(0), name: "kernel", state: State::Running, ticks: 0 },
+        rsp: 0,
+        page_table: kernel_page_table,
+        ring3_stack: 0,
+    });
+    threads[1] = Some(Thread {
+        info: ThreadInfo { id: ThreadId(1), name: "idle", state: State::Ready, ticks: 0 },
+        rsp: new_frame(1, Box::into_raw(Box::new(idle))),
+        page_table: kernel_page_table,
+        ring3_stack: 0,
+    });
+    let scheduler = Scheduler {
+        threads,
+        ready: VecDeque::with_capacity(MAX_THREADS),
+        current: 0,
+        idle: 1,
+        slice_start: pit::ticks(),
+        next_id: 2,
+        kernel_page_table,
+    };
+    interrupts::without_interrupts(|| *SCHEDULER.lock() = Some(scheduler));
+}
+
+/// Starts `f` in a new thread. The thread runs until `f` returns, `join` on the handle waits for that and gets
+/// what it returned. Fails if there are MAX_THREADS already, `f` is dropped then.
+pub fn spawn<F, T>(name: &'static str, f: F) -> Result<JoinHandle<T>, SpawnError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let result = Arc::new(Mutex::new(None));
+    let slot = result.clone();
+    let entry: Entry = Box::new(move || {
+        let value = f();
+        *slot.lock() = Some(value);
+    });
+    let entry = Box::into_raw(Box::new(entry)); // boxed again for a thin pointer that fits in rdi
+    let added = interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        let scheduler = scheduler.as_mut().expect("thread::init hasn't been called");
+        scheduler.reap();
+        scheduler.add(name, entry)
+    });
+    match added {
+        Ok(id) => Ok(JoinHandle { id, result }),
+        Err(error) => {
+            drop(unsafe { Box::from_raw(entry) }); // it never ran, so it still owns `f`
+            Err(error)
+        }
+    }
+}
+
+/// Lets the next thread run now instead of at the end of this one's turn.
+pub fn yield_now() {
+    unsafe { core::arch::asm!("int 0x81") }; // YIELD_VECTOR
+}
+
+/// Gives the running thread a process's level 4 page table and its slot's stack for interrupts from ring 3,
+/// `None` goes back to the kernel's page table. The thread keeps them until this is called again.
+pub(crate) fn set_user_context(page_table: Option<PhysFrame>) {
+    interrupts::without_interrupts(|| {
+        if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+            let current = scheduler.current;
+            let (page_table, ring3_stack) = match page_table {
+                Some(page_table) => (page_table, ring3_stack_top(current)),
+                None => (scheduler.kernel_page_table, 0),
+            };
+            if let Some(thread) = &mut scheduler.threads[current] {
+                thread.page_table = page_table;
+                thread.ring3_stack = ring3_stack;
+            }
+            if ring3_stack != 0 {
+                gdt::set_kernel_stack(VirtAddr::new(ring3_stack));
+            }
+            let (active, flags) = Cr3::read();
+            if active != page_table {
+                unsafe { Cr3::write(page_table, flags) };
+            }
+        }
+    });
+}
+
+/// Makes a thread that got interrupted in ring 3 carry on at `entry` in ring 0 the next time it runs, with the
+/// interrupts off and on its stack for interrupts from ring 3, the way an exception from ring 3 would. A thread that
+/// isn't in ring 3 right now (or is the one calling this) is left alone, that returns false.
+pub(crate) fn divert_from_user_mode(id: ThreadId, entry: extern "C" fn() -> !) -> bool {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        let scheduler = match scheduler.as_mut() {
+            Some(scheduler) => scheduler,
+            None => return false,
+        };
+        let slot = match scheduler.slot(id) {
+            Some(slot) if slot != scheduler.current => slot,
+            _ => return false,
+        };
+        let thread = scheduler.threads[slot].as_mut().expect("slot just found");
+        if thread.ring3_stack == 0 || thread.info.state == State::Finished {
+            return false;
+        }
+        let frame = unsafe { &mut *(thread.rsp as *mut SavedFrame) };
+        if frame.cs & 3 != 3 {
+            return false;
+        }
+        frame.rip = entry as *const () as u64;
+        frame.cs = CS::get_reg().0 as u64;
+        frame.rflags = RFLAGS_RESERVED;
+        frame.rsp = thread.ring3_stack - 8; // as if `entry` was called
+        frame.ss = SS::get_reg().0 as u64;
+        true
+    })
+}
+
+/// The thread that's running.
+pub fn current() -> ThreadId {
+    interrupts::without_interrupts(|| match SCHEDULER.lock().as_ref() {
+        Some(scheduler) => scheduler.threads[scheduler.current].as_ref().map_or(ThreadId(0), |thread| thread.info.id),
+        None => ThreadId(0),
+    })
+}
+
+/// Every thread that hasn't been reaped yet.
+pub fn threads() -> Vec<ThreadInfo> {
+    // copied out first, the Vec can't be made with the interrupts off
+    let mut infos = [None; MAX_THREADS];
+    interrupts::without_interrupts(|| {
+        if let Some(scheduler) = SCHEDULER.lock().as_ref() {
+            for (info, thread) in infos.iter_mut().zip(&scheduler.threads) {
+                *info = thread.as_ref().map(|thread| thread.info);
+            }
+        }
+    });
+    infos.iter().flatten().copied().collect()
+}
+
+pub struct JoinHandle<T> {
+    id: ThreadId,
+    result: Arc<Mutex<Option<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.result.lock().is_some()
+    }
+
+    /// Waits for the thread to finish and returns what it returned. The waiting thread doesn't get any turns
+    /// until then.
+    pub fn join(self) -> T {
+        loop {
+            if let Some(value) = self.result.lock().take() {
+                return value;
+            }
+            interrupts::without_interrupts(|| {
+                if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+                    let running = matches!(
+                        scheduler.slot(self.id).and_then(|slot| scheduler.threads[slot].as_ref()),
+                        Some(thread) if thread.info.state != State::Finished
+                    );
+                    let current = scheduler.current;
+                    if let (true, Some(thread)) = (running, &mut scheduler.threads[current]) {
+                        thread.info.state = State::Joining(self.id);
+                    }
+                }
+            });
+            yield_now(); // if it has finished already its result is about to be there
+        }
+    }
+}
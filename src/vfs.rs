@@ -0,0 +1,594 @@
+/* The virtual filesystem, one tree of paths on top of however many filesystems. Each filesystem implements
+   `FileSystem` and gets mounted at a path in the mount table, `$/` is always the STBFS in `stbfs::FS`.
+
+   Every path here is absolute and already cleaned up by `normalize` (`$/fat/docs`, never `../docs`), the one
+   with the longest mount path in front of it wins and the rest of the path is looked up in that filesystem.
+   Mount points don't have to exist as directories, they show up in their parent's listing anyway. */
+
+use crate::{fat::FatError, stbfs::{disk::DiskError, FsError}};
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use core::fmt;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+/// A node inside one filesystem, what it means is up to the filesystem.
+pub type Ino = u64;
+
+pub const ROOT_PATH: &str = "$/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+}
+
+/// The mode files and directories get on filesystems that don't have permissions of their own.
+pub const DEFAULT_FILE_MODE: u16 = 0o644;
+pub const DEFAULT_DIR_MODE: u16 = 0o755;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stat {
+    pub kind: FileType,
+    pub size: usize,   // in bytes, 0 for directories
+    pub mode: u16,     // permission bits like 0o644
+    pub owner: u16,    // user id, 0 is root
+    pub created: u64,  // unix seconds, 0 if the filesystem doesn't know
+    pub modified: u64, // unix seconds, 0 if the filesystem doesn't know
+}
+
+impl Stat {
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileType::Directory
+    }
+
+    /// The kind and mode the way `ls -l` shows them, like `drwxr-xr-x`.
+    pub fn mode_string(&self) -> String {
+        let mut text = String::from(if self.is_dir() { "d" } else { "-" });
+        for shift in [6, 3, 0] {
+            let bits = self.mode >> shift;
+            text.push(if bits & 4 != 0 { 'r' } else { '-' });
+            text.push(if bits & 2 != 0 { 'w' } else { '-' });
+            text.push(if bits & 1 != 0 { 'x' } else { '-' });
+        }
+        text
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: FileType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VfsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    InvalidName,
+    NameTooLong,
+    NotEmpty,
+    InvalidMove,
+    InvalidSeek,
+    PermissionDenied,
+    NoSpace,
+    ReadOnly,
+    Busy,
+    NotMounted,
+    Unsupported,
+    Corrupt,
+    Io,
+}
+
+impl fmt::Display for VfsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            VfsError::NotFound => "no such file or directory",
+            VfsError::NotADirectory => "not a directory",
+            VfsError::IsADirectory => "is a directory",
+            VfsError::AlreadyExists => "already exists",
+            VfsError::InvalidName => "invalid name",
+            VfsError::NameTooLong => "name too long",
+            VfsError::NotEmpty => "directory not empty",
+            VfsError::InvalidMove => "a directory can't go inside itself",
+            VfsError::InvalidSeek => "can't seek before the start of the file",
+            VfsError::PermissionDenied => "permission denied",
+            VfsError::NoSpace => "no space left",
+            VfsError::ReadOnly => "read-only filesystem",
+            VfsError::Busy => "busy",
+            VfsError::NotMounted => "nothing is mounted there",
+            VfsError::Unsupported => "not supported",
+            VfsError::Corrupt => "filesystem is corrupt",
+            VfsError::Io => "disk error",
+        };
+        f.write_str(message)
+    }
+}
+
+impl From<DiskError> for VfsError {
+    fn from(error: DiskError) -> Self {
+        match error {
+            DiskError::NoSpace | DiskError::NoInodes | DiskError::FileTooBig => VfsError::NoSpace,
+            DiskError::NameTooLong => VfsError::NameTooLong,
+            DiskError::NotFound => VfsError::NotFound,
+            DiskError::NotADirectory => VfsError::NotADirectory,
+            DiskError::AlreadyExists => VfsError::AlreadyExists,
+            DiskError::IsADirectory => VfsError::IsADirectory,
+            DiskError::Io(_) => VfsError::Io,
+            DiskError::NotStbfs | DiskError::UnsupportedVersion(_) | DiskError::TooSmall | DiskError::Corrupt => {
+                VfsError::Corrupt
+            }
+        }
+    }
+}
+
+impl From<FsError> for VfsError {
+    fn from(error: FsError) -> Self {
+        match error {
+            FsError::NotFound => VfsError::NotFound,
+            FsError::NotADirectory => VfsError::NotADirectory,
+            FsError::IsADirectory => VfsError::IsADirectory,
+            FsError::AlreadyExists => VfsError::AlreadyExists,
+            FsError::InvalidName => VfsError::InvalidName,
+            FsError::NameTooLong => VfsError::NameTooLong,
+            FsError::NotEmpty => VfsError::NotEmpty,
+            FsError::InvalidMove => VfsError::InvalidMove,
+            FsError::InvalidSeek => VfsError::InvalidSeek,
+            FsError::PermissionDenied => VfsError::PermissionDenied,
+            FsError::Disk(error) => error.into(),
+        }
+    }
+}
+
+impl From<FatError> for VfsError {
+    fn from(error: FatError) -> Self {
+        match error {
+            FatError::Io(_) => VfsError::Io,
+            FatError::NotFat | FatError::Corrupt => VfsError::Corrupt,
+            FatError::NotFound => VfsError::NotFound,
+            FatError::NotADirectory => VfsError::NotADirectory,
+            FatError::IsADirectory => VfsError::IsADirectory,
+            FatError::AlreadyExists => VfsError::AlreadyExists,
+            FatError::InvalidName => VfsError::InvalidName,
+            FatError::NotEmpty => VfsError::NotEmpty,
+            FatError::NoSpace => VfsError::NoSpace,
+        }
+    }
+}
+
+/// What every filesystem has to be able to do to be mounted.
+pub trait FileSystem: Send {
+    /// Short name of the kind of filesystem, like `stbfs` or `fat`.
+    fn fs_type(&self) -> &'static str;
+
+    fn root(&self) -> Ino;
+
+    /// Finds `name` in a directory.
+    fn lookup(&mut self, dir: Ino, name: &str) -> Result<Ino, VfsError>;
+
+    fn stat(&mut self, node: Ino) -> Result<Stat, VfsError>;
+
+    /// The whole content of a file.
+    fn read(&mut self, file: Ino) -> Result<Vec<u8>, VfsError>;
+
+    /// Replaces the whole content of a file.
+    fn write(&mut self, file: Ino, data: &[u8]) -> Result<(), VfsError>;
+
+    fn readdir(&mut self, dir: Ino) -> Result<Vec<DirEntry>, VfsError>;
+
+    /// Makes an empty file in a directory.
+    fn create(&mut self, dir: Ino, name: &str) -> Result<Ino, VfsError>;
+
+    fn mkdir(&mut self, dir: Ino, name: &str) -> Result<Ino, VfsError>;
+
+    /// Removes a file or an empty directory.
+    fn unlink(&mut self, dir: Ino, name: &str) -> Result<(), VfsError>;
+
+    /// Moves an entry to another name or directory in the same filesystem. `new_name` mustn't exist yet.
+    /// `Unsupported` makes the VFS copy it over and remove the old one instead.
+    fn rename(&mut self, dir: Ino, name: &str, new_dir: Ino, new_name: &str) -> Result<(), VfsError>;
+}
+
+/// A filesystem that lives in a global, like the root STBFS in `stbfs::FS`. Each call takes the lock.
+impl<F: FileSystem + 'static> FileSystem for &'static Mutex<F> {
+    fn fs_type(&self) -> &'static str {
+        self.lock().fs_type()
+    }
+
+    fn root(&self) -> Ino {
+        self.lock().root()
+    }
+
+    fn lookup(&mut self, dir: Ino, name: &str) -> Result<Ino, VfsError> {
+        self.lock().lookup(dir, name)
+    }
+
+    fn stat(&mut self, node: Ino) -> Result<Stat, VfsError> {
+        self.lock().stat(node)
+    }
+
+    fn read(&mut self, file: Ino) -> Result<Vec<u8>, VfsError> {
+        self.lock().read(file)
+    }
+
+    fn write(&mut self, file: Ino, data: &[u8]) -> Result<(), VfsError> {
+        self.lock().write(file, data)
+    }
+
+    fn readdir(&mut self, dir: Ino) -> Result<Vec<DirEntry>, VfsError> {
+        self.lock().readdir(dir)
+    }
+
+    fn create(&mut self, dir: Ino, name: &str) -> Result<Ino, VfsError> {
+        self.lock().create(dir, name)
+    }
+
+    fn mkdir(&mut self, dir: Ino, name: &str) -> Result<Ino, VfsError> {
+        self.lock().mkdir(dir, name)
+    }
+
+    fn unlink(&mut self, dir: Ino, name: &str) -> Result<(), VfsError> {
+        self.lock().unlink(dir, name)
+    }
+
+    fn rename(&mut self, dir: Ino, name: &str, new_dir: Ino, new_name: &str) -> Result<(), VfsError> {
+        self.lock().rename(dir, name, new_dir, new_name)
+    }
+}
+
+pub struct Mount {
+    pub path: String,   // normalized, like `$/fat`
+    pub source: String, // the disk it's on (`hdb`) or `none`
+    pub fs: Box<dyn FileSystem>,
+}
+
+pub struct Vfs {
+    mounts: Vec<Mount>,
+}
+
+impl Vfs {
+    /// A VFS with `root` mounted at `$/`.
+    pub fn new(root: Box<dyn FileSystem>, source: &str) -> Self {
+        Vfs {
+            mounts: vec![Mount {
+                path: String::from(ROOT_PATH),
+                source: String::from(source),
+                fs: root,
+            }],
+        }
+    }
+
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    pub fn mount(&mut self, path: &str, source: &str, fs: Box<dyn FileSystem>) -> Result<(), VfsError> {
+        if self.mounts.iter().any(|mount| mount.path == path) {
+            return Err(VfsError::Busy);
+        }
+        if path != ROOT_PATH {
+            // the parent has to be a directory, the mount point itself may or may not exist
+            let (parent, _) = split_parent(path).ok_or(VfsError::InvalidName)?;
+            if !self.stat(parent)?.is_dir() {
+                return Err(VfsError::NotADirectory);
+            }
+        }
+        self.mounts.push(Mount {
+            path: String::from(path),
+            source: String::from(source),
+            fs,
+        });
+        Ok(())
+    }
+
+    /// Takes a filesystem out of the tree and hands it back. `$/` and anything with mounts below it stay.
+    pub fn umount(&mut self, path: &str) -> Result<Mount, VfsError> {
+        if path == ROOT_PATH {
+            return Err(VfsError::Busy);
+        }
+        let index = self.mounts.iter().position(|mount| mount.path == path).ok_or(VfsError::NotMounted)?;
+        if self.mounts.iter().any(|mount| is_below(&mount.path, path)) {
+            return Err(VfsError::Busy);
+        }
+        Ok(self.mounts.remove(index))
+    }
+
+    /// Changes the source shown for a mount, for when a disk gets attached to a filesystem later on.
+    pub fn set_source(&mut self, path: &str, source: &str) {
+        if let Some(mount) = self.mounts.iter_mut().find(|mount| mount.path == path) {
+            mount.source = String::from(source);
+        }
+    }
+
+    /// The mount a path is on and the part of the path inside it.
+    fn locate<'p>(&self, path: &'p str) -> (usize, &'p str) {
+        let mut best = 0;
+        for (index, mount) in self.mounts.iter().enumerate() {
+            let on_it = mount.path == path || is_below(path, &mount.path);
+            if on_it && mount.path.len() >= self.mounts[best].path.len() {
+                best = index;
+            }
+        }
+        let inner = match &self.mounts[best].path {
+            root if root == ROOT_PATH => &path[2..],
+            mount => &path[mount.len()..],
+        };
+        (best, inner.trim_start_matches('/'))
+    }
+
+    /// Finds the filesystem and node a path points to.
+    fn walk(&mut self, path: &str) -> Result<(usize, Ino), VfsError> {
+        let (index, inner) = self.locate(path);
+        let fs = &mut self.mounts[index].fs;
+        let mut node = fs.root();
+        for name in inner.split('/').filter(|name| !name.is_empty()) {
+            node = fs.lookup(node, name)?;
+        }
+        Ok((index, node))
+    }
+
+    /// Like `walk` but for the directory something is about to be made in, returns the new name too.
+    fn walk_parent<'p>(&mut self, path: &'p str) -> Result<(usize, Ino, &'p str), VfsError> {
+        if self.mounts.iter().any(|mount| mount.path == path) {
+            return Err(VfsError::AlreadyExists); // that name is taken by the mount
+        }
+        let (parent, name) = split_parent(path).ok_or(VfsError::InvalidName)?;
+        let (index, dir) = self.walk(parent)?;
+        Ok((index, dir, name))
+    }
+
+    pub fn stat(&mut self, path: &str) -> Result<Stat, VfsError> {
+        let (index, node) = self.walk(path)?;
+        self.mounts[index].fs.stat(node)
+    }
+
+    pub fn read(&mut self, path: &str) -> Result<Vec<u8>, VfsError> {
+        let (index, node) = self.walk(path)?;
+        self.mounts[index].fs.read(node)
+    }
+
+    /// Replaces the content of a file, making it first if it isn't there.
+    pub fn write(&mut self, path: &str, data: &[u8]) -> Result<(), VfsError> {
+        let (index, node) = match self.walk(path) {
+            Ok(found) => found,
+            Err(VfsError::NotFound) => {
+                let (index, dir, name) = self.walk_parent(path)?;
+                (index, self.mounts[index].fs.create(dir, name)?)
+            }
+            Err(error) => return Err(error),
+        };
+        self.mounts[index].fs.write(node, data)
+    }
+
+    /// Makes a new file, it's an error if there's something there already.
+    pub fn create(&mut self, path: &str, data: &[u8]) -> Result<(), VfsError> {
+        let (index, dir, name) = self.walk_parent(path)?;
+        let fs = &mut self.mounts[index].fs;
+        let file = fs.create(dir, name)?;
+        fs.write(file, data)
+    }
+
+    pub fn mkdir(&mut self, path: &str) -> Result<(), VfsError> {
+        let (index, dir, name) = self.walk_parent(path)?;
+        self.mounts[index].fs.mkdir(dir, name).map(|_| ())
+    }
+
+    pub fn unlink(&mut self, path: &str) -> Result<(), VfsError> {
+        if self.has_mounts(path) {
+            return Err(VfsError::Busy); // unmount it first
+        }
+        let (index, dir, name) = self.walk_parent(path)?;
+        self.mounts[index].fs.unlink(dir, name)
+    }
+
+    /// Adds to the end of a file, making it first if it isn't there.
+    pub fn append(&mut self, path: &str, data: &[u8]) -> Result<(), VfsError> {
+        let mut content = match self.read(path) {
+            Ok(content) => content,
+            Err(VfsError::NotFound) => Vec::new(),
+            Err(error) => return Err(error),
+        };
+        content.extend_from_slice(data);
+        self.write(path, &content)
+    }
+
+    /// Whether there's a mount at `path` or somewhere below it.
+    fn has_mounts(&self, path: &str) -> bool {
+        self.mounts.iter().any(|mount| mount.path == path || is_below(&mount.path, path))
+    }
+
+    /// Removes a file or a directory with everything in it.
+    pub fn remove_all(&mut self, path: &str) -> Result<(), VfsError> {
+        if self.has_mounts(path) {
+            return Err(VfsError::Busy); // check first, we don't want to empty half of it and then stop
+        }
+        if self.stat(path)?.is_dir() {
+            for entry in self.read_dir(path)? {
+                self.remove_all(&normalize(path, &entry.name))?;
+            }
+        }
+        self.unlink(path)
+    }
+
+    /// Copies a file, or with `recursive` a whole directory, to `to`. An existing file at `to` gets replaced.
+    pub fn copy(&mut self, from: &str, to: &str, recursive: bool) -> Result<(), VfsError> {
+        if !self.stat(from)?.is_dir() {
+            let data = self.read(from)?;
+            return self.write(to, &data);
+        }
+        if !recursive {
+            return Err(VfsError::IsADirectory);
+        }
+        if to == from || is_below(to, from) {
+            return Err(VfsError::InvalidMove);
+        }
+        self.mkdir(to)?;
+        for entry in self.read_dir(from)? {
+            self.copy(&normalize(from, &entry.name), &normalize(to, &entry.name), true)?;
+        }
+        Ok(())
+    }
+
+    /// Moves a file or directory to `to`. An existing file at `to` gets replaced, a directory doesn't.
+    ///
+    /// Within one filesystem it's just renamed, otherwise it's copied over and the old one removed. Replacing a
+    /// file always goes the copy way: it's only overwritten once the new data is there, so a move that fails
+    /// halfway doesn't cost the old file.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<(), VfsError> {
+        if from == to {
+            return Ok(());
+        }
+        if self.has_mounts(from) {
+            return Err(VfsError::Busy); // unmount it first
+        }
+        if is_below(to, from) {
+            return Err(VfsError::InvalidMove);
+        }
+        let source = self.stat(from)?;
+        let replacing = match self.stat(to) {
+            Ok(target) if target.is_dir() || source.is_dir() => return Err(VfsError::AlreadyExists),
+            Ok(_) if self.walk(to)? == self.walk(from)? => return Ok(()), // the same file, FAT ignores case
+            Ok(_) => true,
+            Err(VfsError::NotFound) => false,
+            Err(error) => return Err(error),
+        };
+
+        let (from_index, from_dir, from_name) = self.walk_parent(from)?;
+        let (to_index, to_dir, to_name) = self.walk_parent(to)?;
+        if from_index == to_index && !replacing {
+            match self.mounts[from_index].fs.rename(from_dir, from_name, to_dir, to_name) {
+                Err(VfsError::Unsupported) => {}
+                result => return result,
+            }
+        }
+        self.copy(from, to, true)?;
+        self.remove_all(from)
+    }
+
+    /// Everything in a directory, including the mount points right below it. Sorted by name.
+    pub fn read_dir(&mut self, path: &str) -> Result<Vec<DirEntry>, VfsError> {
+        let (index, dir) = self.walk(path)?;
+        let mut entries = self.mounts[index].fs.readdir(dir)?;
+        for mount in &self.mounts {
+            let name = match split_parent(&mount.path) {
+                Some((parent, name)) if parent == path => name,
+                _ => continue,
+            };
+            entries.retain(|entry| entry.name != name); // the mount hides whatever was there
+            entries.push(DirEntry {
+                name: String::from(name),
+                kind: FileType::Directory,
+            });
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+}
+
+/// Whether `path` is somewhere inside `dir` (and not `dir` itself).
+pub fn is_below(path: &str, dir: &str) -> bool {
+    if dir == ROOT_PATH {
+        return path != ROOT_PATH;
+    }
+    path.len() > dir.len() && path.starts_with(dir) && path.as_bytes()[dir.len()] == b'/'
+}
+
+/// `$/a/b` -> (`$/a`, `b`), `$/a` -> (`$/`, `a`). None for the root.
+pub fn split_parent(path: &str) -> Option<(&str, &str)> {
+    if path == ROOT_PATH {
+        return None;
+    }
+    let slash = path.rfind('/')?;
+    let parent = if slash == 1 { ROOT_PATH } else { &path[..slash] };
+    Some((parent, &path[slash + 1..]))
+}
+
+/// Turns a path the user typed into an absolute one without any `.` or `..` in it.
+/// `$/...` is absolute, anything else starts at `cwd` (which has to be absolute already).
+pub fn normalize(cwd: &str, path: &str) -> String {
+    let (base, rest) = if path == "$" || path.starts_with("$/") {
+        (ROOT_PATH, &path[1..])
+    } else {
+        (cwd, path)
+    };
+    let mut parts: Vec<&str> = base[2..].split('/').filter(|part| !part.is_empty()).collect();
+    for component in rest.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                parts.pop(); // `..` of the root is the root
+            }
+            name => parts.push(name),
+        }
+    }
+    let mut absolute = String::from(ROOT_PATH);
+    absolute.push_str(&parts.join("/"));
+    absolute
+}
+
+/// Paths starting with `word` for Tab completion, sorted. Directories end with a `/`.
+pub fn complete(cwd: &str, word: &str) -> Vec<String> {
+    let (dir_part, prefix) = match word.rfind('/') {
+        Some(slash) => word.split_at(slash + 1),
+        None => ("", word),
+    };
+    let dir = normalize(cwd, dir_part);
+    let entries = VFS.lock().read_dir(&dir).unwrap_or_default();
+    entries
+        .into_iter()
+        .filter(|entry| entry.name.starts_with(prefix))
+        .map(|entry| {
+            let mut candidate = String::from(dir_part);
+            candidate.push_str(&entry.name);
+            if entry.kind == FileType::Directory {
+                candidate.push('/');
+            }
+            candidate
+        })
+        .collect()
+}
+
+lazy_static! {
+    pub static ref VFS: Mutex<Vfs> = Mutex::new(Vfs::new(Box::new(&*crate::stbfs::FS), "none"));
+}
+
+#[test_case]
+fn test_normalize() {
+    assert_eq!(normalize("$/", "docs"), "$/docs");
+    assert_eq!(normalize("$/docs", "../a/./b/"), "$/a/b");
+    assert_eq!(normalize("$/docs", "$/x"), "$/x");
+    assert_eq!(normalize("$/", ".."), "$/");
+    assert_eq!(normalize("$/a/b", "$"), "$/");
+}
+
+#[test_case]
+fn test_rename() {
+    use crate::stbfs::Filesystem;
+    let mut vfs = Vfs::new(Box::new(Filesystem::new()), "none");
+    vfs.write("$/a", b"new").unwrap();
+    vfs.write("$/b", b"old").unwrap();
+    vfs.rename("$/a", "$/b").unwrap();
+    assert_eq!(vfs.read("$/b").unwrap(), b"new");
+    assert_eq!(vfs.stat("$/a").err(), Some(VfsError::NotFound));
+
+    vfs.mkdir("$/dir").unwrap();
+    assert_eq!(vfs.rename("$/dir", "$/b"), Err(VfsError::AlreadyExists));
+    assert_eq!(vfs.read("$/b").unwrap(), b"new");
+
+    vfs.mount("$/dir", "none", Box::new(Filesystem::new())).unwrap();
+    vfs.write("$/dir/b", b"other").unwrap();
+    vfs.rename("$/b", "$/dir/b").unwrap();
+    assert_eq!(vfs.read("$/dir/b").unwrap(), b"new");
+    assert_eq!(vfs.stat("$/b").err(), Some(VfsError::NotFound));
+}
+
+#[test_case]
+fn test_split_parent() {
+    assert_eq!(split_parent("$/a/b"), Some(("$/a", "b")));
+    assert_eq!(split_parent("$/a"), Some(("$/", "a")));
+    assert_eq!(split_parent("$/"), None);
+    assert!(is_below("$/a/b", "$/a"));
+    assert!(!is_below("$/ab", "$/a"));
+    assert!(!is_below("$/", "$/"));
+}
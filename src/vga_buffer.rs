@@ -5,6 +5,7 @@ use core::fmt;
 use lazy_static::lazy_static;
 use spin::Mutex;
 use core::arch::asm;
+use x86_64::instructions::interrupts; // the helpers below lock WRITER with them off, like _print
 
 lazy_static! { // this lazy static defines the default vga settings
     pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
@@ -56,6 +57,9 @@ pub struct ScreenChar { // screen characters
 pub const BUFFER_HEIGHT: usize = 25; // the screen resolution, it being 80x25px
 pub const BUFFER_WIDTH: usize = 80;
 
+/// A copy of everything on the screen, so it can be put back later.
+pub type Screen = [[ScreenChar; BUFFER_WIDTH]; BUFFER_HEIGHT];
+
 #[repr(transparent)] // screen buffer
 pub struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
@@ -148,19 +152,88 @@ impl Writer { // implementation of the Writer
         };
         self.buffer.chars[row][col].write(blank);
     }
+
+    /// Puts a character with its own color anywhere on the screen, for full-screen programs like the editor.
+    pub fn write_char_at(&mut self, row: usize, col: usize, byte: u8, color_code: ColorCode) {
+        self.buffer.chars[row][col].write(ScreenChar {
+            ascii_character: byte,
+            color_code,
+        });
+    }
+
+    /// Takes a copy of the screen.
+    pub fn save_screen(&self) -> Screen {
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        let mut screen = [[blank; BUFFER_WIDTH]; BUFFER_HEIGHT];
+        for (row, chars) in screen.iter_mut().enumerate() {
+            for (col, character) in chars.iter_mut().enumerate() {
+                *character = self.buffer.chars[row][col].read();
+            }
+        }
+        screen
+    }
+
+    /// Puts a copy taken with `save_screen` back.
+    pub fn restore_screen(&mut self, screen: &Screen) {
+        for (row, chars) in screen.iter().enumerate() {
+            for (col, character) in chars.iter().enumerate() {
+                self.buffer.chars[row][col].write(*character);
+            }
+        }
+    }
+
+    /// Moves the blinking hardware cursor to where the next character will be written.
+    pub fn update_cursor(&self) {
+        set_cursor_position(BUFFER_HEIGHT - 1, self.column_position.min(BUFFER_WIDTH - 1));
+    }
+}
+
+/// Moves the blinking hardware cursor through the CRT controller ports.
+pub fn set_cursor_position(row: usize, col: usize) {
+    use x86_64::instructions::port::Port;
+
+    let position = (row * BUFFER_WIDTH + col) as u16;
+    let mut index: Port<u8> = Port::new(0x3D4);
+    let mut data: Port<u8> = Port::new(0x3D5);
+    unsafe {
+        index.write(0x0F); // cursor location low byte
+        data.write((position & 0xFF) as u8);
+        index.write(0x0E); // cursor location high byte
+        data.write((position >> 8) as u8);
+    }
+}
+
+/// Turns the hardware cursor on as an underline (scanlines 14 and 15).
+pub fn enable_cursor() {
+    use x86_64::instructions::port::Port;
+
+    let mut index: Port<u8> = Port::new(0x3D4);
+    let mut data: Port<u8> = Port::new(0x3D5);
+    unsafe {
+        index.write(0x0A); // cursor start register
+        let start = data.read();
+        data.write((start & 0xC0) | 14);
+        index.write(0x0B); // cursor end register
+        let end = data.read();
+        data.write((end & 0xE0) | 15);
+    }
 }
 
 pub fn print_something() { // i still use those functions cause you can change the color here!
     use core::fmt::Write;
-    let mut writer = WRITER.lock();
-    writer.color_code = ColorCode::new(Color::Cyan, Color::Black); // Customize the color if needed
-    writer.write_string("Welcome to S.T.B. OS 0.9.8.5 by Admiralix!\n");
-    writer.color_code = ColorCode::new(Color::White, Color::Black); // Restore the default color
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.color_code = ColorCode::new(Color::Cyan, Color::Black); // Customize the color if needed
+        writer.write_string("Welcome to S.T.B. OS 0.9.8.5 by Admiralix!\n");
+        writer.color_code = ColorCode::new(Color::White, Color::Black); // Restore the default color
+    });
 }
 
 pub fn ascii() {
     use core::fmt::Write;
-    let mut writer = WRITER.lock();
     let ascii_art = r#"
     .d8888b.      88888888888     888888b.            .d88888b.   .d8888b.  
     d88P  Y88b         888         888  "88b          d88P" "Y88b d88P  Y88b 
@@ -171,31 +244,38 @@ pub fn ascii() {
     Y88b  d88P d8b     888     d8b 888   d88P d8b     Y88b. .d88P Y88b  d88P 
      "Y8888P"  Y8P     888     Y8P 8888888P"  Y8P      "Y88888P"   "Y8888P"  
     "#;
-    writer.color_code = ColorCode::new(Color::LightGreen, Color::Black); // Customize the color if needed
-    writer.write_string(ascii_art);
-    writer.color_code = ColorCode::new(Color::White, Color::Black); // Restore the default color
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.color_code = ColorCode::new(Color::LightGreen, Color::Black); // Customize the color if needed
+        writer.write_string(ascii_art);
+        writer.color_code = ColorCode::new(Color::White, Color::Black); // Restore the default color
+    });
 }
 
 pub fn print_error1() {
     use core::fmt::Write;
-    let mut writer = WRITER.lock();
-    writer.color_code = ColorCode::new(Color::Red, Color::Black); // Customize the color if needed
-    writer.write_string("\nuse /syshelp to get a list of all possible commands\n");
-    writer.color_code = ColorCode::new(Color::White, Color::Black); // Restore the default color 
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.color_code = ColorCode::new(Color::Red, Color::Black); // Customize the color if needed
+        writer.write_string("\nuse /syshelp to get a list of all possible commands\n");
+        writer.color_code = ColorCode::new(Color::White, Color::Black); // Restore the default color 
+    });
 }
 
 pub fn print_all_ascii() {
-    let mut writer = WRITER.lock();
-    writer.color_code = ColorCode::new(Color::White, Color::Black); // Set the color to white text on a black background
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.color_code = ColorCode::new(Color::White, Color::Black); // Set the color to white text on a black background
 
-    for i in 0..=127 {
-        let ascii_char = match i {
-            0x20..=0x7e => i as u8, // printable ASCII characters
-            _ => 0xfe, // display a special character for non-printable ASCII
-        };
+        for i in 0..=127 {
+            let ascii_char = match i {
+                0x20..=0x7e => i as u8, // printable ASCII characters
+                _ => 0xfe, // display a special character for non-printable ASCII
+            };
 
-        writer.write_byte(ascii_char);
-    }
+            writer.write_byte(ascii_char);
+        }
+    });
 }
 
 // Define a custom smiley face ASCII character pattern (5x8 pixels)
@@ -205,40 +285,39 @@ const SMILEY_FACE_PATTERN: [u8; 2] = [
 ];
 
 pub fn print_smiley_face() {
-    let mut writer = WRITER.lock();
-    writer.color_code = ColorCode::new(Color::White, Color::Black);
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.color_code = ColorCode::new(Color::White, Color::Black);
 
-    const CUSTOM_ASCII_INDEX: usize = 128;
+        const CUSTOM_ASCII_INDEX: usize = 128;
 
-    // Write the smiley face pattern to the VGA buffer for the custom ASCII index
-    for (i, &byte) in SMILEY_FACE_PATTERN.iter().enumerate() {
-        writer.write_byte_at(CUSTOM_ASCII_INDEX * 8 + i, byte);
-    }
+        // Write the smiley face pattern to the VGA buffer for the custom ASCII index
+        for (i, &byte) in SMILEY_FACE_PATTERN.iter().enumerate() {
+            writer.write_byte_at(CUSTOM_ASCII_INDEX * 8 + i, byte);
+        }
 
-    // Display the custom smiley face character at a specific position
-    writer.write_byte(CUSTOM_ASCII_INDEX as u8);
+        // Display the custom smiley face character at a specific position
+        writer.write_byte(CUSTOM_ASCII_INDEX as u8);
+    });
 }
 
 pub fn OK() {
     use core::fmt::Write;
-    let mut writer = WRITER.lock();
-    writer.color_code = ColorCode::new(Color::LightGreen, Color::Black); // Customize the color if needed
-    writer.write_string("\nOK\n");
-    writer.color_code = ColorCode::new(Color::White, Color::Black); // Restore the default color 
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.color_code = ColorCode::new(Color::LightGreen, Color::Black); // Customize the color if needed
+        writer.write_string("\nOK\n");
+        writer.color_code = ColorCode::new(Color::White, Color::Black); // Restore the default color 
+    });
 }
 
 pub fn print_shutdown() {
-    use core::fmt::Write;
-    
-    {
+    interrupts::without_interrupts(|| {
         let mut writer = WRITER.lock();
         writer.color_code = ColorCode::new(Color::Yellow, Color::Black); // Customize the color if needed
         writer.write_string("It is now safe to turn off your computer\n");
-    } // The lock is released here, and the changes to show_prompt and color_code are discarded.
-
-    // Restore the default color
-    let mut writer = WRITER.lock();
-    writer.color_code = ColorCode::new(Color::White, Color::Black);
+        writer.color_code = ColorCode::new(Color::White, Color::Black); // Restore the default color
+    });
 }
 
 impl fmt::Write for Writer { // yes
@@ -268,4 +347,30 @@ pub fn _print(args: fmt::Arguments) {
 
     });
     
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn test_println_simple() {
+    println!("test_println_simple output");
+}
+
+#[test_case]
+fn test_println_many() {
+    for _ in 0..200 {
+        println!("test_println_many output"); // scrolls the screen a lot
+    }
+}
+
+#[test_case]
+fn test_println_output() {
+    use core::fmt::Write;
+    let s = "Some test string that fits on a single line";
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writeln!(writer, "\n{}", s).expect("writeln failed");
+        for (i, c) in s.chars().enumerate() {
+            let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
+            assert_eq!(char::from(screen_char.ascii_character), c);
+        }
+    });
+}
@@ -0,0 +1,57 @@
+/* Block devices, anything that reads and writes fixed size blocks (right now that's the ATA disks).
+   Filesystems only talk to this trait so they don't care what the disk actually is. */
+
+use alloc::boxed::Box;
+use core::fmt;
+
+pub const BLOCK_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    OutOfRange,
+    DeviceError,
+    Timeout,
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            BlockError::OutOfRange => "block out of range",
+            BlockError::DeviceError => "device error",
+            BlockError::Timeout => "device timed out",
+        };
+        f.write_str(message)
+    }
+}
+
+pub trait BlockDevice {
+    /// How many blocks the device has.
+    fn block_count(&self) -> u64;
+
+    fn read_block(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), BlockError>;
+
+    fn write_block(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), BlockError>;
+
+    /// Makes sure everything written so far actually reached the device.
+    fn flush(&mut self) -> Result<(), BlockError> {
+        Ok(())
+    }
+}
+
+impl<D: BlockDevice + ?Sized> BlockDevice for Box<D> {
+    fn block_count(&self) -> u64 {
+        (**self).block_count()
+    }
+
+    fn read_block(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), BlockError> {
+        (**self).read_block(block, buf)
+    }
+
+    fn write_block(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), BlockError> {
+        (**self).write_block(block, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        (**self).flush()
+    }
+}
@@ -0,0 +1,980 @@
+/* The on-disk format of STBFS. Everything is counted in 512 byte blocks:
+
+     block 0                  superblock (magic, version and where everything else is)
+     bitmap_start..           block bitmap, one bit per block of the disk, 1 = used
+     inode_start..            inode table, 4 inodes of 128 bytes per block, inode 0 is the root directory
+     data_start..             file contents and directory blocks
+
+   An inode points at its data with 12 direct block numbers, one indirect block (128 more numbers) and one
+   double-indirect block (128 more indirect blocks), which makes a bit over 8 MiB, and carries the permission bits,
+   the owner and when it was made and last changed (unix seconds, 0 = unknown).
+   Directory data is a list of 64 byte entries: inode number, name length, name. All numbers are little endian.
+   Block number 0 is the superblock, so 0 in a block pointer means "no block". */
+
+use crate::block::{BlockDevice, BlockError, BLOCK_SIZE};
+use alloc::{collections::BTreeSet, string::String, vec, vec::Vec};
+use core::fmt;
+
+pub const MAGIC: [u8; 8] = *b"STBFS\0\0\0";
+pub const VERSION: u32 = 1;
+
+pub const INODE_SIZE: usize = 128;
+pub const INODES_PER_BLOCK: usize = BLOCK_SIZE / INODE_SIZE;
+pub const DIRECT_BLOCKS: usize = 12;
+pub const POINTERS_PER_BLOCK: usize = BLOCK_SIZE / 4;
+pub const MAX_FILE_BLOCKS: usize = DIRECT_BLOCKS + POINTERS_PER_BLOCK + POINTERS_PER_BLOCK * POINTERS_PER_BLOCK;
+
+pub const DIR_ENTRY_SIZE: usize = 64;
+pub const ENTRIES_PER_BLOCK: usize = BLOCK_SIZE / DIR_ENTRY_SIZE;
+pub const MAX_NAME_LEN: usize = DIR_ENTRY_SIZE - 6;
+
+pub const ROOT_INODE: u32 = 0;
+
+/// rwx for the owner and for everyone else, like the lower bits of a unix mode.
+pub const DEFAULT_FILE_MODE: u16 = 0o644;
+pub const DEFAULT_DIR_MODE: u16 = 0o755;
+
+// set in the stored mode, disks from before there were modes have 0 there and get the defaults
+const MODE_PRESENT: u16 = 0x8000;
+
+const BITS_PER_BLOCK: u32 = (BLOCK_SIZE * 8) as u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskError {
+    Io(BlockError),
+    NotStbfs,
+    UnsupportedVersion(u32),
+    TooSmall,
+    NoSpace,
+    NoInodes,
+    FileTooBig,
+    NameTooLong,
+    NotFound,
+    NotADirectory,
+    AlreadyExists,
+    IsADirectory,
+    Corrupt,
+}
+
+impl From<BlockError> for DiskError {
+    fn from(error: BlockError) -> Self {
+        DiskError::Io(error)
+    }
+}
+
+impl fmt::Display for DiskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiskError::Io(error) => write!(f, "disk error: {}", error),
+            DiskError::NotStbfs => f.write_str("not an STBFS disk"),
+            DiskError::UnsupportedVersion(version) => write!(f, "unsupported STBFS version {}", version),
+            DiskError::TooSmall => f.write_str("disk too small"),
+            DiskError::NoSpace => f.write_str("no space left on disk"),
+            DiskError::NoInodes => f.write_str("no free inodes left on disk"),
+            DiskError::FileTooBig => f.write_str("file too big"),
+            DiskError::NameTooLong => f.write_str("name too long"),
+            DiskError::NotFound => f.write_str("no such file or directory"),
+            DiskError::NotADirectory => f.write_str("not a directory"),
+            DiskError::AlreadyExists => f.write_str("already exists"),
+            DiskError::IsADirectory => f.write_str("is a directory"),
+            DiskError::Corrupt => f.write_str("filesystem is corrupt"),
+        }
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn write_u64(buf: &mut [u8], offset: usize, value: u64) {
+    buf[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+/// The mode new files and directories get.
+pub fn default_mode(kind: InodeKind) -> u16 {
+    match kind {
+        InodeKind::Directory => DEFAULT_DIR_MODE,
+        _ => DEFAULT_FILE_MODE,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Superblock {
+    pub block_count: u32,
+    pub inode_count: u32,
+    pub bitmap_start: u32,
+    pub bitmap_blocks: u32,
+    pub inode_start: u32,
+    pub inode_blocks: u32,
+    pub data_start: u32,
+}
+
+impl Superblock {
+    /// Lays out a filesystem on a disk with `block_count` blocks, one inode for every 8 blocks.
+    pub fn new(block_count: u32) -> Result<Self, DiskError> {
+        let bitmap_blocks = block_count.div_ceil(BITS_PER_BLOCK);
+        let inode_count = (block_count / 8).max(INODES_PER_BLOCK as u32);
+        let inode_blocks = inode_count.div_ceil(INODES_PER_BLOCK as u32);
+        let data_start = 1 + bitmap_blocks + inode_blocks;
+        if data_start + 8 > block_count {
+            return Err(DiskError::TooSmall);
+        }
+        Ok(Superblock {
+            block_count,
+            inode_count: inode_blocks * INODES_PER_BLOCK as u32,
+            bitmap_start: 1,
+            bitmap_blocks,
+            inode_start: 1 + bitmap_blocks,
+            inode_blocks,
+            data_start,
+        })
+    }
+
+    pub fn encode(&self, buf: &mut [u8; BLOCK_SIZE]) {
+        buf.fill(0);
+        buf[0..8].copy_from_slice(&MAGIC);
+        write_u32(buf, 8, VERSION);
+        write_u32(buf, 12, self.block_count);
+        write_u32(buf, 16, self.inode_count);
+        write_u32(buf, 20, self.bitmap_start);
+        write_u32(buf, 24, self.bitmap_blocks);
+        write_u32(buf, 28, self.inode_start);
+        write_u32(buf, 32, self.inode_blocks);
+        write_u32(buf, 36, self.data_start);
+    }
+
+    pub fn decode(buf: &[u8; BLOCK_SIZE]) -> Result<Self, DiskError> {
+        if buf[0..8] != MAGIC {
+            return Err(DiskError::NotStbfs);
+        }
+        let version = read_u32(buf, 8);
+        if version != VERSION {
+            return Err(DiskError::UnsupportedVersion(version));
+        }
+        let superblock = Superblock {
+            block_count: read_u32(buf, 12),
+            inode_count: read_u32(buf, 16),
+            bitmap_start: read_u32(buf, 20),
+            bitmap_blocks: read_u32(buf, 24),
+            inode_start: read_u32(buf, 28),
+            inode_blocks: read_u32(buf, 32),
+            data_start: read_u32(buf, 36),
+        };
+        // in u64 so nonsense numbers can't overflow
+        let bitmap_end = superblock.bitmap_start as u64 + superblock.bitmap_blocks as u64;
+        let inode_end = superblock.inode_start as u64 + superblock.inode_blocks as u64;
+        let laid_out = superblock.bitmap_start >= 1
+            && superblock.bitmap_blocks as u64 * BITS_PER_BLOCK as u64 >= superblock.block_count as u64
+            && bitmap_end <= superblock.inode_start as u64
+            && superblock.inode_count as u64 <= superblock.inode_blocks as u64 * INODES_PER_BLOCK as u64
+            && inode_end <= superblock.data_start as u64
+            && superblock.data_start <= superblock.block_count;
+        if !laid_out {
+            return Err(DiskError::Corrupt);
+        }
+        Ok(superblock)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum InodeKind {
+    Free = 0,
+    File = 1,
+    Directory = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Inode {
+    pub kind: InodeKind,
+    pub size: u32,   // in bytes
+    pub parent: u32, // inode of the directory this is in, the root is its own parent
+    pub direct: [u32; DIRECT_BLOCKS],
+    pub indirect: u32,
+    pub double_indirect: u32,
+    pub mode: u16,     // permission bits, 0o777 at most
+    pub owner: u16,    // user id, 0 is root
+    pub created: u64,  // unix seconds
+    pub modified: u64, // unix seconds
+}
+
+impl Inode {
+    pub fn new(kind: InodeKind, parent: u32) -> Self {
+        Inode {
+            kind,
+            size: 0,
+            parent,
+            direct: [0; DIRECT_BLOCKS],
+            indirect: 0,
+            double_indirect: 0,
+            mode: default_mode(kind),
+            owner: 0,
+            created: 0,
+            modified: 0,
+        }
+    }
+
+    // layout: kind, 3 bytes padding, size, parent, direct blocks, indirect block,
+    // then at 64 mode, owner, created, modified, double-indirect block. The rest is reserved
+    fn encode(&self, buf: &mut [u8]) {
+        buf[..INODE_SIZE].fill(0);
+        buf[0] = self.kind as u8;
+        write_u32(buf, 4, self.size);
+        write_u32(buf, 8, self.parent);
+        for (i, block) in self.direct.iter().enumerate() {
+            write_u32(buf, 12 + i * 4, *block);
+        }
+        write_u32(buf, 12 + DIRECT_BLOCKS * 4, self.indirect);
+        write_u16(buf, 64, (self.mode & 0o777) | MODE_PRESENT);
+        write_u16(buf, 66, self.owner);
+        write_u64(buf, 68, self.created);
+        write_u64(buf, 76, self.modified);
+        write_u32(buf, 84, self.double_indirect);
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self, DiskError> {
+        let kind = match buf[0] {
+            0 => InodeKind::Free,
+            1 => InodeKind::File,
+            2 => InodeKind::Directory,
+            _ => return Err(DiskError::Corrupt),
+        };
+        let mut direct = [0; DIRECT_BLOCKS];
+        for (i, block) in direct.iter_mut().enumerate() {
+            *block = read_u32(buf, 12 + i * 4);
+        }
+        Ok(Inode {
+            kind,
+            size: read_u32(buf, 4),
+            parent: read_u32(buf, 8),
+            direct,
+            indirect: read_u32(buf, 12 + DIRECT_BLOCKS * 4),
+            mode: match read_u16(buf, 64) {
+                mode if mode & MODE_PRESENT != 0 => mode & 0o777,
+                _ => default_mode(kind),
+            },
+            owner: read_u16(buf, 66),
+            created: read_u64(buf, 68),
+            modified: read_u64(buf, 76),
+            double_indirect: read_u32(buf, 84),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub inode: u32,
+    pub name: String,
+}
+
+impl DirEntry {
+    // layout: inode, name length (0 = unused entry), 1 byte padding, name
+    fn encode(&self, buf: &mut [u8]) -> Result<(), DiskError> {
+        let name = self.name.as_bytes();
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(DiskError::NameTooLong);
+        }
+        buf[..DIR_ENTRY_SIZE].fill(0);
+        write_u32(buf, 0, self.inode);
+        buf[4] = name.len() as u8;
+        buf[6..6 + name.len()].copy_from_slice(name);
+        Ok(())
+    }
+
+    fn decode(buf: &[u8]) -> Result<Option<Self>, DiskError> {
+        let len = buf[4] as usize;
+        if len == 0 {
+            return Ok(None);
+        }
+        if len > MAX_NAME_LEN {
+            return Err(DiskError::Corrupt);
+        }
+        let name = core::str::from_utf8(&buf[6..6 + len]).map_err(|_| DiskError::Corrupt)?;
+        Ok(Some(DirEntry {
+            inode: read_u32(buf, 0),
+            name: String::from(name),
+        }))
+    }
+}
+
+/// An STBFS filesystem on a block device.
+pub struct DiskFs<D: BlockDevice> {
+    device: D,
+    superblock: Superblock,
+}
+
+impl<D: BlockDevice> DiskFs<D> {
+    /// Writes an empty filesystem (just the root directory) to the device.
+    pub fn format(mut device: D) -> Result<Self, DiskError> {
+        let block_count = device.block_count().min(u32::MAX as u64) as u32;
+        let superblock = Superblock::new(block_count)?;
+        let mut buf = [0u8; BLOCK_SIZE];
+
+        // the bitmap starts out with just the metadata blocks marked as used
+        for index in 0..superblock.bitmap_blocks {
+            buf.fill(0);
+            let first = index * BITS_PER_BLOCK;
+            for block in first..superblock.data_start.min(first + BITS_PER_BLOCK) {
+                let bit = (block - first) as usize;
+                buf[bit / 8] |= 1 << (bit % 8);
+            }
+            device.write_block((superblock.bitmap_start + index) as u64, &buf)?;
+        }
+        buf.fill(0);
+        for block in superblock.inode_start..superblock.data_start {
+            device.write_block(block as u64, &buf)?;
+        }
+        superblock.encode(&mut buf);
+        device.write_block(0, &buf)?;
+
+        let mut fs = DiskFs { device, superblock };
+        fs.write_inode(ROOT_INODE, &Inode::new(InodeKind::Directory, ROOT_INODE))?;
+        fs.device.flush()?;
+        Ok(fs)
+    }
+
+    /// Opens the filesystem that's already on the device.
+    pub fn mount(mut device: D) -> Result<Self, DiskError> {
+        let mut buf = [0u8; BLOCK_SIZE];
+        device.read_block(0, &mut buf)?;
+        let superblock = Superblock::decode(&buf)?;
+        if superblock.block_count as u64 > device.block_count() {
+            return Err(DiskError::Corrupt);
+        }
+        Ok(DiskFs { device, superblock })
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.superblock
+    }
+
+    pub fn into_device(self) -> D {
+        self.device
+    }
+
+    pub fn flush(&mut self) -> Result<(), DiskError> {
+        Ok(self.device.flush()?)
+    }
+
+    fn inode_location(&self, inode: u32) -> Result<(u64, usize), DiskError> {
+        if inode >= self.superblock.inode_count {
+            return Err(DiskError::Corrupt);
+        }
+        let block = self.superblock.inode_start + inode / INODES_PER_BLOCK as u32;
+        let offset = (inode as usize % INODES_PER_BLOCK) * INODE_SIZE;
+        Ok((block as u64, offset))
+    }
+
+    pub fn read_inode(&mut self, inode: u32) -> Result<Inode, DiskError> {
+        let (block, offset) = self.inode_location(inode)?;
+        let mut buf = [0u8; BLOCK_SIZE];
+        self.device.read_block(block, &mut buf)?;
+        Inode::decode(&buf[offset..offset + INODE_SIZE])
+    }
+
+    pub fn write_inode(&mut self, inode: u32, data: &Inode) -> Result<(), DiskError> {
+        let (block, offset) = self.inode_location(inode)?;
+        let mut buf = [0u8; BLOCK_SIZE];
+        self.device.read_block(block, &mut buf)?;
+        data.encode(&mut buf[offset..offset + INODE_SIZE]);
+        self.device.write_block(block, &buf)?;
+        Ok(())
+    }
+
+    /// Finds an inode nobody uses.
+    pub fn find_free_inode(&mut self) -> Result<u32, DiskError> {
+        for inode in 0..self.superblock.inode_count {
+            if self.read_inode(inode)?.kind == InodeKind::Free {
+                return Ok(inode);
+            }
+        }
+        Err(DiskError::NoInodes)
+    }
+
+    fn set_block_used(&mut self, block: u32, used: bool) -> Result<(), DiskError> {
+        let bitmap_block = self.superblock.bitmap_start + block / BITS_PER_BLOCK;
+        let bit = (block % BITS_PER_BLOCK) as usize;
+        let mut buf = [0u8; BLOCK_SIZE];
+        self.device.read_block(bitmap_block as u64, &mut buf)?;
+        if used {
+            buf[bit / 8] |= 1 << (bit % 8);
+        } else {
+            buf[bit / 8] &= !(1 << (bit % 8));
+        }
+        self.device.write_block(bitmap_block as u64, &buf)?;
+        Ok(())
+    }
+
+    /// Takes a free data block out of the bitmap.
+    fn alloc_block(&mut self) -> Result<u32, DiskError> {
+        let mut buf = [0u8; BLOCK_SIZE];
+        for index in 0..self.superblock.bitmap_blocks {
+            let bitmap_block = self.superblock.bitmap_start + index;
+            self.device.read_block(bitmap_block as u64, &mut buf)?;
+            for (byte_index, byte) in buf.iter_mut().enumerate() {
+                if *byte == 0xFF {
+                    continue;
+                }
+                let bit = byte.trailing_ones();
+                let block = index * BITS_PER_BLOCK + byte_index as u32 * 8 + bit;
+                if block >= self.superblock.block_count {
+                    return Err(DiskError::NoSpace);
+                }
+                *byte |= 1 << bit;
+                self.device.write_block(bitmap_block as u64, &buf)?;
+                return Ok(block);
+            }
+        }
+        Err(DiskError::NoSpace)
+    }
+
+    fn is_data_block(&self, block: u32) -> bool {
+        block >= self.superblock.data_start && block < self.superblock.block_count
+    }
+
+    /// Appends the first `count` block numbers in the pointer block `block` to `blocks`.
+    fn read_pointers(&mut self, block: u32, count: usize, blocks: &mut Vec<u32>) -> Result<(), DiskError> {
+        if !self.is_data_block(block) {
+            return Err(DiskError::Corrupt);
+        }
+        let mut buf = [0u8; BLOCK_SIZE];
+        self.device.read_block(block as u64, &mut buf)?;
+        blocks.extend((0..count).map(|i| read_u32(&buf, i * 4)));
+        Ok(())
+    }
+
+    /// Puts block numbers in a new pointer block and returns where it is.
+    fn write_pointers(&mut self, blocks: &[u32], allocated: &mut Vec<u32>) -> Result<u32, DiskError> {
+        let mut buf = [0u8; BLOCK_SIZE];
+        for (i, block) in blocks.iter().enumerate() {
+            write_u32(&mut buf, i * 4, *block);
+        }
+        let block = self.alloc_block()?;
+        allocated.push(block);
+        self.device.write_block(block as u64, &buf)?;
+        Ok(block)
+    }
+
+    /// The data blocks of an inode in order, and the blocks that only hold pointers to them.
+    fn block_map(&mut self, inode: &Inode) -> Result<(Vec<u32>, Vec<u32>), DiskError> {
+        let count = (inode.size as usize).div_ceil(BLOCK_SIZE);
+        if count > MAX_FILE_BLOCKS {
+            return Err(DiskError::Corrupt);
+        }
+        let mut blocks: Vec<u32> = inode.direct.iter().copied().take(count).collect();
+        let mut pointers = Vec::new();
+        if inode.indirect != 0 {
+            pointers.push(inode.indirect);
+        }
+        if count > DIRECT_BLOCKS {
+            let wanted = (count - DIRECT_BLOCKS).min(POINTERS_PER_BLOCK);
+            self.read_pointers(inode.indirect, wanted, &mut blocks)?;
+        }
+        if inode.double_indirect != 0 {
+            pointers.push(inode.double_indirect);
+        }
+        if count > DIRECT_BLOCKS + POINTERS_PER_BLOCK {
+            let left = count - DIRECT_BLOCKS - POINTERS_PER_BLOCK;
+            let mut indirect = Vec::new();
+            let wanted = left.div_ceil(POINTERS_PER_BLOCK);
+            self.read_pointers(inode.double_indirect, wanted, &mut indirect)?;
+            for (i, &block) in indirect.iter().enumerate() {
+                let wanted = (left - i * POINTERS_PER_BLOCK).min(POINTERS_PER_BLOCK);
+                self.read_pointers(block, wanted, &mut blocks)?;
+            }
+            pointers.extend(indirect);
+        }
+        if blocks.iter().chain(&pointers).any(|&block| !self.is_data_block(block)) {
+            return Err(DiskError::Corrupt);
+        }
+        Ok((blocks, pointers))
+    }
+
+    /// The data blocks of an inode, in order.
+    pub fn data_blocks(&mut self, inode: &Inode) -> Result<Vec<u32>, DiskError> {
+        Ok(self.block_map(inode)?.0)
+    }
+
+    pub fn read_data(&mut self, inode: &Inode) -> Result<Vec<u8>, DiskError> {
+        let mut data = Vec::with_capacity(inode.size as usize);
+        let mut buf = [0u8; BLOCK_SIZE];
+        for block in self.data_blocks(inode)? {
+            self.device.read_block(block as u64, &mut buf)?;
+            let wanted = (inode.size as usize - data.len()).min(BLOCK_SIZE);
+            data.extend_from_slice(&buf[..wanted]);
+        }
+        Ok(data)
+    }
+
+    /// Gives all data blocks of an inode back to the bitmap, leaving it empty.
+    pub fn free_data(&mut self, inode: &mut Inode) -> Result<(), DiskError> {
+        let (blocks, pointers) = self.block_map(inode)?;
+        for block in blocks.into_iter().chain(pointers) {
+            self.set_block_used(block, false)?;
+        }
+        inode.size = 0;
+        inode.direct = [0; DIRECT_BLOCKS];
+        inode.indirect = 0;
+        inode.double_indirect = 0;
+        Ok(())
+    }
+
+    /// Replaces the data of an inode. The caller still has to write the inode itself. The new data is written
+    /// before the old one is freed, so when it doesn't fit (or the disk fails) the inode still has what it had.
+    pub fn write_data(&mut self, inode: &mut Inode, data: &[u8]) -> Result<(), DiskError> {
+        if data.len().div_ceil(BLOCK_SIZE) > MAX_FILE_BLOCKS {
+            return Err(DiskError::FileTooBig);
+        }
+        let mut new = Inode {
+            size: 0,
+            direct: [0; DIRECT_BLOCKS],
+            indirect: 0,
+            double_indirect: 0,
+            ..*inode
+        };
+        let mut allocated = Vec::new();
+        if let Err(error) = self.place_data(&mut new, data, &mut allocated) {
+            for block in allocated {
+                self.set_block_used(block, false)?;
+            }
+            return Err(error);
+        }
+        self.free_data(inode)?;
+        *inode = new;
+        Ok(())
+    }
+
+    /// Writes `data` to new blocks and points the empty `inode` at them. Every block it takes is added to
+    /// `allocated`, also when it fails halfway.
+    fn place_data(&mut self, inode: &mut Inode, data: &[u8], allocated: &mut Vec<u32>) -> Result<(), DiskError> {
+        let mut buf = [0u8; BLOCK_SIZE];
+        for chunk in data.chunks(BLOCK_SIZE) {
+            let block = self.alloc_block()?;
+            allocated.push(block);
+            buf.fill(0);
+            buf[..chunk.len()].copy_from_slice(chunk);
+            self.device.write_block(block as u64, &buf)?;
+        }
+        let blocks = allocated.clone();
+        let direct = blocks.len().min(DIRECT_BLOCKS);
+        inode.direct[..direct].copy_from_slice(&blocks[..direct]);
+        let (indirect, double_indirect) = blocks[direct..].split_at((blocks.len() - direct).min(POINTERS_PER_BLOCK));
+        if !indirect.is_empty() {
+            inode.indirect = self.write_pointers(indirect, allocated)?;
+        }
+        if !double_indirect.is_empty() {
+            let mut pointers = Vec::new();
+            for chunk in double_indirect.chunks(POINTERS_PER_BLOCK) {
+                pointers.push(self.write_pointers(chunk, allocated)?);
+            }
+            inode.double_indirect = self.write_pointers(&pointers, allocated)?;
+        }
+        inode.size = data.len() as u32;
+        Ok(())
+    }
+
+    pub fn read_dir(&mut self, inode: &Inode) -> Result<Vec<DirEntry>, DiskError> {
+        let data = self.read_data(inode)?;
+        let mut entries = Vec::new();
+        for chunk in data.chunks_exact(DIR_ENTRY_SIZE) {
+            if let Some(entry) = DirEntry::decode(chunk)? {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Replaces the entries of a directory. The caller still has to write the inode itself.
+    pub fn write_dir(&mut self, inode: &mut Inode, entries: &[DirEntry]) -> Result<(), DiskError> {
+        let mut data = vec![0u8; entries.len() * DIR_ENTRY_SIZE];
+        for (entry, chunk) in entries.iter().zip(data.chunks_exact_mut(DIR_ENTRY_SIZE)) {
+            entry.encode(chunk)?;
+        }
+        self.write_data(inode, &data)
+    }
+
+    /// The entries of the directory with the given inode number.
+    pub fn list(&mut self, dir: u32) -> Result<Vec<DirEntry>, DiskError> {
+        let inode = self.read_inode(dir)?;
+        if inode.kind != InodeKind::Directory {
+            return Err(DiskError::NotADirectory);
+        }
+        self.read_dir(&inode)
+    }
+
+    /// Finds `name` in a directory.
+    pub fn lookup(&mut self, dir: u32, name: &str) -> Result<u32, DiskError> {
+        self.list(dir)?
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.inode)
+            .ok_or(DiskError::NotFound)
+    }
+
+    /// Walks a path from the root, `$/` in front is optional (`$/kernl/stbos.uff` or `kernl/stbos.uff`).
+    pub fn resolve(&mut self, path: &str) -> Result<u32, DiskError> {
+        let path = path.strip_prefix('$').unwrap_or(path);
+        let mut current = ROOT_INODE;
+        for component in path.split('/') {
+            current = match component {
+                "" | "." => current,
+                ".." => self.read_inode(current)?.parent,
+                name => self.lookup(current, name)?,
+            };
+        }
+        Ok(current)
+    }
+
+    /// Makes a new empty file or directory called `name` in a directory and returns its inode number.
+    /// `time` (unix seconds) goes in as both its creation and modification time.
+    pub fn create(&mut self, dir: u32, name: &str, kind: InodeKind, time: u64) -> Result<u32, DiskError> {
+        let mut dir_inode = self.read_inode(dir)?;
+        if dir_inode.kind != InodeKind::Directory {
+            return Err(DiskError::NotADirectory);
+        }
+        if name.len() > MAX_NAME_LEN {
+            return Err(DiskError::NameTooLong);
+        }
+        let mut entries = self.read_dir(&dir_inode)?;
+        if entries.iter().any(|entry| entry.name == name) {
+            return Err(DiskError::AlreadyExists);
+        }
+
+        let inode = self.find_free_inode()?;
+        let mut new_inode = Inode::new(kind, dir);
+        new_inode.created = time;
+        new_inode.modified = time;
+        self.write_inode(inode, &new_inode)?;
+        entries.push(DirEntry {
+            inode,
+            name: String::from(name),
+        });
+        self.write_dir(&mut dir_inode, &entries)?;
+        self.write_inode(dir, &dir_inode)?;
+        Ok(inode)
+    }
+
+    /// Replaces the content of a file, inode and all.
+    pub fn write_file(&mut self, file: u32, data: &[u8]) -> Result<(), DiskError> {
+        let mut inode = self.read_inode(file)?;
+        if inode.kind != InodeKind::File {
+            return Err(DiskError::Corrupt);
+        }
+        self.write_data(&mut inode, data)?;
+        self.write_inode(file, &inode)
+    }
+
+    /// Takes a file out of a directory and frees its inode and data.
+    pub fn remove_file(&mut self, dir: u32, name: &str) -> Result<(), DiskError> {
+        let mut dir_inode = self.read_inode(dir)?;
+        if dir_inode.kind != InodeKind::Directory {
+            return Err(DiskError::NotADirectory);
+        }
+        let mut entries = self.read_dir(&dir_inode)?;
+        let index = entries.iter().position(|entry| entry.name == name).ok_or(DiskError::NotFound)?;
+        let file = entries[index].inode;
+        let mut inode = self.read_inode(file)?;
+        match inode.kind {
+            InodeKind::File => {}
+            InodeKind::Directory => return Err(DiskError::IsADirectory),
+            InodeKind::Free => return Err(DiskError::Corrupt),
+        }
+        entries.remove(index);
+        self.write_dir(&mut dir_inode, &entries)?;
+        self.write_inode(dir, &dir_inode)?;
+        self.free_data(&mut inode)?;
+        self.write_inode(file, &Inode::new(InodeKind::Free, ROOT_INODE))
+    }
+
+    /// The content of a file.
+    pub fn read_file(&mut self, file: u32) -> Result<Vec<u8>, DiskError> {
+        let inode = self.read_inode(file)?;
+        if inode.kind != InodeKind::File {
+            return Err(DiskError::Corrupt);
+        }
+        self.read_data(&inode)
+    }
+
+    fn is_block_used(&mut self, block: u32) -> Result<bool, DiskError> {
+        let bitmap_block = self.superblock.bitmap_start + block / BITS_PER_BLOCK;
+        let bit = (block % BITS_PER_BLOCK) as usize;
+        let mut buf = [0u8; BLOCK_SIZE];
+        self.device.read_block(bitmap_block as u64, &mut buf)?;
+        Ok(buf[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// Checks the whole filesystem for problems. With `repair` it also fixes the ones it can:
+    /// the bitmap gets rebuilt from the blocks actually in use and unreachable inodes are freed.
+    pub fn check(&mut self, repair: bool) -> Result<Vec<Problem>, DiskError> {
+        let mut problems = Vec::new();
+        let mut reachable = BTreeSet::new();
+        let mut used_blocks = BTreeSet::new();
+        reachable.insert(ROOT_INODE);
+
+        let mut pending = vec![ROOT_INODE];
+        while let Some(number) = pending.pop() {
+            let inode = match self.read_inode(number) {
+                Ok(inode) => inode,
+                Err(DiskError::Corrupt) => {
+                    problems.push(Problem::BadInode(number));
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+            if inode.kind == InodeKind::Free {
+                problems.push(Problem::FreeInodeInUse(number));
+                continue;
+            }
+            let (blocks, pointers) = match self.block_map(&inode) {
+                Ok(map) => map,
+                Err(DiskError::Corrupt) => {
+                    problems.push(Problem::BadInode(number));
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+            for block in blocks.into_iter().chain(pointers) {
+                if !used_blocks.insert(block) {
+                    problems.push(Problem::BlockSharedBy(block, number));
+                }
+            }
+            if inode.kind != InodeKind::Directory {
+                continue;
+            }
+
+            for entry in self.read_dir(&inode)? {
+                if entry.inode >= self.superblock.inode_count {
+                    problems.push(Problem::BadEntry(number, entry.name));
+                } else if !reachable.insert(entry.inode) {
+                    problems.push(Problem::LinkedTwice(entry.inode));
+                } else {
+                    if self.read_inode(entry.inode).map(|child| child.parent) != Ok(number) {
+                        problems.push(Problem::WrongParent(entry.inode));
+                    }
+                    pending.push(entry.inode);
+                }
+            }
+        }
+
+        for number in 0..self.superblock.inode_count {
+            if reachable.contains(&number) {
+                continue;
+            }
+            let orphan = match self.read_inode(number) {
+                Ok(inode) => inode.kind != InodeKind::Free,
+                Err(_) => true,
+            };
+            if orphan {
+                problems.push(Problem::Orphan(number));
+                if repair {
+                    self.write_inode(number, &Inode::new(InodeKind::Free, ROOT_INODE))?;
+                }
+            }
+        }
+
+        for block in self.superblock.data_start..self.superblock.block_count {
+            let marked = self.is_block_used(block)?;
+            let used = used_blocks.contains(&block);
+            if marked != used {
+                problems.push(if used { Problem::UsedBlockFree(block) } else { Problem::LeakedBlock(block) });
+                if repair {
+                    self.set_block_used(block, used)?;
+                }
+            }
+        }
+        if repair {
+            self.device.flush()?;
+        }
+        Ok(problems)
+    }
+}
+
+/// Something `DiskFs::check` found wrong with a filesystem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    BadInode(u32),
+    FreeInodeInUse(u32),
+    BadEntry(u32, String),
+    LinkedTwice(u32),
+    WrongParent(u32),
+    BlockSharedBy(u32, u32),
+    Orphan(u32),
+    UsedBlockFree(u32),
+    LeakedBlock(u32),
+}
+
+impl Problem {
+    /// Whether `check(true)` fixes this one.
+    pub fn is_repairable(&self) -> bool {
+        matches!(self, Problem::Orphan(_) | Problem::UsedBlockFree(_) | Problem::LeakedBlock(_))
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::BadInode(inode) => write!(f, "inode {} is unreadable", inode),
+            Problem::FreeInodeInUse(inode) => write!(f, "inode {} is linked but marked free", inode),
+            Problem::BadEntry(dir, name) => write!(f, "entry '{}' in directory inode {} points nowhere", name, dir),
+            Problem::LinkedTwice(inode) => write!(f, "inode {} is linked more than once", inode),
+            Problem::WrongParent(inode) => write!(f, "inode {} has the wrong parent", inode),
+            Problem::BlockSharedBy(block, inode) => write!(f, "block {} of inode {} is used twice", block, inode),
+            Problem::Orphan(inode) => write!(f, "inode {} is in use but not linked anywhere", inode),
+            Problem::UsedBlockFree(block) => write!(f, "block {} is in use but marked free", block),
+            Problem::LeakedBlock(block) => write!(f, "block {} is marked used but nothing uses it", block),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A disk that's just blocks in a Vec.
+    struct RamDisk {
+        blocks: Vec<[u8; BLOCK_SIZE]>,
+    }
+
+    impl BlockDevice for RamDisk {
+        fn block_count(&self) -> u64 {
+            self.blocks.len() as u64
+        }
+
+        fn read_block(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), BlockError> {
+            *buf = *self.blocks.get(block as usize).ok_or(BlockError::OutOfRange)?;
+            Ok(())
+        }
+
+        fn write_block(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), BlockError> {
+            *self.blocks.get_mut(block as usize).ok_or(BlockError::OutOfRange)? = *buf;
+            Ok(())
+        }
+    }
+
+    fn fresh(blocks: usize) -> DiskFs<RamDisk> {
+        DiskFs::format(RamDisk { blocks: vec![[0; BLOCK_SIZE]; blocks] }).unwrap()
+    }
+
+    /// Different bytes in every block, so blocks that got mixed up show.
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i / BLOCK_SIZE + i) as u8).collect()
+    }
+
+    #[test]
+    fn format_and_mount() {
+        let fs = fresh(256);
+        let superblock = *fs.superblock();
+        let mut fs = DiskFs::mount(fs.into_device()).unwrap();
+        assert_eq!(*fs.superblock(), superblock);
+        assert_eq!(fs.list(ROOT_INODE).unwrap(), Vec::new());
+        assert_eq!(fs.check(false).unwrap(), Vec::new());
+        assert_eq!(DiskFs::format(RamDisk { blocks: vec![[0; BLOCK_SIZE]; 8] }).err(), Some(DiskError::TooSmall));
+    }
+
+    #[test]
+    fn write_and_read() {
+        let mut fs = fresh(256);
+        let docs = fs.create(ROOT_INODE, "docs", InodeKind::Directory, 1).unwrap();
+        let notes = fs.create(docs, "notes.txt", InodeKind::File, 2).unwrap();
+        fs.write_file(notes, b"hello").unwrap();
+        assert_eq!(fs.read_file(notes).unwrap(), b"hello");
+        assert_eq!(fs.resolve("$/docs/notes.txt"), Ok(notes));
+        assert_eq!(fs.read_inode(notes).unwrap().created, 2);
+        assert_eq!(fs.create(docs, "notes.txt", InodeKind::File, 3), Err(DiskError::AlreadyExists));
+
+        fs.write_file(notes, b"hi").unwrap();
+        let mut fs = DiskFs::mount(fs.into_device()).unwrap();
+        assert_eq!(fs.read_file(notes).unwrap(), b"hi");
+        assert_eq!(fs.check(false).unwrap(), Vec::new());
+
+        assert_eq!(fs.remove_file(ROOT_INODE, "docs"), Err(DiskError::IsADirectory));
+        fs.remove_file(docs, "notes.txt").unwrap();
+        assert_eq!(fs.list(docs).unwrap(), Vec::new());
+        assert_eq!(fs.read_inode(notes).unwrap().kind, InodeKind::Free);
+        assert_eq!(fs.check(false).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn indirect_blocks() {
+        let mut fs = fresh(2048);
+        let file = fs.create(ROOT_INODE, "big", InodeKind::File, 0).unwrap();
+        let sizes = [
+            DIRECT_BLOCKS * BLOCK_SIZE + 1,                              // just into the indirect block
+            (DIRECT_BLOCKS + POINTERS_PER_BLOCK) * BLOCK_SIZE + 1,       // just into the double-indirect one
+            (DIRECT_BLOCKS + 3 * POINTERS_PER_BLOCK) * BLOCK_SIZE + 100, // a few indirect blocks deep in it
+            10,                                                          // and small again
+        ];
+        for size in sizes {
+            let data = pattern(size);
+            fs.write_file(file, &data).unwrap();
+            assert_eq!(fs.read_file(file).unwrap(), data);
+            assert_eq!(fs.check(false).unwrap(), Vec::new());
+        }
+        let inode = fs.read_inode(file).unwrap();
+        assert_eq!((inode.indirect, inode.double_indirect), (0, 0));
+    }
+
+    #[test]
+    fn too_big() {
+        let mut fs = fresh(256);
+        let file = fs.create(ROOT_INODE, "huge", InodeKind::File, 0).unwrap();
+        let data = vec![0; MAX_FILE_BLOCKS * BLOCK_SIZE + 1];
+        assert_eq!(fs.write_file(file, &data), Err(DiskError::FileTooBig));
+    }
+
+    #[test]
+    fn full_disk() {
+        let mut fs = fresh(64);
+        let file = fs.create(ROOT_INODE, "file", InodeKind::File, 0).unwrap();
+        fs.write_file(file, &pattern(40 * BLOCK_SIZE)).unwrap();
+        // doesn't fit next to the old data, which has to stay, and what it took has to come back
+        assert_eq!(fs.write_file(file, &pattern(30 * BLOCK_SIZE)), Err(DiskError::NoSpace));
+        assert_eq!(fs.read_file(file).unwrap(), pattern(40 * BLOCK_SIZE));
+        assert_eq!(fs.check(false).unwrap(), Vec::new());
+        fs.write_file(file, &pattern(10 * BLOCK_SIZE)).unwrap();
+        assert_eq!(fs.check(false).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn bad_superblock() {
+        let good = Superblock::new(10000).unwrap();
+        let mut buf = [0u8; BLOCK_SIZE];
+        good.encode(&mut buf);
+        assert_eq!(Superblock::decode(&buf), Ok(good));
+
+        let broken = [
+            Superblock { bitmap_blocks: 0, ..good },        // doesn't cover the disk
+            Superblock { block_count: 100000, ..good },     // neither
+            Superblock { bitmap_start: 0, ..good },         // on top of the superblock
+            Superblock { inode_start: 1, ..good },          // on top of the bitmap
+            Superblock { inode_count: u32::MAX, ..good },   // more than the table holds
+            Superblock { inode_blocks: u32::MAX, ..good },  // past the end
+            Superblock { data_start: 2, ..good },           // in the inode table
+            Superblock { data_start: 20000, ..good },       // past the end
+        ];
+        for superblock in broken {
+            superblock.encode(&mut buf);
+            assert_eq!(Superblock::decode(&buf), Err(DiskError::Corrupt), "{:?}", superblock);
+        }
+        buf[0] = b'X';
+        assert_eq!(Superblock::decode(&buf), Err(DiskError::NotStbfs));
+    }
+}
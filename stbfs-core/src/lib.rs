@@ -0,0 +1,9 @@
+/* The parts of STBFS that don't care whether they run in the kernel or on the host: the block device trait
+   and the on-disk format. The kernel uses it to mount its data disk and tools/mkstbfs to build disk images. */
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod block;
+pub mod disk;
@@ -0,0 +1,31 @@
+/* Boots with nothing set up but what the bootloader did, not even `init`, and checks printing already works. */
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(admiralix_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use admiralix_os::{println, serial_println};
+use core::panic::PanicInfo;
+
+#[no_mangle] // the bootloader jumps to _start
+pub extern "C" fn _start() -> ! {
+    test_main();
+    admiralix_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    admiralix_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_println() {
+    println!("test_println output");
+}
+
+#[test_case]
+fn test_serial_println() {
+    serial_println!("test_serial_println output");
+}
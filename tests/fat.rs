@@ -0,0 +1,215 @@
+/* The FAT driver on its own: every test formats a FAT12, FAT16 or FAT32 volume on a disk in memory, the way
+   `mkfs.vfat` lays them out, and mounts it with `FatVolume` directly, so the disks the kernel finds don't matter. */
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(admiralix_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use admiralix_os::{
+    block::{BlockDevice, BlockError, BLOCK_SIZE},
+    fat::{self, FatError, FatType, FatVolume},
+};
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    admiralix_os::init();
+    admiralix_os::init_memory(boot_info);
+    test_main();
+    admiralix_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    admiralix_os::test_panic_handler(info)
+}
+
+/// A disk in memory that only keeps the blocks written to it, the rest reads as zeros. FAT32 needs at least 65525
+/// clusters, which wouldn't fit in the heap otherwise.
+struct SparseDisk {
+    blocks: BTreeMap<u64, [u8; BLOCK_SIZE]>,
+    count: u64,
+}
+
+impl BlockDevice for SparseDisk {
+    fn block_count(&self) -> u64 {
+        self.count
+    }
+
+    fn read_block(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), BlockError> {
+        if block >= self.count {
+            return Err(BlockError::OutOfRange);
+        }
+        *buf = self.blocks.get(&block).copied().unwrap_or([0; BLOCK_SIZE]);
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), BlockError> {
+        if block >= self.count {
+            return Err(BlockError::OutOfRange);
+        }
+        self.blocks.insert(block, *buf);
+        Ok(())
+    }
+}
+
+/// An empty volume of the given type, one sector per cluster and two FATs.
+fn format(fat_type: FatType) -> SparseDisk {
+    let (sectors, reserved, root_entries, fat_sectors): (u32, u16, u16, u32) = match fat_type {
+        FatType::Fat12 => (2048, 1, 224, 6),
+        FatType::Fat16 => (16384, 1, 512, 64),
+        FatType::Fat32 => (70000, 32, 0, 550),
+    };
+    let mut disk = SparseDisk { blocks: BTreeMap::new(), count: sectors as u64 };
+
+    let mut boot = [0u8; BLOCK_SIZE];
+    boot[..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+    boot[3..11].copy_from_slice(b"MSWIN4.1");
+    boot[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+    boot[13] = 1;
+    boot[14..16].copy_from_slice(&reserved.to_le_bytes());
+    boot[16] = 2;
+    boot[17..19].copy_from_slice(&root_entries.to_le_bytes());
+    boot[21] = 0xF8; // a fixed disk
+    match fat_type {
+        FatType::Fat32 => {
+            boot[32..36].copy_from_slice(&sectors.to_le_bytes());
+            boot[36..40].copy_from_slice(&fat_sectors.to_le_bytes());
+            boot[44..48].copy_from_slice(&2u32.to_le_bytes()); // the root directory's cluster
+            boot[48..50].copy_from_slice(&1u16.to_le_bytes()); // FSInfo
+            boot[71..82].copy_from_slice(b"TEST FAT32 ");
+            boot[82..90].copy_from_slice(b"FAT32   ");
+        }
+        _ => {
+            boot[19..21].copy_from_slice(&(sectors as u16).to_le_bytes());
+            boot[22..24].copy_from_slice(&(fat_sectors as u16).to_le_bytes());
+            boot[43..54].copy_from_slice(b"TEST FAT   ");
+            boot[54..62].copy_from_slice(if fat_type == FatType::Fat12 { b"FAT12   " } else { b"FAT16   " });
+        }
+    }
+    boot[510] = 0x55;
+    boot[511] = 0xAA;
+    disk.write_block(0, &boot).unwrap();
+
+    if fat_type == FatType::Fat32 {
+        let mut fs_info = [0u8; BLOCK_SIZE];
+        fs_info[..4].copy_from_slice(&0x4161_5252u32.to_le_bytes());
+        fs_info[484..488].copy_from_slice(&0x6141_7272u32.to_le_bytes());
+        fs_info[488..496].copy_from_slice(&[0xFF; 8]); // free count and next free unknown
+        fs_info[510] = 0x55;
+        fs_info[511] = 0xAA;
+        disk.write_block(1, &fs_info).unwrap();
+    }
+
+    // clusters 0 and 1 are reserved, on FAT32 cluster 2 is the (empty) root directory
+    let mut first = [0u8; BLOCK_SIZE];
+    match fat_type {
+        FatType::Fat12 => first[..3].copy_from_slice(&[0xF8, 0xFF, 0xFF]),
+        FatType::Fat16 => first[..4].copy_from_slice(&[0xF8, 0xFF, 0xFF, 0xFF]),
+        FatType::Fat32 => {
+            first[..4].copy_from_slice(&0x0FFF_FFF8u32.to_le_bytes());
+            first[4..8].copy_from_slice(&0x0FFF_FFFFu32.to_le_bytes());
+            first[8..12].copy_from_slice(&0x0FFF_FFFFu32.to_le_bytes());
+        }
+    }
+    for copy in 0..2 {
+        disk.write_block(reserved as u64 + copy * fat_sectors as u64, &first).unwrap();
+    }
+    disk
+}
+
+/// Different bytes in every sector, so sectors that got mixed up show.
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i / BLOCK_SIZE + i) as u8).collect()
+}
+
+fn names(volume: &mut FatVolume<SparseDisk>, path: &str) -> Vec<String> {
+    let dir = volume.resolve(path).unwrap();
+    volume.read_dir(dir.cluster).unwrap().into_iter().map(|node| node.name).collect()
+}
+
+fn round_trip(fat_type: FatType) {
+    let mut disk = format(fat_type);
+    assert!(fat::is_fat(&mut disk));
+    let mut volume = FatVolume::mount(disk).unwrap();
+    assert_eq!(volume.fat_type(), fat_type);
+
+    let root = volume.resolve("").unwrap();
+    let docs = volume.create(&root, "docs", true).unwrap();
+    let mut notes = volume.create(&docs, "notes.txt", false).unwrap();
+    let mut long = volume.create(&docs, "A long file name.text", false).unwrap();
+    assert_eq!(volume.create(&docs, "NOTES.TXT", false).err(), Some(FatError::AlreadyExists));
+    assert_ne!(notes.created, 0); // made now, by the clock
+    assert_eq!(notes.modified, notes.created);
+    volume.write_file(&mut notes, &pattern(3000)).unwrap(); // a chain of 6 clusters
+    volume.write_file(&mut long, b"short").unwrap();
+    volume.write_file(&mut notes, &pattern(1000)).unwrap(); // and shorter again
+
+    // mounted again, so nothing comes out of what the first one had in memory
+    let mut volume = FatVolume::mount(volume.into_device()).unwrap();
+    let notes = volume.resolve("docs/notes.txt").unwrap();
+    assert_eq!(volume.read_file(&notes).unwrap(), pattern(1000));
+    assert!(notes.created != 0 && notes.modified >= notes.created);
+    let long = volume.resolve("DOCS/a long file name.text").unwrap();
+    assert_eq!(volume.read_file(&long).unwrap(), b"short");
+    assert_eq!(names(&mut volume, "docs"), ["notes.txt", "A long file name.text"]);
+
+    let root = volume.resolve("").unwrap();
+    let docs = volume.resolve("docs").unwrap();
+    assert_eq!(volume.remove(&root, "docs").err(), Some(FatError::NotEmpty));
+    volume.remove(&docs, "notes.txt").unwrap();
+    volume.remove(&docs, "A long file name.text").unwrap();
+    volume.remove(&root, "docs").unwrap();
+    assert_eq!(names(&mut volume, ""), Vec::<String>::new());
+    assert_eq!(volume.resolve("docs").err(), Some(FatError::NotFound));
+}
+
+/// More entries than fit in a cluster, so the directory has to grow.
+fn big_directory(fat_type: FatType) {
+    let mut volume = FatVolume::mount(format(fat_type)).unwrap();
+    let root = volume.resolve("").unwrap();
+    let dir = volume.create(&root, "many", true).unwrap();
+    let expected: Vec<String> = (0..40).map(|i| format!("file number {}.txt", i)).collect();
+    for name in &expected {
+        let mut file = volume.create(&dir, name, false).unwrap();
+        volume.write_file(&mut file, name.as_bytes()).unwrap();
+    }
+    assert_eq!(names(&mut volume, "many"), expected);
+    let file = volume.resolve("many/file number 39.txt").unwrap();
+    assert_eq!(volume.read_file(&file).unwrap(), b"file number 39.txt");
+}
+
+#[test_case]
+fn fat12() {
+    round_trip(FatType::Fat12);
+}
+
+#[test_case]
+fn fat16() {
+    round_trip(FatType::Fat16);
+}
+
+#[test_case]
+fn fat32() {
+    round_trip(FatType::Fat32);
+}
+
+#[test_case]
+fn directories_grow() {
+    big_directory(FatType::Fat12);
+    big_directory(FatType::Fat32);
+}
+
+#[test_case]
+fn not_fat() {
+    let mut disk = SparseDisk { blocks: BTreeMap::new(), count: 2048 };
+    assert!(!fat::is_fat(&mut disk));
+    assert_eq!(FatVolume::mount(disk).err(), Some(FatError::NotFat));
+}
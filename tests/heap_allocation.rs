@@ -0,0 +1,74 @@
+/* The heap: small and big allocations, lots of them, and that freed memory really comes back. */
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(admiralix_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use admiralix_os::allocator::{self, HEAP_SIZE};
+use alloc::{boxed::Box, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    admiralix_os::init();
+    admiralix_os::init_memory(boot_info);
+    test_main();
+    admiralix_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    admiralix_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn simple_allocation() {
+    let heap_value_1 = Box::new(41);
+    let heap_value_2 = Box::new(13);
+    assert_eq!(*heap_value_1, 41);
+    assert_eq!(*heap_value_2, 13);
+}
+
+#[test_case]
+fn large_vec() {
+    let n = 1000;
+    let mut vec = Vec::new();
+    for i in 0..n {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+}
+
+#[test_case]
+fn many_boxes() {
+    // together way more than the heap, so this only works if every box is freed again
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+}
+
+#[test_case]
+fn many_boxes_long_lived() {
+    let long_lived = Box::new(1); // the allocator has to reuse the memory around this one
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+    assert_eq!(*long_lived, 1);
+}
+
+#[test_case]
+fn freed_memory_comes_back() {
+    let before = allocator::stats().used;
+    let buffer: Vec<u8> = Vec::with_capacity(HEAP_SIZE / 4);
+    assert!(allocator::stats().used >= before + HEAP_SIZE / 4);
+    drop(buffer);
+    assert_eq!(allocator::stats().used, before);
+}
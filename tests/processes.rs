@@ -0,0 +1,319 @@
+/* User mode: small programs written in assembly right here, wrapped in an ELF header and run in ring 3. They make
+   syscalls, get their arguments and input, grow their heap, write a file, crash in ways that only end them, and
+   get killed. */
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(admiralix_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use admiralix_os::{
+    allocator::HEAP_START,
+    commands::{Output, Terminal},
+    process::{self, ExitStatus, Fault, Finished, ProcessError, Program, USER_START},
+    thread,
+    vfs::VFS,
+};
+use alloc::{string::String, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::{arch::global_asm, panic::PanicInfo, ptr::addr_of, slice};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    admiralix_os::init();
+    admiralix_os::init_memory(boot_info);
+    thread::init();
+    test_main();
+    admiralix_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    admiralix_os::test_panic_handler(info)
+}
+
+global_asm!(
+    // write(1, "hello\n", 6), exit(7)
+    ".global hello_start",
+    "hello_start:",
+    "    mov eax, 1",
+    "    mov edi, 1",
+    "    lea rsi, [rip + hello_message]",
+    "    mov edx, 6",
+    "    int 0x80",
+    "    xor eax, eax",
+    "    mov edi, 7",
+    "    int 0x80",
+    "hello_message:",
+    "    .ascii \"hello\\n\"",
+    ".global hello_end",
+    "hello_end:",
+    "",
+    // exit(argc), argc is in rdi already
+    ".global argc_start",
+    "argc_start:",
+    "    xor eax, eax",
+    "    int 0x80",
+    ".global argc_end",
+    "argc_end:",
+    "",
+    // copies up to 64 bytes from fd 0 to fd 1 and exits with how many
+    ".global cat_start",
+    "cat_start:",
+    "    sub rsp, 64",
+    "    mov eax, 2",
+    "    xor edi, edi",
+    "    mov rsi, rsp",
+    "    mov edx, 64",
+    "    int 0x80",
+    "    mov rdx, rax",
+    "    mov eax, 1",
+    "    mov edi, 1",
+    "    int 0x80",
+    "    mov rdi, rdx",
+    "    xor eax, eax",
+    "    int 0x80",
+    ".global cat_end",
+    "cat_end:",
+    "",
+    // sbrk(8192), stores 42 near the end of it, sbrk(0), exits with the 42 plus how far the heap moved
+    ".global sbrk_start",
+    "sbrk_start:",
+    "    mov eax, 5",
+    "    mov edi, 8192",
+    "    int 0x80",
+    "    mov rbx, rax",
+    "    mov qword ptr [rbx + 8000], 42",
+    "    mov eax, 5",
+    "    xor edi, edi",
+    "    int 0x80",
+    "    mov rdi, [rbx + 8000]",
+    "    add rdi, rax",
+    "    sub rdi, rbx",
+    "    xor eax, eax",
+    "    int 0x80",
+    ".global sbrk_end",
+    "sbrk_end:",
+    "",
+    // open(path, OPEN_WRITE), write "data", close, then open a file that isn't there and exit with the error
+    ".global file_start",
+    "file_start:",
+    "    mov eax, 3",
+    "    lea rdi, [rip + file_path]",
+    "    mov esi, 12",
+    "    mov edx, 1",
+    "    int 0x80",
+    "    mov rbx, rax",
+    "    mov eax, 1",
+    "    mov rdi, rbx",
+    "    lea rsi, [rip + file_data]",
+    "    mov edx, 4",
+    "    int 0x80",
+    "    mov eax, 4",
+    "    mov rdi, rbx",
+    "    int 0x80",
+    "    mov eax, 3",
+    "    lea rdi, [rip + file_missing]",
+    "    mov esi, 7",
+    "    xor edx, edx",
+    "    int 0x80",
+    "    mov rdi, rax",
+    "    xor eax, eax",
+    "    int 0x80",
+    "file_path:",
+    "    .ascii \"$/output.txt\"",
+    "file_missing:",
+    "    .ascii \"nothing\"",
+    "file_data:",
+    "    .ascii \"data\"",
+    ".global file_end",
+    "file_end:",
+    "",
+    // reads the kernel's heap (HEAP_START), which is mapped but not for ring 3
+    ".global peek_start",
+    "peek_start:",
+    "    movabs rax, 0x444444440000",
+    "    mov rax, [rax]",
+    ".global peek_end",
+    "peek_end:",
+    "",
+    // turns the interrupts off, which ring 3 isn't allowed to
+    ".global cli_start",
+    "cli_start:",
+    "    cli",
+    ".global cli_end",
+    "cli_end:",
+    "",
+    // never ends by itself
+    ".global spin_start",
+    "spin_start:",
+    "    jmp spin_start",
+    ".global spin_end",
+    "spin_end:",
+);
+
+extern "C" {
+    static hello_start: u8;
+    static hello_end: u8;
+    static argc_start: u8;
+    static argc_end: u8;
+    static cat_start: u8;
+    static cat_end: u8;
+    static sbrk_start: u8;
+    static sbrk_end: u8;
+    static file_start: u8;
+    static file_end: u8;
+    static peek_start: u8;
+    static peek_end: u8;
+    static cli_start: u8;
+    static cli_end: u8;
+    static spin_start: u8;
+    static spin_end: u8;
+}
+
+fn code(start: *const u8, end: *const u8) -> &'static [u8] {
+    unsafe { slice::from_raw_parts(start, end as usize - start as usize) }
+}
+
+/// An ELF file with one segment at USER_START: the headers, then `code`, which is where it starts.
+fn elf(code: &[u8]) -> Vec<u8> {
+    const HEADERS: usize = 64 + 56;
+    let mut image = vec![0u8; HEADERS];
+    image[..4].copy_from_slice(b"\x7fELF");
+    image[4] = 2; // 64 bit
+    image[5] = 1; // little endian
+    image[6] = 1; // version
+    image[16..18].copy_from_slice(&2u16.to_le_bytes()); // an executable
+    image[18..20].copy_from_slice(&0x3eu16.to_le_bytes()); // x86_64
+    image[24..32].copy_from_slice(&(USER_START + HEADERS as u64).to_le_bytes());
+    image[32..40].copy_from_slice(&64u64.to_le_bytes());
+    image[52..54].copy_from_slice(&64u16.to_le_bytes());
+    image[54..56].copy_from_slice(&56u16.to_le_bytes());
+    image[56..58].copy_from_slice(&1u16.to_le_bytes());
+    let size = (HEADERS + code.len()) as u64;
+    image[64..68].copy_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+    image[68..72].copy_from_slice(&5u32.to_le_bytes()); // read and execute
+    image[80..88].copy_from_slice(&USER_START.to_le_bytes());
+    image[96..104].copy_from_slice(&size.to_le_bytes());
+    image[104..112].copy_from_slice(&size.to_le_bytes());
+    image.extend_from_slice(code);
+    image
+}
+
+fn run(code: &[u8], args: &[&str], input: &str) -> Finished {
+    let image = elf(code);
+    let program = Program {
+        image: &image,
+        args,
+        cwd: "$/",
+        input: input.as_bytes().to_vec(),
+        output: Output::Buffer(String::new()),
+        terminal: Terminal::Serial,
+    };
+    process::spawn(program).expect("the program should load").join()
+}
+
+fn output(finished: &Finished) -> &str {
+    match &finished.output {
+        Output::Buffer(text) => text,
+        Output::Terminal(_) => panic!("the output went to the terminal"),
+    }
+}
+
+#[test_case]
+fn hello() {
+    let finished = run(code(addr_of!(hello_start), addr_of!(hello_end)), &["hello"], "");
+    assert_eq!(finished.status, ExitStatus::Exited(7));
+    assert_eq!(output(&finished), "hello\n");
+}
+
+#[test_case]
+fn arguments() {
+    let program = code(addr_of!(argc_start), addr_of!(argc_end));
+    assert_eq!(run(program, &["argc", "a", "b"], "").status, ExitStatus::Exited(3));
+}
+
+#[test_case]
+fn input_and_output() {
+    let finished = run(code(addr_of!(cat_start), addr_of!(cat_end)), &["cat"], "piped text");
+    assert_eq!(finished.status, ExitStatus::Exited(10));
+    assert_eq!(output(&finished), "piped text");
+}
+
+#[test_case]
+fn heap() {
+    let program = code(addr_of!(sbrk_start), addr_of!(sbrk_end));
+    assert_eq!(run(program, &["sbrk"], "").status, ExitStatus::Exited(42 + 8192));
+}
+
+#[test_case]
+fn files() {
+    let program = code(addr_of!(file_start), addr_of!(file_end));
+    let not_found = process::SyscallError::NotFound.code();
+    assert_eq!(run(program, &["file"], "").status, ExitStatus::Exited(not_found));
+    assert_eq!(VFS.lock().read("$/output.txt").expect("the program wrote it"), b"data");
+}
+
+#[test_case]
+fn kernel_memory_is_off_limits() {
+    let program = code(addr_of!(peek_start), addr_of!(peek_end));
+    let fault = Fault::PageFault(VirtAddr::new(HEAP_START as u64));
+    assert_eq!(run(program, &["peek"], "").status, ExitStatus::Faulted(fault));
+}
+
+#[test_case]
+fn privileged_instructions() {
+    let program = code(addr_of!(cli_start), addr_of!(cli_end));
+    assert_eq!(run(program, &["cli"], "").status, ExitStatus::Faulted(Fault::GeneralProtection));
+}
+
+#[test_case]
+fn many_processes() {
+    // their memory has to come back, or this runs out
+    let program = code(addr_of!(hello_start), addr_of!(hello_end));
+    for _ in 0..50 {
+        assert_eq!(run(program, &["hello"], "").status, ExitStatus::Exited(7));
+    }
+}
+
+#[test_case]
+fn killed() {
+    let image = elf(code(addr_of!(spin_start), addr_of!(spin_end)));
+    let program = Program {
+        image: &image,
+        args: &["spin"],
+        cwd: "$/",
+        input: Vec::new(),
+        output: Output::Buffer(String::new()),
+        terminal: Terminal::Serial,
+    };
+    let handle = process::spawn(program).expect("the program should load");
+    // until it has had the timer interrupt it in its loop, so it gets killed from ring 3
+    while !thread::threads().iter().any(|info| info.id == handle.id() && info.ticks > 0) {
+        thread::yield_now();
+    }
+    // like Ctrl+C does it, until it's gone
+    while !handle.is_finished() {
+        process::kill_started_by(thread::current());
+        thread::yield_now();
+    }
+    assert_eq!(handle.join().status, ExitStatus::Killed);
+}
+
+#[test_case]
+fn not_a_program() {
+    let program = Program {
+        image: b"#!/bin/sh\n",
+        args: &[],
+        cwd: "$/",
+        input: Vec::new(),
+        output: Output::Buffer(String::new()),
+        terminal: Terminal::Serial,
+    };
+    assert_eq!(process::spawn(program).err(), Some(ProcessError::NotElf));
+}
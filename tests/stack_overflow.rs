@@ -0,0 +1,52 @@
+/* Overflows the kernel stack on purpose. The page fault that causes can't be handled on the broken stack, so it
+   becomes a double fault, and that only works because its handler runs on its own stack from the IST (see gdt.rs).
+   Without that the CPU triple faults and qemu resets, and the test times out instead of passing. */
+
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use admiralix_os::{exit_qemu, gdt, serial_print, serial_println, QemuExitCode};
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("stack_overflow::stack_overflow...\t");
+
+    gdt::init();
+    TEST_IDT.load(); // the kernel's double fault handler panics, this one ends the test
+    stack_overflow();
+
+    panic!("Execution continued after stack overflow");
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow(); // every call pushes the return address
+    volatile::Volatile::new(0).read(); // so it isn't turned into a loop
+}
+
+extern "x86-interrupt" fn test_double_fault_handler(_stack_frame: InterruptStackFrame, _error_code: u64) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    admiralix_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    admiralix_os::test_panic_handler(info)
+}
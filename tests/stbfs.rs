@@ -0,0 +1,153 @@
+/* STBFS on its own, a fresh `Filesystem` per test so they don't depend on each other or on the one the kernel
+   mounts at `$/`. The last test saves a tree to a disk in memory and loads it back. */
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(admiralix_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use admiralix_os::{
+    block::{BlockDevice, BlockError, BLOCK_SIZE},
+    stbfs::{
+        disk::{DiskError, DiskFs},
+        Disk, FsError, Filesystem, SeekFrom, MAX_FILE_SIZE,
+    },
+};
+use alloc::{boxed::Box, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    admiralix_os::init();
+    admiralix_os::init_memory(boot_info);
+    test_main();
+    admiralix_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    admiralix_os::test_panic_handler(info)
+}
+
+/// A disk that's just blocks in a Vec.
+struct RamDisk {
+    blocks: Vec<[u8; BLOCK_SIZE]>,
+}
+
+impl BlockDevice for RamDisk {
+    fn block_count(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+
+    fn read_block(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), BlockError> {
+        *buf = *self.blocks.get(block as usize).ok_or(BlockError::OutOfRange)?;
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), BlockError> {
+        *self.blocks.get_mut(block as usize).ok_or(BlockError::OutOfRange)? = *buf;
+        Ok(())
+    }
+}
+
+#[test_case]
+fn create_and_read() {
+    let mut fs = Filesystem::new();
+    let docs = fs.create_dir(Filesystem::ROOT, "docs").unwrap();
+    let notes = fs.create_file(docs, "notes.txt", b"hello").unwrap();
+    assert_eq!(fs.read(notes).unwrap(), b"hello");
+    assert_eq!(fs.lookup(docs, "notes.txt"), Ok(notes));
+    assert_eq!(fs.path_of(notes), "$/docs/notes.txt");
+    assert_eq!(fs.create_file(docs, "notes.txt", b""), Err(FsError::AlreadyExists));
+    assert_eq!(fs.read(docs), Err(FsError::IsADirectory));
+}
+
+#[test_case]
+fn resolve_paths() {
+    let mut fs = Filesystem::new();
+    let a = fs.create_dir(Filesystem::ROOT, "a").unwrap();
+    let b = fs.create_dir(a, "b").unwrap();
+    assert_eq!(fs.resolve(Filesystem::ROOT, "$/a/b"), Ok(b));
+    assert_eq!(fs.resolve(a, "b/.."), Ok(a));
+    assert_eq!(fs.resolve(b, "$/a/./b"), Ok(b));
+    assert_eq!(fs.resolve(b, "../.."), Ok(Filesystem::ROOT));
+    assert_eq!(fs.resolve(Filesystem::ROOT, ".."), Ok(Filesystem::ROOT)); // `cd ..` at the root used to panic
+    assert_eq!(fs.resolve(a, "missing"), Err(FsError::NotFound));
+}
+
+#[test_case]
+fn write_and_truncate() {
+    let mut fs = Filesystem::new();
+    let file = fs.create_file(Filesystem::ROOT, "f", b"abc").unwrap();
+    fs.write(file, b"hello world").unwrap();
+    assert_eq!(fs.write_at(file, 6, b"there"), Ok(5));
+    assert_eq!(fs.read(file).unwrap(), b"hello there");
+    fs.truncate(file, 5).unwrap();
+    let mut buf = [0u8; 16];
+    assert_eq!(fs.read_at(file, 1, &mut buf), Ok(4));
+    assert_eq!(&buf[..4], b"ello");
+}
+
+#[test_case]
+fn file_handles() {
+    let mut fs = Filesystem::new();
+    let file = fs.create_file(Filesystem::ROOT, "f", b"").unwrap();
+    let mut handle = fs.open(file).unwrap();
+    handle.write(&mut fs, b"one two").unwrap();
+    assert_eq!(handle.seek(&fs, SeekFrom::Start(4)), Ok(4));
+    let mut buf = [0u8; 3];
+    assert_eq!(handle.read(&fs, &mut buf), Ok(3));
+    assert_eq!(&buf, b"two");
+    assert_eq!(handle.seek(&fs, SeekFrom::Current(-10)), Err(FsError::InvalidSeek));
+    assert_eq!(fs.open(Filesystem::ROOT).map(|_| ()), Err(FsError::IsADirectory));
+
+    // writing far past the end is too big for a file, it doesn't try to make the buffer that big
+    let too_big = Err(FsError::Disk(DiskError::FileTooBig));
+    handle.seek(&fs, SeekFrom::Start(MAX_FILE_SIZE)).unwrap();
+    assert_eq!(handle.write(&mut fs, b"x"), too_big);
+    handle.seek(&fs, SeekFrom::Start(isize::MAX as usize)).unwrap();
+    assert_eq!(handle.write(&mut fs, b"x"), too_big);
+    assert_eq!(fs.write_at(file, usize::MAX, b"x"), too_big);
+    assert_eq!(fs.truncate(file, usize::MAX), Err(FsError::Disk(DiskError::FileTooBig)));
+    assert_eq!(fs.read(file).unwrap(), b"one two");
+}
+
+#[test_case]
+fn remove_and_rename() {
+    let mut fs = Filesystem::new();
+    let a = fs.create_dir(Filesystem::ROOT, "a").unwrap();
+    let b = fs.create_dir(a, "b").unwrap();
+    let file = fs.create_file(b, "f", b"data").unwrap();
+    assert_eq!(fs.remove(a, "b"), Err(FsError::NotEmpty));
+    assert_eq!(fs.rename(Filesystem::ROOT, "a", b, "a"), Err(FsError::InvalidMove));
+
+    fs.rename(b, "f", Filesystem::ROOT, "g").unwrap();
+    assert_eq!(fs.lookup(Filesystem::ROOT, "g"), Ok(file)); // moving keeps the NodeId
+    assert_eq!(fs.lookup(b, "f"), Err(FsError::NotFound));
+    fs.remove(a, "b").unwrap();
+    assert_eq!(fs.lookup(a, "b"), Err(FsError::NotFound));
+    assert_eq!(fs.create_dir(a, "bad/name"), Err(FsError::InvalidName));
+}
+
+#[test_case]
+fn save_and_load() {
+    let disk = RamDisk { blocks: vec![[0u8; BLOCK_SIZE]; 64] };
+    let disk: Disk = DiskFs::format(Box::new(disk) as Box<dyn BlockDevice + Send>).unwrap();
+    let mut fs = Filesystem::new();
+    fs.attach(disk).unwrap();
+    let docs = fs.create_dir(Filesystem::ROOT, "docs").unwrap();
+    fs.create_file(docs, "notes.txt", b"kept across reboots").unwrap();
+    fs.create_file(Filesystem::ROOT, "gone", b"").unwrap();
+    fs.remove(Filesystem::ROOT, "gone").unwrap();
+
+    let device = fs.detach().unwrap().into_device();
+    let loaded = Filesystem::load(DiskFs::mount(device).unwrap()).unwrap();
+    let notes = loaded.resolve(Filesystem::ROOT, "$/docs/notes.txt").unwrap();
+    assert_eq!(loaded.read(notes).unwrap(), b"kept across reboots");
+    assert_eq!(loaded.lookup(Filesystem::ROOT, "gone"), Err(FsError::NotFound));
+}
@@ -0,0 +1,50 @@
+/* Overflows a thread's stack on purpose. Below every thread stack is a page that isn't mapped, so this has to end
+   in the double fault handler like the kernel stack overflow in stack_overflow.rs does, and not go on writing over
+   another thread's stack. The kernel's double fault handler panics, which is how this one passes. */
+
+#![no_std]
+#![no_main]
+
+use admiralix_os::{exit_qemu, serial_print, serial_println, thread, QemuExitCode};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static OVERFLOWING: AtomicBool = AtomicBool::new(false);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("thread_stack_overflow::thread_stack_overflow...\t");
+
+    admiralix_os::init();
+    admiralix_os::init_memory(boot_info);
+    thread::init();
+    let handle = thread::spawn("overflow", || {
+        OVERFLOWING.store(true, Ordering::SeqCst);
+        stack_overflow();
+    })
+    .expect("there is room");
+    handle.join();
+
+    OVERFLOWING.store(false, Ordering::SeqCst); // so this panic is a failure
+    panic!("Execution continued after stack overflow");
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow(); // every call pushes the return address
+    volatile::Volatile::new(0).read(); // so it isn't turned into a loop
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    if !OVERFLOWING.load(Ordering::SeqCst) {
+        admiralix_os::test_panic_handler(info)
+    }
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    admiralix_os::hlt_loop();
+}
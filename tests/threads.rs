@@ -0,0 +1,115 @@
+/* Kernel threads: spawning and joining, lots of them one after the other so the finished ones get reaped, yielding,
+   that a thread that never yields doesn't keep the others from running, and running out of thread slots. */
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(admiralix_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use admiralix_os::thread::{self, SpawnError, State, MAX_THREADS};
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    admiralix_os::init();
+    admiralix_os::init_memory(boot_info);
+    thread::init();
+    test_main();
+    admiralix_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    admiralix_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn spawn_and_join() {
+    let handle = thread::spawn("answer", || 6 * 7).expect("there is room");
+    assert_eq!(handle.join(), 42);
+}
+
+#[test_case]
+fn many_threads() {
+    // more than fit at once, so this only works if the finished ones make room
+    for i in 0..100u64 {
+        assert_eq!(thread::spawn("many", move || i * 2).unwrap().join(), i * 2);
+    }
+}
+
+#[test_case]
+fn threads_at_once() {
+    let handles: Vec<_> = (0..8u64)
+        .map(|i| thread::spawn("sum", move || (0..=i * 1000).sum::<u64>()).unwrap())
+        .collect();
+    for (i, handle) in handles.into_iter().enumerate() {
+        let n = i as u64 * 1000;
+        assert_eq!(handle.join(), n * (n + 1) / 2);
+    }
+}
+
+#[test_case]
+fn yield_takes_turns() {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let handle = thread::spawn("yielder", || {
+        for _ in 0..10 {
+            COUNTER.fetch_add(1, Ordering::SeqCst);
+            thread::yield_now();
+        }
+    })
+    .unwrap();
+    let own = thread::current();
+    assert_ne!(own, handle.id());
+    handle.join();
+    assert_eq!(COUNTER.load(Ordering::SeqCst), 10);
+    assert!(thread::threads().iter().any(|info| info.id == own && info.state == State::Running));
+}
+
+#[test_case]
+fn spinner_does_not_block() {
+    // this one never yields, only the timer gets the others a turn
+    static STOP: AtomicBool = AtomicBool::new(false);
+    let spinner = thread::spawn("spinner", || {
+        while !STOP.load(Ordering::SeqCst) {
+            core::hint::spin_loop();
+        }
+    })
+    .unwrap();
+    assert_eq!(thread::spawn("other", || 1 + 1).unwrap().join(), 2);
+    STOP.store(true, Ordering::SeqCst);
+    spinner.join();
+}
+
+#[test_case]
+fn every_slot_taken() {
+    static RELEASE: AtomicBool = AtomicBool::new(false);
+    let mut waiting = Vec::new();
+    let error = loop {
+        match thread::spawn("waiting", || {
+            while !RELEASE.load(Ordering::SeqCst) {
+                thread::yield_now();
+            }
+        }) {
+            Ok(handle) => waiting.push(handle),
+            Err(error) => break error,
+        }
+        assert!(waiting.len() < MAX_THREADS, "more threads than there are slots");
+    };
+    assert_eq!(error, SpawnError::TooManyThreads);
+    assert!(!waiting.is_empty());
+    RELEASE.store(true, Ordering::SeqCst);
+    for handle in waiting {
+        handle.join();
+    }
+    // and the slots come back
+    assert_eq!(thread::spawn("after", || 3).unwrap().join(), 3);
+}
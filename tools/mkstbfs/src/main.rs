@@ -0,0 +1,338 @@
+/* mkstbfs, builds and inspects STBFS disk images on the host so you don't have to type every file in with /tch.
+   The image it makes is what you hand to qemu as the second drive (-drive format=raw,file=stbfs.img,index=1). */
+
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+    process,
+    time::UNIX_EPOCH,
+};
+use stbfs_core::{
+    block::{BlockDevice, BlockError, BLOCK_SIZE},
+    disk::{DiskError, DiskFs, InodeKind, MAX_FILE_BLOCKS},
+};
+
+const USAGE: &str = "Usage:
+  mkstbfs format <image> [size]            make a new image, size like 8M or 512K (default 8M)
+  mkstbfs put <image> <host path> [path]   copy a file or folder into the image
+  mkstbfs get <image> <path> <host path>   copy a file out of the image
+  mkstbfs ls <image> [path] [-r]           list a directory, -r goes into subdirectories
+  mkstbfs fsck <image> [--repair]          check the image for errors";
+
+const DEFAULT_SIZE: u64 = 8 * 1024 * 1024;
+
+/// A disk image file on the host.
+struct FileDevice {
+    file: File,
+    blocks: u64,
+}
+
+impl FileDevice {
+    fn open(path: &str) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|error| format!("can't open '{}': {}", path, error))?;
+        let len = file.metadata().map_err(|error| error.to_string())?.len();
+        Ok(FileDevice {
+            file,
+            blocks: len / BLOCK_SIZE as u64,
+        })
+    }
+
+    fn create(path: &str, size: u64) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|error| format!("can't create '{}': {}", path, error))?;
+        file.set_len(size).map_err(|error| error.to_string())?;
+        Ok(FileDevice {
+            file,
+            blocks: size / BLOCK_SIZE as u64,
+        })
+    }
+
+    fn seek_to(&mut self, block: u64) -> Result<(), BlockError> {
+        if block >= self.blocks {
+            return Err(BlockError::OutOfRange);
+        }
+        self.file
+            .seek(SeekFrom::Start(block * BLOCK_SIZE as u64))
+            .map(|_| ())
+            .map_err(|_| BlockError::DeviceError)
+    }
+}
+
+impl BlockDevice for FileDevice {
+    fn block_count(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_block(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), BlockError> {
+        self.seek_to(block)?;
+        self.file.read_exact(buf).map_err(|_| BlockError::DeviceError)
+    }
+
+    fn write_block(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), BlockError> {
+        self.seek_to(block)?;
+        self.file.write_all(buf).map_err(|_| BlockError::DeviceError)
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        self.file.sync_data().map_err(|_| BlockError::DeviceError)
+    }
+}
+
+type Image = DiskFs<FileDevice>;
+
+fn mount(path: &str) -> Result<Image, String> {
+    DiskFs::mount(FileDevice::open(path)?).map_err(|error| format!("can't mount '{}': {}", path, error))
+}
+
+/// "8M", "512K", "1G" or a plain number of bytes.
+fn parse_size(text: &str) -> Option<u64> {
+    let (number, unit) = match text.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => text.split_at(i),
+        None => (text, ""),
+    };
+    let multiplier = match unit.to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.parse::<u64>().ok().map(|n| n * multiplier)
+}
+
+fn format(args: &[String]) -> Result<(), String> {
+    let (image, size) = match args {
+        [image] => (image, DEFAULT_SIZE),
+        [image, size] => (image, parse_size(size).ok_or(format!("bad size '{}'", size))?),
+        _ => return Err(String::from(USAGE)),
+    };
+    let disk = DiskFs::format(FileDevice::create(image, size)?).map_err(|error| format!("can't format: {}", error))?;
+    let superblock = disk.superblock();
+    println!(
+        "Formatted '{}': {} blocks, {} inodes, data starts at block {}.",
+        image, superblock.block_count, superblock.inode_count, superblock.data_start
+    );
+    Ok(())
+}
+
+/// When a host file was last changed, in unix seconds (0 if the host can't tell).
+fn modified_time(host: &Path) -> u64 {
+    fs::metadata(host)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// Copies a host file or a whole host folder into directory `dir` of the image, under `name`. Whatever can't be
+/// copied is reported and skipped, the rest of a folder still goes in. Returns how many files or folders failed.
+fn put_path(disk: &mut Image, host: &Path, dir: u32, name: &str) -> usize {
+    match put_one(disk, host, dir, name) {
+        Ok(failed) => failed,
+        Err(message) => {
+            eprintln!("{}", message);
+            1
+        }
+    }
+}
+
+fn put_one(disk: &mut Image, host: &Path, dir: u32, name: &str) -> Result<usize, String> {
+    let kind = if host.is_dir() { InodeKind::Directory } else { InodeKind::File };
+    let time = modified_time(host);
+    // read and size up a file before it gets an entry, so a file that can't go in doesn't leave an empty one
+    let data = match kind {
+        InodeKind::File => {
+            let data = fs::read(host).map_err(|error| format!("can't read '{}': {}", host.display(), error))?;
+            if data.len() > MAX_FILE_BLOCKS * BLOCK_SIZE {
+                return Err(format!("can't write '{}': {}", host.display(), DiskError::FileTooBig));
+            }
+            data
+        }
+        _ => Vec::new(),
+    };
+    let (inode, created) = match disk.create(dir, name, kind, time) {
+        Err(DiskError::AlreadyExists) if kind == InodeKind::Directory => {
+            disk.lookup(dir, name).map(|inode| (inode, false))
+        }
+        Err(DiskError::AlreadyExists) => disk.lookup(dir, name).and_then(|inode| match disk.read_inode(inode)?.kind {
+            InodeKind::File => Ok((inode, false)), // overwrite it
+            _ => Err(DiskError::AlreadyExists),
+        }),
+        result => result.map(|inode| (inode, true)),
+    }
+    .map_err(|error| format!("can't create '{}': {}", name, error))?;
+
+    if kind == InodeKind::Directory {
+        let mut entries: Vec<_> = fs::read_dir(host)
+            .map_err(|error| format!("can't read '{}': {}", host.display(), error))?
+            .filter_map(|entry| entry.ok())
+            .collect();
+        entries.sort_by_key(|entry| entry.file_name());
+        let mut failed = 0;
+        for entry in entries {
+            let child = entry.file_name().to_string_lossy().into_owned();
+            failed += put_path(disk, &entry.path(), inode, &child);
+        }
+        return Ok(failed);
+    }
+
+    let written = disk.write_file(inode, &data).and_then(|()| {
+        let mut file = disk.read_inode(inode)?;
+        file.modified = time; // keep the host's time, even when overwriting
+        disk.write_inode(inode, &file)
+    });
+    if let Err(error) = written {
+        // a file that was already there keeps its old content, a new one goes again
+        if created {
+            let _ = disk.remove_file(dir, name);
+        }
+        return Err(format!("can't write '{}': {}", host.display(), error));
+    }
+    println!("{} -> {} ({} bytes)", host.display(), name, data.len());
+    Ok(0)
+}
+
+fn put(args: &[String]) -> Result<(), String> {
+    let (image, host, target) = match args {
+        [image, host] => (image, host, "$/"),
+        [image, host, target] => (image, host, target.as_str()),
+        _ => return Err(String::from(USAGE)),
+    };
+    let host = Path::new(host);
+    let mut disk = mount(image)?;
+
+    // putting into an existing directory keeps the host name, otherwise the last part of the path is the new name
+    let (dir, name) = match disk.resolve(target) {
+        Ok(inode) if disk.read_inode(inode).map(|inode| inode.kind) == Ok(InodeKind::Directory) => {
+            let name = host
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .ok_or(format!("'{}' has no name to use", host.display()))?;
+            (inode, name)
+        }
+        _ => {
+            let trimmed = target.trim_end_matches('/');
+            let (parent, name) = match trimmed.rfind('/') {
+                Some(slash) => (&trimmed[..slash], &trimmed[slash + 1..]),
+                None => ("$", trimmed),
+            };
+            let dir = disk
+                .resolve(parent)
+                .map_err(|error| format!("can't find '{}': {}", parent, error))?;
+            (dir, String::from(name))
+        }
+    };
+    let failed = put_path(&mut disk, host, dir, &name);
+    disk.flush().map_err(|error| error.to_string())?;
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} file(s) or folder(s) couldn't be copied", failed)),
+    }
+}
+
+fn get(args: &[String]) -> Result<(), String> {
+    let (image, path, host) = match args {
+        [image, path, host] => (image, path, host),
+        _ => return Err(String::from(USAGE)),
+    };
+    let mut disk = mount(image)?;
+    let data = disk
+        .resolve(path)
+        .and_then(|inode| disk.read_file(inode))
+        .map_err(|error| format!("can't read '{}': {}", path, error))?;
+    fs::write(host, &data).map_err(|error| format!("can't write '{}': {}", host, error))?;
+    println!("{} -> {} ({} bytes)", path, host, data.len());
+    Ok(())
+}
+
+fn list_dir(disk: &mut Image, dir: u32, path: &str, recursive: bool) -> Result<(), DiskError> {
+    println!("{}:", path);
+    let mut entries = disk.list(dir)?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut subdirs = Vec::new();
+    for entry in entries {
+        let inode = disk.read_inode(entry.inode)?;
+        match inode.kind {
+            InodeKind::Directory => {
+                println!("  {:<8} {}/", "<DIR>", entry.name);
+                subdirs.push(entry);
+            }
+            _ => println!("  {:<8} {}", inode.size, entry.name),
+        }
+    }
+    if recursive {
+        for entry in subdirs {
+            let child = format!("{}/{}", path.trim_end_matches('/'), entry.name);
+            list_dir(disk, entry.inode, &child, recursive)?;
+        }
+    }
+    Ok(())
+}
+
+fn ls(args: &[String]) -> Result<(), String> {
+    let recursive = args.iter().any(|arg| arg == "-r");
+    let rest: Vec<&String> = args.iter().filter(|arg| *arg != "-r").collect();
+    let (image, path) = match rest.as_slice() {
+        [image] => (*image, "$/"),
+        [image, path] => (*image, path.as_str()),
+        _ => return Err(String::from(USAGE)),
+    };
+    let mut disk = mount(image)?;
+    let dir = disk
+        .resolve(path)
+        .map_err(|error| format!("can't find '{}': {}", path, error))?;
+    list_dir(&mut disk, dir, path, recursive).map_err(|error| format!("can't list '{}': {}", path, error))
+}
+
+fn fsck(args: &[String]) -> Result<(), String> {
+    let (image, repair) = match args {
+        [image] => (image, false),
+        [image, flag] if flag == "--repair" => (image, true),
+        _ => return Err(String::from(USAGE)),
+    };
+    let mut disk = mount(image)?;
+    let problems = disk.check(repair).map_err(|error| format!("can't check '{}': {}", image, error))?;
+    if problems.is_empty() {
+        println!("'{}' is clean.", image);
+        return Ok(());
+    }
+    for problem in &problems {
+        let note = if repair && problem.is_repairable() { " (fixed)" } else { "" };
+        println!("{}{}", problem, note);
+    }
+    let left = problems.iter().filter(|problem| !repair || !problem.is_repairable()).count();
+    if left > 0 {
+        return Err(format!("{} problem(s) left in '{}'", left, image));
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.split_first() {
+        Some((command, rest)) => match command.as_str() {
+            "format" => format(rest),
+            "put" => put(rest),
+            "get" => get(rest),
+            "ls" => ls(rest),
+            "fsck" => fsck(rest),
+            _ => Err(String::from(USAGE)),
+        },
+        None => Err(String::from(USAGE)),
+    };
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
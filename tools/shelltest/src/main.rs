@@ -0,0 +1,287 @@
+/* shelltest, boots the kernel in qemu and drives the serial shell the way someone at `qemu -serial stdio` would:
+   it types the commands from a test case and checks the lines that come back. Every case file gets its own boot,
+   so cases can't step on each other. At the end it tells the kernel to exit qemu with /qemu-exit, which goes
+   through the isa-debug-exit device, and qemu's exit code says whether the kernel made it that far.
+
+   A case file looks like this:
+
+       # comments start with #
+       > /mkdir docs
+       Directory 'docs' created.
+       > /lf
+       Directory: docs
+       !Can't
+
+   `> ` lines are typed into the shell. The lines after one must show up in that command's output, in that order,
+   and `!text` means no line of it may contain `text`. A kernel panic always fails the case. */
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{self, Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+const USAGE: &str = "Usage:
+  shelltest <bootimage> [case or folder...]   run the test cases (default: the cases folder of this tool)
+
+Set QEMU to use another qemu binary than qemu-system-x86_64.";
+
+const BOOT_TIMEOUT: Duration = Duration::from_secs(60); // there's a 5 second delay in kernel_main alone
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+const EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What we set PROMPT to, so we know when a command is done. The line before it ends in \r\n.
+const PROMPT: &str = "#shelltest# ";
+
+/// Exit codes of qemu when the kernel writes a `QemuExitCode` to isa-debug-exit, it makes them (code << 1) | 1.
+const QEMU_SUCCESS: i32 = (0x10 << 1) | 1;
+const QEMU_FAILED: i32 = (0x11 << 1) | 1;
+
+/// One command of a case and what to check in its output.
+struct Step {
+    line: usize,
+    command: String,
+    expect: Vec<String>,
+    reject: Vec<String>,
+}
+
+fn parse_case(text: &str) -> Result<Vec<Step>, String> {
+    let mut steps: Vec<Step> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(command) = line.strip_prefix("> ") {
+            steps.push(Step {
+                line: index + 1,
+                command: String::from(command),
+                expect: Vec::new(),
+                reject: Vec::new(),
+            });
+            continue;
+        }
+        let step = steps
+            .last_mut()
+            .ok_or_else(|| format!("line {}: expected output before any '> ' command", index + 1))?;
+        match line.strip_prefix('!') {
+            Some(rejected) => step.reject.push(String::from(rejected)),
+            None => step.expect.push(String::from(line)),
+        }
+    }
+    Ok(steps)
+}
+
+/// The kernel running in qemu, with its serial port on our end of a pipe.
+struct Qemu {
+    child: Child,
+    stdin: ChildStdin,
+    output: Receiver<Vec<u8>>,
+    pending: String, // received but not handed out yet
+}
+
+impl Qemu {
+    fn boot(image: &Path) -> Result<Self, String> {
+        let binary = env::var("QEMU").unwrap_or_else(|_| String::from("qemu-system-x86_64"));
+        let mut child = Command::new(&binary)
+            .arg("-drive")
+            .arg(format!("format=raw,file={}", image.display()))
+            .args(["-serial", "stdio", "-display", "none", "-monitor", "none", "-no-reboot"])
+            .args(["-device", "isa-debug-exit,iobase=0xf4,iosize=0x04"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|error| format!("can't start '{}': {}", binary, error))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+
+        // reading blocks, so it gets a thread and we wait on the channel with a timeout
+        let (sender, output) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0u8; 1024];
+            while let Ok(read) = stdout.read(&mut buf) {
+                if read == 0 || sender.send(buf[..read].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Qemu {
+            child,
+            stdin,
+            output,
+            pending: String::new(),
+        })
+    }
+
+    /// Everything that comes in up to and including `marker`.
+    fn wait_for(&mut self, marker: &str, timeout: Duration) -> Result<String, String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(index) = self.pending.find(marker) {
+                let rest = self.pending.split_off(index + marker.len());
+                return Ok(std::mem::replace(&mut self.pending, rest));
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.output.recv_timeout(left) {
+                Ok(bytes) => self.pending.push_str(&String::from_utf8_lossy(&bytes)),
+                Err(RecvTimeoutError::Timeout) => {
+                    let what = if self.pending.contains("panicked at") { "the kernel panicked" } else { "timed out" };
+                    return Err(format!("{}, the last output was:\n{}", what, self.pending));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(format!("qemu is gone, the last output was:\n{}", self.pending));
+                }
+            }
+        }
+    }
+
+    fn type_line(&mut self, line: &str) -> Result<(), String> {
+        write!(self.stdin, "{}\r", line)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|error| format!("can't type into qemu: {}", error))
+    }
+
+    /// Waits for the first prompt and swaps it for ours.
+    fn start_shell(&mut self) -> Result<(), String> {
+        self.wait_for("> ", BOOT_TIMEOUT).map_err(|error| format!("no prompt on the serial port: {}", error))?;
+        self.run(&format!("/set PROMPT=\"{}\"", PROMPT)).map(|_| ())
+    }
+
+    /// Types a command and returns the lines it printed.
+    fn run(&mut self, command: &str) -> Result<Vec<String>, String> {
+        self.type_line(command)?;
+        let output = self.wait_for(&format!("\n{}", PROMPT), COMMAND_TIMEOUT)?;
+        Ok(output
+            .lines()
+            .skip(1) // the shell echoing what we typed
+            .map(|line| String::from(line.trim_end()))
+            .filter(|line| !line.ends_with(PROMPT.trim_end()))
+            .collect())
+    }
+
+    /// Has the kernel end qemu through isa-debug-exit and returns qemu's exit code.
+    fn exit(mut self, passed: bool) -> Result<i32, String> {
+        let result = self.type_line(if passed { "/qemu-exit success" } else { "/qemu-exit failed" });
+        let deadline = Instant::now() + EXIT_TIMEOUT;
+        while result.is_ok() && Instant::now() < deadline {
+            if let Some(status) = self.child.try_wait().map_err(|error| error.to_string())? {
+                return status.code().ok_or_else(|| String::from("qemu was killed"));
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let _ = self.child.kill();
+        Err(String::from("the kernel didn't exit qemu"))
+    }
+}
+
+impl Drop for Qemu {
+    fn drop(&mut self) {
+        let _ = self.child.kill(); // a case that failed halfway leaves it running
+        let _ = self.child.wait();
+    }
+}
+
+fn check(step: &Step, output: &[String]) -> Result<(), String> {
+    if let Some(panic) = output.iter().find(|line| line.contains("panicked at")) {
+        return Err(format!("the kernel panicked: {}", panic));
+    }
+    let mut lines = output.iter();
+    for expected in &step.expect {
+        if !lines.any(|line| line == expected) {
+            return Err(format!("expected '{}'", expected));
+        }
+    }
+    for rejected in &step.reject {
+        if let Some(line) = output.iter().find(|line| line.contains(rejected.as_str())) {
+            return Err(format!("didn't expect '{}'", line));
+        }
+    }
+    Ok(())
+}
+
+fn run_case(image: &Path, case: &Path) -> Result<(), String> {
+    let text = fs::read_to_string(case).map_err(|error| format!("can't read it: {}", error))?;
+    let steps = parse_case(&text)?;
+    let mut qemu = Qemu::boot(image)?;
+    qemu.start_shell()?;
+
+    let mut failure = None;
+    for step in &steps {
+        let result = qemu
+            .run(&step.command)
+            .and_then(|output| check(step, &output).map_err(|error| format!("{}, got:\n{}", error, output.join("\n"))));
+        if let Err(error) = result {
+            failure = Some(format!("line {} `{}`: {}", step.line, step.command, error));
+            break;
+        }
+    }
+    let code = qemu.exit(failure.is_none());
+    match (failure, code) {
+        (Some(failure), _) => Err(failure),
+        (None, Ok(QEMU_SUCCESS)) => Ok(()),
+        (None, Ok(QEMU_FAILED)) => Err(String::from("the kernel exited with QemuExitCode::Failed")),
+        (None, Ok(code)) => Err(format!("qemu exited with {}", code)),
+        (None, Err(error)) => Err(error),
+    }
+}
+
+/// The case files to run, folders are searched for `.txt` files.
+fn find_cases(paths: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut cases = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            cases.push(path.clone());
+            continue;
+        }
+        let entries = fs::read_dir(path).map_err(|error| format!("can't list '{}': {}", path.display(), error))?;
+        let mut found: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().map(|extension| extension == "txt").unwrap_or(false))
+            .collect();
+        found.sort();
+        cases.extend(found);
+    }
+    Ok(cases)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (image, paths) = match args.split_first() {
+        Some((image, paths)) if image != "-h" && image != "--help" => (PathBuf::from(image), paths),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+    let paths: Vec<PathBuf> = if paths.is_empty() {
+        vec![Path::new(env!("CARGO_MANIFEST_DIR")).join("cases")]
+    } else {
+        paths.iter().map(PathBuf::from).collect()
+    };
+    let cases = match find_cases(&paths) {
+        Ok(cases) => cases,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+    };
+
+    let mut failed = 0;
+    for case in &cases {
+        match run_case(&image, case) {
+            Ok(()) => println!("{} ... ok", case.display()),
+            Err(message) => {
+                println!("{} ... FAILED\n  {}", case.display(), message.replace('\n', "\n  "));
+                failed += 1;
+            }
+        }
+    }
+    println!("{} passed, {} failed", cases.len() - failed, failed);
+    if failed > 0 {
+        process::exit(1);
+    }
+}